@@ -0,0 +1,154 @@
+#ifndef ALLOYSTACK_H
+#define ALLOYSTACK_H
+
+/* Generated by `just c_header`, do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * `open` flag, append to the end of the file.
+ */
+#define AS_O_APPEND 1
+
+/**
+ * `open` flag, create the file if it does not exist.
+ */
+#define AS_O_CREAT 2
+
+/**
+ * `open` mode, read only.
+ */
+#define AS_MODE_RD 1
+
+/**
+ * `open` mode, write only.
+ */
+#define AS_MODE_WR 2
+
+/**
+ * `open` mode, read and write.
+ */
+#define AS_MODE_RDWR 3
+
+/**
+ * `lseek` whence, from the start of the file.
+ */
+#define AS_SEEK_SET 0
+
+/**
+ * `lseek` whence, from the current offset.
+ */
+#define AS_SEEK_CUR 1
+
+/**
+ * `lseek` whence, from the end of the file.
+ */
+#define AS_SEEK_END 2
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Copy the value of argument `key` and a terminating nul into `buf` of
+ * `len` bytes. Returns the length of the value, which was truncated if it
+ * is not less than `len`, or `-ENOENT` if there is no such argument.
+ *
+ * # Safety
+ * `key` is a nul-terminated string and `buf` has `len` writable bytes.
+ */
+ptrdiff_t as_arg(const char *key, char *buf, size_t len);
+
+/**
+ * Write `len` bytes of `buf` to the standard output of the function.
+ * Returns the number of bytes written.
+ *
+ * # Safety
+ * `buf` has `len` readable bytes.
+ */
+ptrdiff_t as_print(const char *buf, size_t len);
+
+/**
+ * Open the file at `path` with `AS_O_*` flags and an `AS_MODE_*` mode.
+ * Returns the file descriptor or a negative errno.
+ *
+ * # Safety
+ * `path` is a nul-terminated string.
+ */
+int as_open(const char *path, uint32_t flags, uint32_t mode);
+
+/**
+ * Read up to `len` bytes into `buf`. Returns the number of bytes read, 0
+ * at the end of the file, or a negative errno.
+ *
+ * # Safety
+ * `buf` has `len` writable bytes.
+ */
+ptrdiff_t as_read(int fd, void *buf, size_t len);
+
+/**
+ * Write `len` bytes of `buf`. Returns the number of bytes written or a
+ * negative errno.
+ *
+ * # Safety
+ * `buf` has `len` readable bytes.
+ */
+ptrdiff_t as_write(int fd, const void *buf, size_t len);
+
+/**
+ * Move the offset of `fd` to `offset` from `whence`, one of the
+ * `AS_SEEK_*` values. Returns the new offset from the start or a negative
+ * errno.
+ */
+int64_t as_lseek(int fd, int64_t offset, int whence);
+
+/**
+ * Close `fd`. Returns 0 or a negative errno.
+ */
+int as_close(int fd);
+
+/**
+ * Allocate a buffer of `size` bytes to pass to the next function through
+ * `slot`. Returns the data of the buffer, or NULL if it can not be
+ * allocated.
+ *
+ * # Safety
+ * `slot` is a nul-terminated string.
+ */
+void *as_buffer_alloc(const char *slot, size_t size);
+
+/**
+ * Take the buffer a previous function passed through `slot`, and store its
+ * size in `size`. Returns the data of the buffer, or NULL if there is none
+ * or it was not allocated by a C function, which leaves it in the slot.
+ *
+ * # Safety
+ * `slot` is a nul-terminated string and `size` is writable.
+ */
+void *as_buffer_access(const char *slot, size_t *size);
+
+/**
+ * Free a buffer returned by `as_buffer_access`.
+ *
+ * # Safety
+ * `buf` is returned by `as_buffer_access` and not used afterwards.
+ */
+void as_buffer_free(void *buf);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+/**
+ * Entry of the C function, defined by the function. Returns 0 on success.
+ */
+#ifdef __cplusplus
+extern "C"
+#endif  // __cplusplus
+int as_main(void);
+
+#endif  /* ALLOYSTACK_H */
@@ -0,0 +1,253 @@
+//! Runtime of native C functions. It is linked into the shared library of a
+//! C function together with `as_std`, which gives the library `rust_main`
+//! and the other symbols `ServiceLoader` looks up in an app, and it exposes
+//! hostcalls with a C ABI: integers, pointers with lengths, and negative
+//! errno values for errors.
+//!
+//! The C declarations are in `include/alloystack.h`, generated from this
+//! file by `just c_header`.
+
+#![no_std]
+
+extern crate alloc;
+
+use core::{
+    alloc::Layout,
+    ffi::{c_char, c_int, c_void, CStr},
+    ptr, slice,
+};
+
+use alloc::{format, vec::Vec};
+use as_hostcall::{
+    err::{AsErrno, Errno},
+    fdtab::FdtabError,
+    manifest::signature_hash,
+    types::{OpenFlags, OpenMode, TraceEvent, Whence},
+};
+use as_std::{
+    agent::FaaSFuncResult,
+    args,
+    libos::{self, libos},
+};
+use spin::Mutex;
+
+/// `open` flag, append to the end of the file.
+pub const AS_O_APPEND: u32 = 1;
+/// `open` flag, create the file if it does not exist.
+pub const AS_O_CREAT: u32 = 2;
+/// `open` mode, read only.
+pub const AS_MODE_RD: u32 = 1;
+/// `open` mode, write only.
+pub const AS_MODE_WR: u32 = 2;
+/// `open` mode, read and write.
+pub const AS_MODE_RDWR: u32 = 3;
+/// `lseek` whence, from the start of the file.
+pub const AS_SEEK_SET: c_int = 0;
+/// `lseek` whence, from the current offset.
+pub const AS_SEEK_CUR: c_int = 1;
+/// `lseek` whence, from the end of the file.
+pub const AS_SEEK_END: c_int = 2;
+
+const _: () = assert!(AS_O_APPEND == OpenFlags::O_APPEND.bits());
+const _: () = assert!(AS_O_CREAT == OpenFlags::O_CREAT.bits());
+const _: () = assert!(AS_MODE_RD == OpenMode::RD.bits());
+const _: () = assert!(AS_MODE_WR == OpenMode::WR.bits());
+const _: () = assert!(AS_MODE_RDWR == OpenMode::RDWR.bits());
+const _: () = assert!(AS_SEEK_SET == Whence::Set as c_int);
+const _: () = assert!(AS_SEEK_CUR == Whence::Cur as c_int);
+const _: () = assert!(AS_SEEK_END == Whence::End as c_int);
+
+// Linux errno values, which C functions compare with `<errno.h>`.
+const ENOENT: isize = Errno::ENOENT as isize;
+const EINVAL: isize = Errno::EINVAL as isize;
+
+fn errno_ret(res: Result<isize, FdtabError>) -> isize {
+    res.unwrap_or_else(|e| -(e.errno().code() as isize))
+}
+
+extern "C" {
+    /// Entry of the C function, which returns 0 on success.
+    fn as_main() -> c_int;
+}
+
+/// Arguments of the function, taken at the entry as `args::get` only finds
+/// them near the top of the stack.
+static ARGS: Mutex<Vec<(&str, &str)>> = Mutex::new(Vec::new());
+
+#[no_mangle]
+pub fn main() -> FaaSFuncResult<()> {
+    *ARGS.lock() = args::all().collect();
+    match unsafe { as_main() } {
+        0 => Ok(().into()),
+        code => Err(format!("as_main returned {}", code).into()),
+    }
+}
+
+unsafe fn c_str<'a>(s: *const c_char) -> Option<&'a str> {
+    if s.is_null() {
+        return None;
+    }
+    CStr::from_ptr(s).to_str().ok()
+}
+
+/// Copy the value of argument `key` and a terminating nul into `buf` of
+/// `len` bytes. Returns the length of the value, which was truncated if it
+/// is not less than `len`, or `-ENOENT` if there is no such argument.
+///
+/// # Safety
+/// `key` is a nul-terminated string and `buf` has `len` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn as_arg(key: *const c_char, buf: *mut c_char, len: usize) -> isize {
+    let Some(key) = c_str(key) else {
+        return -EINVAL;
+    };
+    let args = ARGS.lock();
+    let Some((_, val)) = args.iter().find(|(k, _)| *k == key) else {
+        return -ENOENT;
+    };
+    if len > 0 {
+        let copied = val.len().min(len - 1);
+        ptr::copy_nonoverlapping(val.as_ptr(), buf as *mut u8, copied);
+        *buf.add(copied) = 0;
+    }
+
+    val.len() as isize
+}
+
+/// Write `len` bytes of `buf` to the standard output of the function.
+/// Returns the number of bytes written.
+///
+/// # Safety
+/// `buf` has `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn as_print(buf: *const c_char, len: usize) -> isize {
+    let buf = slice::from_raw_parts(buf as *const u8, len);
+    libos!(stdout(buf)) as isize
+}
+
+/// Open the file at `path` with `AS_O_*` flags and an `AS_MODE_*` mode.
+/// Returns the file descriptor or a negative errno.
+///
+/// # Safety
+/// `path` is a nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn as_open(path: *const c_char, flags: u32, mode: u32) -> c_int {
+    let Some(path) = c_str(path) else {
+        return -EINVAL as c_int;
+    };
+    let flags = OpenFlags::from_bits_truncate(flags);
+    let mode = OpenMode::from_bits_truncate(mode);
+    errno_ret(libos!(open(path, flags, mode)).map(|fd| fd as isize)) as c_int
+}
+
+/// Read up to `len` bytes into `buf`. Returns the number of bytes read, 0
+/// at the end of the file, or a negative errno.
+///
+/// # Safety
+/// `buf` has `len` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn as_read(fd: c_int, buf: *mut c_void, len: usize) -> isize {
+    let buf = slice::from_raw_parts_mut(buf as *mut u8, len);
+    errno_ret(libos!(read(fd as u32, buf)).map(|size| size as isize))
+}
+
+/// Write `len` bytes of `buf`. Returns the number of bytes written or a
+/// negative errno.
+///
+/// # Safety
+/// `buf` has `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn as_write(fd: c_int, buf: *const c_void, len: usize) -> isize {
+    let buf = slice::from_raw_parts(buf as *const u8, len);
+    errno_ret(libos!(write(fd as u32, buf)).map(|size| size as isize))
+}
+
+/// Move the offset of `fd` to `offset` from `whence`, one of the
+/// `AS_SEEK_*` values. Returns the new offset from the start or a negative
+/// errno.
+#[no_mangle]
+pub extern "C" fn as_lseek(fd: c_int, offset: i64, whence: c_int) -> i64 {
+    let Some(whence) = Whence::from_raw(whence as u32) else {
+        return -EINVAL as i64;
+    };
+    match libos!(lseek(fd as u32, offset, whence)) {
+        // Offsets past `i64::MAX` would read as an errno.
+        Ok(pos) => i64::try_from(pos).unwrap_or(-(Errno::EOVERFLOW as i64)),
+        Err(e) => -(e.errno().code() as i64),
+    }
+}
+
+/// Close `fd`. Returns 0 or a negative errno.
+#[no_mangle]
+pub extern "C" fn as_close(fd: c_int) -> c_int {
+    errno_ret(libos!(close(fd as u32)).map(|_| 0)) as c_int
+}
+
+/// Bytes before the data of a buffer, which keep its size as slots only
+/// keep the address.
+const BUFFER_HEADER: usize = 16;
+
+/// Fingerprint of the buffers of C functions, which hold bytes.
+fn bytes_fingerprint() -> u64 {
+    signature_hash::<[u8]>()
+}
+
+fn buffer_layout(size: usize) -> Option<Layout> {
+    Layout::from_size_align(size.checked_add(BUFFER_HEADER)?, BUFFER_HEADER).ok()
+}
+
+/// Allocate a buffer of `size` bytes to pass to the next function through
+/// `slot`. Returns the data of the buffer, or NULL if it can not be
+/// allocated.
+///
+/// # Safety
+/// `slot` is a nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn as_buffer_alloc(slot: *const c_char, size: usize) -> *mut c_void {
+    let (Some(slot), Some(layout)) = (c_str(slot), buffer_layout(size)) else {
+        return ptr::null_mut();
+    };
+    let Ok(addr) = libos!(buffer_alloc(slot, layout, bytes_fingerprint())) else {
+        return ptr::null_mut();
+    };
+    libos::trace(TraceEvent::BufferProduced(slot));
+
+    *(addr as *mut usize) = size;
+    (addr + BUFFER_HEADER) as *mut c_void
+}
+
+/// Take the buffer a previous function passed through `slot`, and store its
+/// size in `size`. Returns the data of the buffer, or NULL if there is none
+/// or it was not allocated by a C function, which leaves it in the slot.
+///
+/// # Safety
+/// `slot` is a nul-terminated string and `size` is writable.
+#[no_mangle]
+pub unsafe extern "C" fn as_buffer_access(slot: *const c_char, size: *mut usize) -> *mut c_void {
+    let Some(slot) = c_str(slot) else {
+        return ptr::null_mut();
+    };
+    let Some((addr, fingerprint)) = libos!(access_buffer(slot, bytes_fingerprint())) else {
+        return ptr::null_mut();
+    };
+    if fingerprint != bytes_fingerprint() {
+        return ptr::null_mut();
+    }
+    libos::trace(TraceEvent::BufferConsumed(slot));
+
+    *size = *(addr as *const usize);
+    (addr + BUFFER_HEADER) as *mut c_void
+}
+
+/// Free a buffer returned by `as_buffer_access`.
+///
+/// # Safety
+/// `buf` is returned by `as_buffer_access` and not used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn as_buffer_free(buf: *mut c_void) {
+    let addr = buf as usize - BUFFER_HEADER;
+    let size = *(addr as *const usize);
+    if let Some(layout) = buffer_layout(size) {
+        libos!(buffer_dealloc(addr, layout));
+    }
+}
@@ -1,19 +1,178 @@
+//! Error model shared by all hostcalls. The error types of each hostcall
+//! family implement `AsErrno`, which tells the POSIX error code and the
+//! service of an error, so WASI shims, C functions and functions can branch
+//! on the kind of an error instead of its message. `LibOSError` erases the
+//! type of such an error and keeps a chain of context.
+
+use alloc::{
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::fmt;
+
 use derive_more::Display;
 
-#[derive(Debug, Display)]
-pub enum LibOSErr {
-    Unknown,
-    BadArgs,
+macro_rules! errno {
+    ($($name:ident = $code:literal,)*) => {
+        /// POSIX error codes, numbered as on Linux.
+        #[derive(Debug, Display, Clone, Copy, PartialEq, Eq, Hash)]
+        #[repr(i32)]
+        pub enum Errno {
+            $($name = $code,)*
+        }
+
+        impl Errno {
+            pub fn from_code(code: i32) -> Option<Self> {
+                match code {
+                    $($code => Some(Self::$name),)*
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+errno! {
+    EPERM = 1,
+    ENOENT = 2,
+    EINTR = 4,
+    EIO = 5,
+    EBADF = 9,
+    EAGAIN = 11,
+    ENOMEM = 12,
+    EACCES = 13,
+    EFAULT = 14,
+    EBUSY = 16,
+    EEXIST = 17,
+    ENODEV = 19,
+    ENOTDIR = 20,
+    EISDIR = 21,
+    EINVAL = 22,
+    EMFILE = 24,
+    EFBIG = 27,
+    ENOSPC = 28,
+    ESPIPE = 29,
+    EROFS = 30,
+    ENAMETOOLONG = 36,
+    ENOSYS = 38,
+    ENOTEMPTY = 39,
+    ELOOP = 40,
+    EOVERFLOW = 75,
+    ENOTSOCK = 88,
+    EOPNOTSUPP = 95,
+    EADDRINUSE = 98,
+    EADDRNOTAVAIL = 99,
+    ENETUNREACH = 101,
+    ECONNABORTED = 103,
+    ECONNRESET = 104,
+    EISCONN = 106,
+    ENOTCONN = 107,
+    ETIMEDOUT = 110,
+    ECONNREFUSED = 111,
+    EHOSTUNREACH = 113,
+}
+
+impl Errno {
+    pub fn code(self) -> i32 {
+        self as i32
+    }
+
+    /// The errno of a code from another source such as the host OS, `EIO`
+    /// if it is unknown.
+    pub fn from_code_or_io(code: i32) -> Self {
+        Self::from_code(code).unwrap_or(Self::EIO)
+    }
+}
+
+/// Errors of hostcalls.
+pub trait AsErrno: fmt::Display {
+    fn errno(&self) -> Errno;
+
+    /// Name of the service the error comes from.
+    fn service(&self) -> &'static str;
+}
+
+/// An error of any hostcall, with the messages of the contexts it went
+/// through.
+#[derive(Debug)]
+pub struct LibOSError {
+    pub errno: Errno,
+    pub service: &'static str,
+    /// Messages from the error itself to the outermost context.
+    chain: Vec<String>,
+}
+
+impl LibOSError {
+    pub fn new(errno: Errno, service: &'static str, msg: impl Into<String>) -> Self {
+        Self {
+            errno,
+            service,
+            chain: vec![msg.into()],
+        }
+    }
+
+    pub fn context(mut self, msg: impl Into<String>) -> Self {
+        self.chain.push(msg.into());
+        self
+    }
+
+    /// Messages from the outermost context to the error itself.
+    pub fn chain(&self) -> impl Iterator<Item = &str> {
+        self.chain.iter().rev().map(String::as_str)
+    }
+}
+
+impl<E: AsErrno> From<E> for LibOSError {
+    fn from(e: E) -> Self {
+        Self::new(e.errno(), e.service(), e.to_string())
+    }
+}
+
+impl fmt::Display for LibOSError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (idx, msg) in self.chain().enumerate() {
+            if idx > 0 {
+                write!(f, ": ")?;
+            }
+            write!(f, "{}", msg)?;
+        }
+        write!(f, " ({}, {})", self.errno, self.service)
+    }
+}
+
+impl core::error::Error for LibOSError {}
 
-    BadFileDescriptor,
-    NoReadPerm,
-    NoWritePerm,
+pub type LibOSResult<T> = Result<T, LibOSError>;
 
-    TcpListenErr,
-    PhyWaitErr,
-    WrongSockState,
+/// Add context to the error of a hostcall.
+pub trait Context<T> {
+    fn context(self, msg: impl Into<String>) -> LibOSResult<T>;
 
-    UFFDErr,
+    fn with_context<M: Into<String>>(self, f: impl FnOnce() -> M) -> LibOSResult<T>;
 }
 
-pub type LibOSResult<T> = Result<T, LibOSErr>;
+impl<T, E: Into<LibOSError>> Context<T> for Result<T, E> {
+    fn context(self, msg: impl Into<String>) -> LibOSResult<T> {
+        self.map_err(|e| e.into().context(msg))
+    }
+
+    fn with_context<M: Into<String>>(self, f: impl FnOnce() -> M) -> LibOSResult<T> {
+        self.map_err(|e| e.into().context(f()))
+    }
+}
+
+#[test]
+fn libos_error_test() {
+    assert_eq!(Errno::from_code(2), Some(Errno::ENOENT));
+    assert_eq!(Errno::from_code_or_io(-1), Errno::EIO);
+    assert_eq!(Errno::EACCES.code(), 13);
+
+    let res: LibOSResult<()> = Err(LibOSError::new(Errno::ENOENT, "fatfs", "no such file"));
+    let e = res.context("open a.txt").context("load input").unwrap_err();
+    assert_eq!(e.errno, Errno::ENOENT);
+    assert_eq!(
+        e.to_string(),
+        "load input: open a.txt: no such file (ENOENT, fatfs)"
+    );
+}
@@ -1,26 +1,51 @@
 use alloc::string::String;
 use thiserror_no_std::Error;
 
-use crate::types::{Fd, OpenFlags, Size, Stat};
+use crate::{
+    err::{AsErrno, Errno},
+    types::{Fd, OpenFlags, Size, Stat, Whence},
+};
 
 pub type FatfsOpenFunc = fn(&str, OpenFlags) -> FatfsResult<Fd>;
 pub type FatfsWriteFunc = fn(Fd, &[u8]) -> FatfsResult<Size>;
 pub type FatfsReadFunc = fn(Fd, &mut [u8]) -> FatfsResult<Size>;
 pub type FatfsCloseFunc = fn(Fd) -> FatfsResult<()>;
-pub type FatfsSeekFunc = fn(Fd, u32) -> FatfsResult<()>;
+pub type FatfsSeekFunc = fn(Fd, i64, Whence) -> FatfsResult<u64>;
 pub type FatfsStatFunc = fn(Fd) -> FatfsResult<Stat>;
+pub type FatfsPreadFunc = fn(Fd, &mut [u8], u64) -> FatfsResult<Size>;
+pub type FatfsPwriteFunc = fn(Fd, &[u8], u64) -> FatfsResult<Size>;
 
 pub type FatfsResult<T> = Result<T, FatfsError>;
 
 #[derive(Debug, Error)]
 #[repr(C)]
 pub enum FatfsError {
-    #[error("std::io::error, {0}")]
-    HostIOErr(String),
+    #[error("std::io::error, {1}")]
+    HostIOErr(Errno, String),
     #[error("acquire std::sync::mutex::Mutex failed, {0}")]
     AcquireLockErr(String),
     #[error("bad input fd: {0}, do not exist")]
     BadInputFd(Fd),
     #[error("Unknown.")]
     Unknown,
+    #[error("denied by the policy of the app")]
+    Denied,
+}
+
+impl AsErrno for FatfsError {
+    fn errno(&self) -> Errno {
+        match self {
+            Self::HostIOErr(errno, _) => *errno,
+            Self::BadInputFd(_) => Errno::EBADF,
+            Self::AcquireLockErr(_) | Self::Unknown => Errno::EIO,
+            Self::Denied => Errno::EACCES,
+        }
+    }
+
+    fn service(&self) -> &'static str {
+        match self {
+            Self::Denied => "as-visor",
+            _ => "fatfs",
+        }
+    }
 }
@@ -4,21 +4,29 @@ use alloc::{string::String, vec::Vec};
 use thiserror_no_std::Error;
 
 use crate::{
+    err::{AsErrno, Errno},
     fatfs::FatfsError,
     socket::SmoltcpError,
-    types::{Fd, OpenFlags, OpenMode, Size, SockFd, Stat, DirEntry},
+    types::{Fd, OpenFlags, OpenMode, Size, SockFd, Stat, DirEntry, Whence},
 };
 
 pub type OpenFunc = fn(&str, OpenFlags, OpenMode) -> FdtabResult<Fd>;
 pub type WriteFunc = fn(Fd, &[u8]) -> FdtabResult<Size>;
 pub type ReadFunc = fn(Fd, &mut [u8]) -> FdtabResult<Size>;
 pub type CloseFunc = fn(Fd) -> FdtabResult<()>;
-pub type LseekFunc = fn(Fd, u32) -> FdtabResult<()>;
+/// Move the offset of the file, returning the new offset from the start.
+pub type LseekFunc = fn(Fd, i64, Whence) -> FdtabResult<u64>;
 pub type StatFunc = fn(Fd) -> FdtabResult<Stat>;
 pub type ReadDirFunc = fn(&str) -> FdtabResult<Vec<DirEntry>>;
 pub type ConnectFunc = fn(SocketAddrV4) -> FdtabResult<Fd>;
 pub type BindFunc = fn(SocketAddrV4) -> FdtabResult<Fd>;
 pub type AcceptFunc = fn(SockFd) -> FdtabResult<SockFd>;
+pub type ReadvFunc = fn(Fd, &mut [&mut [u8]]) -> FdtabResult<Size>;
+pub type WritevFunc = fn(Fd, &[&[u8]]) -> FdtabResult<Size>;
+/// Read at an offset, without moving the offset of the file.
+pub type PreadFunc = fn(Fd, &mut [u8], u64) -> FdtabResult<Size>;
+/// Write at an offset, without moving the offset of the file.
+pub type PwriteFunc = fn(Fd, &[u8], u64) -> FdtabResult<Size>;
 
 pub type FdtabResult<T> = Result<T, FdtabError>;
 
@@ -38,10 +46,114 @@ pub enum FdtabError {
     SocketError(#[from] SmoltcpError),
     #[error("undefine {op} to {fd_type}, fd={fd}")]
     UndefinedOperation { op: String, fd: Fd, fd_type: String },
-    #[error("ruxfs error: {0}")]
-    RuxfsError(String),
-    #[error("rcore-fs error: {0}")]
-    FsError(String),
-    #[error("ext4 error: {0}")]
-    Ext4Error(String),
+    #[error("ruxfs error: {1}")]
+    RuxfsError(Errno, String),
+    #[error("rcore-fs error: {1}")]
+    FsError(Errno, String),
+    #[error("ext4 error: {1}")]
+    Ext4Error(Errno, String),
+    #[error("denied by the policy of the app")]
+    Denied,
+}
+
+impl AsErrno for FdtabError {
+    fn errno(&self) -> Errno {
+        match self {
+            Self::BadInputFd(..) | Self::NoExistFd(_) => Errno::EBADF,
+            Self::NoReadPerm(_) | Self::NoWritePerm(_) => Errno::EBADF,
+            Self::FatfsError(e) => e.errno(),
+            Self::SocketError(e) => e.errno(),
+            Self::UndefinedOperation { .. } => Errno::EINVAL,
+            Self::RuxfsError(errno, _) | Self::FsError(errno, _) | Self::Ext4Error(errno, _) => {
+                *errno
+            }
+            Self::Denied => Errno::EACCES,
+        }
+    }
+
+    fn service(&self) -> &'static str {
+        match self {
+            Self::FatfsError(e) => e.service(),
+            Self::SocketError(e) => e.service(),
+            Self::RuxfsError(..) => "ruxfs",
+            Self::FsError(..) => "rcore-fs",
+            Self::Ext4Error(..) => "ext4",
+            Self::Denied => "as-visor",
+            _ => "fdtab",
+        }
+    }
+}
+
+/// `readv` by a `read` of the same fd into each buffer in turn, stopping at
+/// the first short read. An error after some bytes are read ends it early.
+pub fn readv_by(
+    bufs: &mut [&mut [u8]],
+    mut read: impl FnMut(&mut [u8]) -> FdtabResult<Size>,
+) -> FdtabResult<Size> {
+    let mut total = 0;
+    for buf in bufs.iter_mut() {
+        let size = match read(buf) {
+            Ok(size) => size,
+            Err(_) if total > 0 => break,
+            Err(e) => return Err(e),
+        };
+        total += size;
+        if size < buf.len() {
+            break;
+        }
+    }
+
+    Ok(total)
+}
+
+/// `writev` by a `write` of the same fd from each buffer in turn, like
+/// `readv_by`.
+pub fn writev_by(
+    bufs: &[&[u8]],
+    mut write: impl FnMut(&[u8]) -> FdtabResult<Size>,
+) -> FdtabResult<Size> {
+    let mut total = 0;
+    for buf in bufs {
+        let size = match write(buf) {
+            Ok(size) => size,
+            Err(_) if total > 0 => break,
+            Err(e) => return Err(e),
+        };
+        total += size;
+        if size < buf.len() {
+            break;
+        }
+    }
+
+    Ok(total)
+}
+
+#[test]
+fn readv_by_test() {
+    let src = b"hello world";
+    let mut pos = 0;
+    let mut read = |buf: &mut [u8]| {
+        let size = buf.len().min(src.len() - pos);
+        buf[..size].copy_from_slice(&src[pos..pos + size]);
+        pos += size;
+        Ok(size)
+    };
+
+    let (mut a, mut b, mut c) = ([0; 4], [0; 4], [0; 4]);
+    let size = readv_by(&mut [&mut a, &mut b, &mut c], &mut read).unwrap();
+    assert_eq!(size, 11);
+    assert_eq!((&a, &b, &c[..3]), (b"hell", b"o wo", &b"rld"[..]));
+    assert_eq!(readv_by(&mut [&mut a], &mut read).unwrap(), 0);
+
+    let mut calls = 0;
+    let size = writev_by(&[b"ab", b"cd"], |buf| {
+        calls += 1;
+        if calls == 1 {
+            Ok(buf.len())
+        } else {
+            Err(FdtabError::NoWritePerm(3))
+        }
+    })
+    .unwrap();
+    assert_eq!(size, 2);
 }
@@ -10,6 +10,7 @@ pub mod err;
 pub mod fatfs;
 #[cfg(feature = "fdtab")]
 pub mod fdtab;
+pub mod manifest;
 #[cfg(feature = "mm")]
 pub mod mm;
 #[cfg(feature = "mmap_file_backend")]
@@ -22,12 +23,12 @@ pub mod signal;
 pub mod socket;
 pub mod types;
 
-use alloc::{borrow::ToOwned, string::String};
+use alloc::borrow::ToOwned;
 use types::{IsolationID, ServiceName};
 
 use derive_more::Display;
 
-#[derive(Debug, Display)]
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, Hash)]
 #[repr(C)]
 pub enum CommonHostCall {
     #[display(fmt = "metric")]
@@ -36,6 +37,8 @@ pub enum CommonHostCall {
     FsImage,
     #[display(fmt = "spawn_fault_thread")]
     SpawnFaultThread,
+    #[display(fmt = "trace")]
+    Trace,
 
     #[display(fmt = "write")]
     Write,
@@ -59,9 +62,19 @@ pub enum CommonHostCall {
     Bind,
     #[display(fmt = "accept")]
     Accept,
+    #[display(fmt = "readv")]
+    Readv,
+    #[display(fmt = "writev")]
+    Writev,
+    #[display(fmt = "pread")]
+    Pread,
+    #[display(fmt = "pwrite")]
+    Pwrite,
 
     #[display(fmt = "host_stdout")]
     Stdout,
+    #[display(fmt = "host_stderr")]
+    Stderr,
 
     #[display(fmt = "fatfs_open")]
     FatfsOpen,
@@ -75,6 +88,10 @@ pub enum CommonHostCall {
     FatfsSeek,
     #[display(fmt = "fatfs_stat")]
     FatfsStat,
+    #[display(fmt = "fatfs_pread")]
+    FatfsPread,
+    #[display(fmt = "fatfs_pwrite")]
+    FatfsPwrite,
 
     #[display(fmt = "addrinfo")]
     SmoltcpAddrInfo,
@@ -120,19 +137,46 @@ pub enum CommonHostCall {
     SigAction,
 }
 
+impl CommonHostCall {
+    /// `manifest::signature_hash` of the function type of the hostcall in
+    /// this revision of `as_hostcall`.
+    pub fn signature(&self) -> Option<u64> {
+        use alloc::string::ToString;
+
+        manifest::hostcall_signature(&self.to_string())
+    }
+}
+
+/// A hostcall exported by a service outside this crate, called through
+/// `as_std::libos_custom!`.
+#[derive(Debug, Display, Clone, Copy)]
+#[display(fmt = "{}", name)]
+#[repr(C)]
+pub struct CustomHostCall {
+    pub service: &'static str,
+    /// Name of the exported symbol.
+    pub name: &'static str,
+    /// `type_name` of the function type the caller expects, checked against
+    /// the manifest of the service.
+    pub signature: &'static str,
+}
+
 #[derive(Debug, Display)]
 #[repr(C)]
 pub enum HostCallID {
     Common(CommonHostCall),
-    Custom(String),
+    Custom(CustomHostCall),
 }
 impl HostCallID {
+    /// The service that provides the hostcall by default, for services
+    /// without a manifest. Custom hostcalls name their service.
     pub fn belong_to(&self) -> ServiceName {
         match self {
             Self::Common(common) => match common {
                 CommonHostCall::Metric
                 | CommonHostCall::FsImage
-                | CommonHostCall::SpawnFaultThread => "".to_owned(),
+                | CommonHostCall::SpawnFaultThread
+                | CommonHostCall::Trace => "".to_owned(),
 
                 CommonHostCall::Write
                 | CommonHostCall::Open
@@ -144,16 +188,22 @@ impl HostCallID {
                 | CommonHostCall::Connect
                 | CommonHostCall::Socket
                 | CommonHostCall::Bind
-                | CommonHostCall::Accept => "fdtab".to_owned(),
+                | CommonHostCall::Accept
+                | CommonHostCall::Readv
+                | CommonHostCall::Writev
+                | CommonHostCall::Pread
+                | CommonHostCall::Pwrite => "fdtab".to_owned(),
 
-                CommonHostCall::Stdout => "stdio".to_owned(),
+                CommonHostCall::Stdout | CommonHostCall::Stderr => "stdio".to_owned(),
 
                 CommonHostCall::FatfsOpen
                 | CommonHostCall::FatfsWrite
                 | CommonHostCall::FatfsRead
                 | CommonHostCall::FatfsClose
                 | CommonHostCall::FatfsSeek
-                | CommonHostCall::FatfsStat => "fatfs".to_owned(),
+                | CommonHostCall::FatfsStat
+                | CommonHostCall::FatfsPread
+                | CommonHostCall::FatfsPwrite => "fatfs".to_owned(),
 
                 CommonHostCall::SmoltcpAddrInfo
                 | CommonHostCall::SmoltcpConnect
@@ -178,7 +228,7 @@ impl HostCallID {
 
                 CommonHostCall::GetTime | CommonHostCall::NanoSleep => "time".to_owned(),
             },
-            HostCallID::Custom(_) => todo!(),
+            HostCallID::Custom(custom) => custom.service.to_owned(),
         }
     }
 }
@@ -198,6 +248,10 @@ fn format_hostcall_id() {
 #[derive(Clone, Default)]
 #[repr(C)]
 pub struct IsolationContext {
+    /// `manifest::ABI_VERSION` of as-visor. It comes first so that it stays
+    /// at offset 0 in every version, and modules read it alone before the
+    /// other fields, whose layout may differ from theirs.
+    pub abi_version: u32,
     pub isol_id: IsolationID,
     pub find_handler: usize,
     pub panic_handler: usize,
@@ -207,6 +261,7 @@ pub struct IsolationContext {
 impl IsolationContext {
     pub const fn uninit() -> Self {
         Self {
+            abi_version: 0,
             isol_id: 0,
             find_handler: 0,
             panic_handler: 0,
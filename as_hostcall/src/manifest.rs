@@ -0,0 +1,229 @@
+//! Manifest exported by every LibOS service, so that as-visor routes a
+//! hostcall to the service that provides it instead of by a fixed map.
+
+use crate::types;
+
+/// Version of the interface between as-visor, `as_std` and services. A
+/// module built against another version is refused at load time, by its
+/// manifest and by `set_handler_addr`.
+pub const ABI_VERSION: u32 = 9;
+
+pub const MANIFEST_SYMBOL: &str = "libos_service_manifest";
+pub type ManifestFunc = fn() -> &'static ServiceManifest;
+
+/// `abi_version` comes first so that it stays at offset 0 in every version,
+/// see `abi_version_of`.
+#[derive(Debug)]
+#[repr(C)]
+pub struct ServiceManifest {
+    pub abi_version: u32,
+    pub name: &'static str,
+    /// Names of the hostcalls, which are also the exported symbols.
+    pub provides: &'static [&'static str],
+    /// Services whose hostcalls this one calls.
+    pub depends: &'static [&'static str],
+    /// Hostcalls for `as_std::libos_custom!`, with their function types.
+    pub custom: &'static [CustomSignature],
+    /// `hostcall_signature` of the `as_hostcall` the service is built with.
+    pub signature: fn(&str) -> Option<u64>,
+}
+
+#[derive(Debug)]
+#[repr(C)]
+pub struct CustomSignature {
+    pub name: &'static str,
+    /// `core::any::type_name` of the function type, which is what callers
+    /// send in `CustomHostCall::signature`.
+    pub signature: fn() -> &'static str,
+}
+
+/// Export the `ServiceManifest` of the service being built.
+///
+/// ```ignore
+/// as_hostcall::service_manifest! {
+///     name: "stdio",
+///     provides: ["host_stdout", "host_stderr"],
+///     depends: [],
+/// }
+/// ```
+///
+/// A service with custom hostcalls lists them with their function types,
+/// which must be exported with the same types:
+///
+/// ```ignore
+/// as_hostcall::service_manifest! {
+///     name: "kv_cache",
+///     provides: [],
+///     depends: ["mm"],
+///     custom: {
+///         "cache_get": fn(&str) -> Option<u64>,
+///         "cache_put": fn(&str, u64),
+///     },
+/// }
+/// ```
+#[macro_export]
+macro_rules! service_manifest {
+    (
+        name: $name:literal,
+        provides: [$($hostcall:literal),* $(,)?],
+        depends: [$($dep:literal),* $(,)?]
+        $(, custom: { $($custom:literal: $sig:ty),* $(,)? })? $(,)?
+    ) => {
+        #[no_mangle]
+        pub fn libos_service_manifest() -> &'static $crate::manifest::ServiceManifest {
+            static MANIFEST: $crate::manifest::ServiceManifest = $crate::manifest::ServiceManifest {
+                abi_version: $crate::manifest::ABI_VERSION,
+                name: $name,
+                provides: &[$($hostcall),*],
+                depends: &[$($dep),*],
+                custom: &[$($($crate::manifest::CustomSignature {
+                    name: $custom,
+                    signature: core::any::type_name::<$sig>,
+                }),*)?],
+                signature: $crate::manifest::hostcall_signature,
+            };
+            &MANIFEST
+        }
+    };
+}
+
+/// The `abi_version` of a manifest exported by a module of any version, read
+/// alone as the other fields may be laid out differently.
+///
+/// # Safety
+/// `manifest` must point to the `ServiceManifest` of some version.
+pub unsafe fn abi_version_of(manifest: *const ServiceManifest) -> u32 {
+    manifest.cast::<u32>().read()
+}
+
+/// Hash of the function type `F`, FNV-1a of its `type_name`. A change of the
+/// fields of a struct the type refers to is not seen.
+pub fn signature_hash<F: ?Sized>() -> u64 {
+    core::any::type_name::<F>()
+        .bytes()
+        .fold(0xcbf29ce484222325, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        })
+}
+
+/// `signature_hash` of the function type of the common hostcall exported as
+/// `name`. `None` for other names and hostcalls whose types are behind a
+/// disabled feature, which are not checked.
+pub fn hostcall_signature(name: &str) -> Option<u64> {
+    let hash = match name {
+        "metric" => signature_hash::<types::MetricFunc>(),
+        "fs_image" => signature_hash::<types::FsImageFunc>(),
+        "spawn_fault_thread" => signature_hash::<types::SpawnFaultThreadFunc>(),
+        "trace" => signature_hash::<types::TraceFunc>(),
+        "host_stdout" | "host_stderr" => signature_hash::<types::HostStdioFunc>(),
+        "get_time" => signature_hash::<types::GetTimeFunc>(),
+        "host_nanosleep" => signature_hash::<types::NanoSleepFunc>(),
+
+        #[cfg(feature = "fdtab")]
+        "write" => signature_hash::<crate::fdtab::WriteFunc>(),
+        #[cfg(feature = "fdtab")]
+        "read" => signature_hash::<crate::fdtab::ReadFunc>(),
+        #[cfg(feature = "fdtab")]
+        "open" => signature_hash::<crate::fdtab::OpenFunc>(),
+        #[cfg(feature = "fdtab")]
+        "close" => signature_hash::<crate::fdtab::CloseFunc>(),
+        #[cfg(feature = "fdtab")]
+        "lseek" => signature_hash::<crate::fdtab::LseekFunc>(),
+        #[cfg(feature = "fdtab")]
+        "stat" => signature_hash::<crate::fdtab::StatFunc>(),
+        #[cfg(feature = "fdtab")]
+        "readdir" => signature_hash::<crate::fdtab::ReadDirFunc>(),
+        #[cfg(feature = "fdtab")]
+        "connect" => signature_hash::<crate::fdtab::ConnectFunc>(),
+        #[cfg(feature = "fdtab")]
+        "bind" => signature_hash::<crate::fdtab::BindFunc>(),
+        #[cfg(feature = "fdtab")]
+        "accept" => signature_hash::<crate::fdtab::AcceptFunc>(),
+        #[cfg(feature = "fdtab")]
+        "readv" => signature_hash::<crate::fdtab::ReadvFunc>(),
+        #[cfg(feature = "fdtab")]
+        "writev" => signature_hash::<crate::fdtab::WritevFunc>(),
+        #[cfg(feature = "fdtab")]
+        "pread" => signature_hash::<crate::fdtab::PreadFunc>(),
+        #[cfg(feature = "fdtab")]
+        "pwrite" => signature_hash::<crate::fdtab::PwriteFunc>(),
+
+        #[cfg(feature = "fatfs")]
+        "fatfs_open" => signature_hash::<crate::fatfs::FatfsOpenFunc>(),
+        #[cfg(feature = "fatfs")]
+        "fatfs_write" => signature_hash::<crate::fatfs::FatfsWriteFunc>(),
+        #[cfg(feature = "fatfs")]
+        "fatfs_read" => signature_hash::<crate::fatfs::FatfsReadFunc>(),
+        #[cfg(feature = "fatfs")]
+        "fatfs_close" => signature_hash::<crate::fatfs::FatfsCloseFunc>(),
+        #[cfg(feature = "fatfs")]
+        "fatfs_seek" => signature_hash::<crate::fatfs::FatfsSeekFunc>(),
+        #[cfg(feature = "fatfs")]
+        "fatfs_stat" => signature_hash::<crate::fatfs::FatfsStatFunc>(),
+        #[cfg(feature = "fatfs")]
+        "fatfs_pread" => signature_hash::<crate::fatfs::FatfsPreadFunc>(),
+        #[cfg(feature = "fatfs")]
+        "fatfs_pwrite" => signature_hash::<crate::fatfs::FatfsPwriteFunc>(),
+
+        #[cfg(feature = "socket")]
+        "addrinfo" => signature_hash::<crate::socket::SmoltcpAddrInfoFunc>(),
+        #[cfg(feature = "socket")]
+        "smol_connect" => signature_hash::<crate::socket::SmoltcpConnectFunc>(),
+        #[cfg(feature = "socket")]
+        "smol_send" => signature_hash::<crate::socket::SmoltcpSendFunc>(),
+        #[cfg(feature = "socket")]
+        "smol_recv" => signature_hash::<crate::socket::SmoltcpRecvFunc>(),
+        #[cfg(feature = "socket")]
+        "smol_bind" => signature_hash::<crate::socket::SmoltcpBindFunc>(),
+        #[cfg(feature = "socket")]
+        "smol_accept" => signature_hash::<crate::socket::SmoltcpAcceptFunc>(),
+        #[cfg(feature = "socket")]
+        "smol_close" => signature_hash::<crate::socket::SmoltcpCloseFunc>(),
+
+        #[cfg(feature = "mm")]
+        "buffer_alloc" => signature_hash::<crate::mm::BufferAllocFunc>(),
+        #[cfg(feature = "mm")]
+        "access_buffer" => signature_hash::<crate::mm::AccessBufferFunc>(),
+        #[cfg(feature = "mm")]
+        "buffer_dealloc" => signature_hash::<crate::mm::BufferDeallocFunc>(),
+        #[cfg(feature = "mm")]
+        "libos_mmap" => signature_hash::<crate::mm::MemmapFunc>(),
+        #[cfg(feature = "mm")]
+        "libos_munmap" => signature_hash::<crate::mm::MemunmapFunc>(),
+        #[cfg(feature = "mm")]
+        "libos_mprotect" => signature_hash::<crate::mm::MprotectFunc>(),
+
+        #[cfg(feature = "mmap_file_backend")]
+        "register_file_backend" => {
+            signature_hash::<crate::mmap_file_backend::RegisterFileBackendFunc>()
+        }
+        #[cfg(feature = "mmap_file_backend")]
+        "unregister_file_backend" => {
+            signature_hash::<crate::mmap_file_backend::UnregisterFileBackendFunc>()
+        }
+        #[cfg(feature = "mmap_file_backend")]
+        "file_page_fault_handler" => {
+            signature_hash::<crate::mmap_file_backend::FilePageFaultHandlerFunc>()
+        }
+
+        #[cfg(feature = "signal")]
+        "libos_sigaction" => signature_hash::<crate::signal::SigActionFunc>(),
+
+        _ => return None,
+    };
+
+    Some(hash)
+}
+
+#[test]
+fn hostcall_signature_test() {
+    assert_eq!(
+        hostcall_signature("host_stdout"),
+        hostcall_signature("host_stderr")
+    );
+    assert_ne!(
+        hostcall_signature("host_stdout"),
+        hostcall_signature("get_time")
+    );
+    assert_eq!(hostcall_signature("cache_get"), None);
+}
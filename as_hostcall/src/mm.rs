@@ -4,7 +4,11 @@ use alloc::string::String;
 use bitflags::bitflags;
 use thiserror_no_std::Error;
 
-use crate::{mmap_file_backend::MmapFileErr, types::Fd};
+use crate::{
+    err::{AsErrno, Errno},
+    mmap_file_backend::MmapFileErr,
+    types::Fd,
+};
 
 bitflags! {
     #[derive(PartialEq, Eq)]
@@ -16,12 +20,44 @@ bitflags! {
 }
 
 pub type BufferAllocFunc = fn(&str, Layout, u64) -> MMResult<usize>;
-pub type AccessBufferFunc = fn(&str) -> Option<(usize, u64)>;
+/// Take the buffer in a slot if it has the given fingerprint, and return its
+/// address and fingerprint. A buffer of another fingerprint is left in the
+/// slot, so that a caller seeing the mismatch does not leak it.
+pub type AccessBufferFunc = fn(&str, u64) -> Option<(usize, u64)>;
 pub type BufferDeallocFunc = fn(usize, Layout);
 pub type MemmapFunc = fn(usize, usize, ProtFlags, Fd) -> MMResult<usize>;
 pub type MemunmapFunc = fn(&mut [u8], bool) -> MMResult<()>;
 pub type MprotectFunc = fn(usize, usize, ProtFlags) -> MMResult<()>;
 
+/// Symbol queried by as-visor after a workflow run, it is not a hostcall.
+pub const BUFFER_STATS_SYMBOL: &str = "buffer_stats";
+pub type BufferStatsFunc = fn() -> BufferStats;
+
+#[derive(Debug, Default, Clone, Copy)]
+#[repr(C)]
+pub struct BufferStats {
+    /// Bytes allocated by `buffer_alloc` so far.
+    pub alloc_bytes: usize,
+    /// Bytes handed to consumers by `access_buffer` so far.
+    pub transferred_bytes: usize,
+    /// Bytes allocated and not yet deallocated.
+    pub in_use_bytes: usize,
+}
+
+/// Symbol of every module that uses the heap allocator of `as_std`, queried
+/// by as-visor for memory metrics. It is not a hostcall.
+pub const HEAP_STATS_SYMBOL: &str = "heap_stats";
+pub type HeapStatsFunc = fn() -> HeapStats;
+
+#[derive(Debug, Default, Clone, Copy)]
+#[repr(C)]
+pub struct HeapStats {
+    pub used_bytes: usize,
+    pub free_bytes: usize,
+    /// The highest `used_bytes` so far.
+    pub peak_bytes: usize,
+}
+
 pub type MMResult<T> = Result<T, MMError>;
 
 #[derive(Debug, Error)]
@@ -32,6 +68,23 @@ pub enum MMError {
     LayoutErr(#[from] LayoutError),
     #[error(transparent)]
     FileBackendErr(#[from] MmapFileErr),
-    #[error("libc api error: {0}")]
-    LibcErr(String),
+    #[error("libc api error: {1}")]
+    LibcErr(Errno, String),
+}
+
+impl AsErrno for MMError {
+    fn errno(&self) -> Errno {
+        match self {
+            Self::InvaildArg(..) | Self::LayoutErr(_) => Errno::EINVAL,
+            Self::FileBackendErr(e) => e.errno(),
+            Self::LibcErr(errno, _) => *errno,
+        }
+    }
+
+    fn service(&self) -> &'static str {
+        match self {
+            Self::FileBackendErr(e) => e.service(),
+            _ => "mm",
+        }
+    }
 }
@@ -3,12 +3,21 @@ use core::ffi::c_void;
 use alloc::string::String;
 use thiserror_no_std::Error;
 
-use crate::{fdtab::FdtabError, types::Fd};
+use crate::{
+    err::{AsErrno, Errno},
+    fdtab::FdtabError,
+    types::Fd,
+};
 
 pub type RegisterFileBackendFunc = fn(&mut [c_void], Fd) -> MmapFileResult<()>;
 pub type UnregisterFileBackendFunc = fn(usize) -> MmapFileResult<()>;
 pub type FilePageFaultHandlerFunc = fn();
 
+/// Symbol called by as-visor before an isolation is dropped, so that the
+/// thread of `file_page_fault_handler` exits. It is not a hostcall.
+pub const STOP_FAULT_HANDLER_SYMBOL: &str = "stop_file_page_fault_handler";
+pub type StopFaultHandlerFunc = fn();
+
 pub type MmapFileResult<T> = Result<T, MmapFileErr>;
 
 #[derive(Debug, Error)]
@@ -17,8 +26,8 @@ pub enum MmapFileErr {
     AcquireLockErr(String, String),
     #[error("pipe error: {0}")]
     PipeStateErr(String),
-    #[error("nix error: {0}")]
-    NixErr(String),
+    #[error("nix error: {1}")]
+    NixErr(Errno, String),
     #[error("Unknown error: {0}")]
     Unknown(String),
     #[error(transparent)]
@@ -28,3 +37,20 @@ pub enum MmapFileErr {
     #[error("userfaultfd error: {0}")]
     UffdError(String),
 }
+
+impl AsErrno for MmapFileErr {
+    fn errno(&self) -> Errno {
+        match self {
+            Self::NixErr(errno, _) => *errno,
+            Self::FileError(e) => e.errno(),
+            _ => Errno::EIO,
+        }
+    }
+
+    fn service(&self) -> &'static str {
+        match self {
+            Self::FileError(e) => e.service(),
+            _ => "mmap_file_backend",
+        }
+    }
+}
@@ -3,7 +3,10 @@ use core::net::{Ipv4Addr, SocketAddrV4};
 use alloc::string::String;
 use thiserror_no_std::Error;
 
-use crate::types::{Size, SockFd};
+use crate::{
+    err::{AsErrno, Errno},
+    types::{Size, SockFd},
+};
 
 pub type SmoltcpAddrInfoFunc = fn(&str) -> SmoltcpResult<Ipv4Addr>;
 pub type SmoltcpConnectFunc = fn(SocketAddrV4) -> SmoltcpResult<SockFd>;
@@ -21,12 +24,33 @@ pub enum SmoltcpError {
     AcquireLockErr(String, String),
     #[error("smoltcp error, {0}")]
     SmoltcpErr(String),
-    #[error("std::io::error, {0}")]
-    HostIOErr(String),
+    #[error("std::io::error, {1}")]
+    HostIOErr(Errno, String),
     #[error("unknown reason, expect have query result")]
     DNSQueryFailed,
     #[error("wrong tcp state, expect={0}, found={1}")]
     BadTCPState(String, String),
     #[error("unknown reason, expect have local endpoint")]
     NoLocalEndpoint,
+    #[error("denied by the policy of the app")]
+    Denied,
+}
+
+impl AsErrno for SmoltcpError {
+    fn errno(&self) -> Errno {
+        match self {
+            Self::HostIOErr(errno, _) => *errno,
+            Self::AcquireLockErr(..) | Self::SmoltcpErr(_) => Errno::EIO,
+            Self::DNSQueryFailed => Errno::EHOSTUNREACH,
+            Self::BadTCPState(..) | Self::NoLocalEndpoint => Errno::ENOTCONN,
+            Self::Denied => Errno::EACCES,
+        }
+    }
+
+    fn service(&self) -> &'static str {
+        match self {
+            Self::Denied => "as-visor",
+            _ => "smoltcp",
+        }
+    }
 }
@@ -2,7 +2,7 @@
 
 use alloc::string::String;
 
-use crate::{HostCallID, IsolationContext};
+use crate::{CommonHostCall, HostCallID, IsolationContext};
 
 use bitflags::bitflags;
 
@@ -20,10 +20,40 @@ pub struct NetdevName {
 #[derive(Debug)]
 pub enum HostCallError {
     HasBeenSet,
+    /// The module is built for this ABI version instead of the one of
+    /// as-visor.
+    AbiVersion(u32),
+    /// The hostcall is not allowed by the policy of the app.
+    Denied,
+    /// The caller and the provider disagree on the type of the hostcall,
+    /// see `CommonHostCall::signature`.
+    Signature {
+        called: u64,
+        provided: u64,
+    },
+    /// A custom hostcall is called with another type than its service
+    /// declares, both are `type_name`s.
+    CustomSignature {
+        called: String,
+        provided: String,
+    },
+    /// The service the hostcall is routed to does not provide it in its
+    /// manifest.
+    NotProvided(ServiceName),
+    /// No service is routed to for the hostcall.
+    NoRoute,
+    /// The service the hostcall is routed to can not be loaded, the error of
+    /// the loader.
+    LoadFailed(String),
+    /// The service the hostcall is routed to does not export it.
+    NoSymbol(ServiceName),
 }
 
 // msvisor
-pub type FindHostCallFunc = unsafe extern "C" fn(IsolationID, HostCallID) -> usize;
+/// Resolve a hostcall, given the `CommonHostCall::signature` the caller
+/// expects, or 0 to skip the check.
+pub type FindHostCallFunc =
+    unsafe extern "C" fn(IsolationID, HostCallID, u64) -> Result<usize, HostCallError>;
 
 // service init
 pub type SetHandlerFunc = unsafe extern "C" fn(&IsolationContext) -> HostCallResult;
@@ -38,12 +68,13 @@ pub type RustMainFunc = unsafe fn() -> u64;
 
 // fdtab
 bitflags! {
+    #[derive(Debug)]
     pub struct OpenFlags: u32 {
        const O_APPEND = 1;
        const O_CREAT = 2;
     }
 
-    #[derive(PartialEq, Clone)]
+    #[derive(Debug, PartialEq, Clone)]
     pub struct OpenMode: u32 {
         const RD = 1;
         const WR = 2;
@@ -53,6 +84,38 @@ bitflags! {
 pub type Fd = u32;
 pub type Size = usize;
 
+/// Where the offset of `lseek` counts from, with the values of `SEEK_SET`,
+/// `SEEK_CUR` and `SEEK_END`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum Whence {
+    Set = 0,
+    Cur = 1,
+    End = 2,
+}
+
+impl Whence {
+    pub fn from_raw(whence: u32) -> Option<Self> {
+        match whence {
+            0 => Some(Self::Set),
+            1 => Some(Self::Cur),
+            2 => Some(Self::End),
+            _ => None,
+        }
+    }
+
+    /// The offset `offset` away from `whence`, given the current offset and
+    /// the size of the file, or `None` if it would be negative or overflow.
+    pub fn resolve(self, offset: i64, cur: u64, size: u64) -> Option<u64> {
+        let base = match self {
+            Self::Set => 0,
+            Self::Cur => cur,
+            Self::End => size,
+        };
+        base.checked_add_signed(offset)
+    }
+}
+
 // time for stat
 #[derive(Debug)]
 pub struct TimeSpec {
@@ -121,10 +184,29 @@ pub type MetricFunc = fn(IsolationID, MetricEvent) -> Result<(), ()>;
 pub type FsImageFunc = fn(IsolationID) -> Option<String>;
 pub type SpawnFaultThreadFunc = fn(IsolationID) -> Result<(), String>;
 
+// trace
+/// Events reported to as-visor when the isolation records a trace or
+/// hostcall statistics, see `CommonHostCall::Trace`.
+#[derive(Debug)]
+pub enum TraceEvent<'a> {
+    HostcallBegin(CommonHostCall),
+    HostcallEnd(CommonHostCall),
+    /// TSC cycles spent writing PKRU around a hostcall by
+    /// `libos_with_switch_mpk`. Reported before switching back, so it has
+    /// the switch back of the previous call from the same module instead.
+    PkeySwitch(CommonHostCall, u64),
+    /// A data buffer was allocated for `slot`.
+    BufferProduced(&'a str),
+    /// A data buffer was taken from `slot`.
+    BufferConsumed(&'a str),
+}
+pub type TraceFunc = fn(IsolationID, TraceEvent);
+
 #[derive(Debug)]
 pub enum MetricEvent {
     // IsolationEvent
     IsolBegin,
+    IsolRun,
     LoadService,
     IsolEnd,
 
@@ -141,3 +223,14 @@ pub trait Transmutor {
     fn find_host_call() -> FindHostCallFunc;
     fn host_panic_handler() -> PanicHandlerFunc;
 }
+
+#[test]
+fn whence_resolve_test() {
+    assert_eq!(Whence::from_raw(2), Some(Whence::End));
+    assert_eq!(Whence::from_raw(3), None);
+    assert_eq!(Whence::Set.resolve(5, 3, 10), Some(5));
+    assert_eq!(Whence::Cur.resolve(-2, 3, 10), Some(1));
+    assert_eq!(Whence::End.resolve(-10, 3, 10), Some(0));
+    assert_eq!(Whence::Cur.resolve(-4, 3, 10), None);
+    assert_eq!(Whence::End.resolve(i64::MAX, 0, u64::MAX), None);
+}
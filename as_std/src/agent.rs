@@ -35,9 +35,12 @@ mod refer_based_impl {
     use core::{alloc::Layout, borrow::Borrow, mem::ManuallyDrop};
 
     use alloc::{boxed::Box, string::String};
-    use as_hostcall::Verify;
+    use as_hostcall::{types::TraceEvent, Verify};
 
-    use crate::{libos::libos, println};
+    use crate::{
+        libos::{self, libos},
+        println,
+    };
 
     #[derive(Debug)]
     pub struct DataBuffer<T> {
@@ -70,7 +73,9 @@ mod refer_based_impl {
             } else {
                 let fingerprint = T::__fingerprint();
 
-                libos!(buffer_alloc(&slot, l, fingerprint)).expect("alloc failed.") as *mut T
+                let p = libos!(buffer_alloc(&slot, l, fingerprint)).expect("alloc failed.");
+                libos::trace(TraceEvent::BufferProduced(&slot));
+                p as *mut T
 
                 // let val = T::default();
                 // println!("will write addr=0x{:x}", addr as usize);
@@ -90,7 +95,10 @@ mod refer_based_impl {
         }
 
         pub fn from_buffer_slot(slot: String) -> Option<Self> {
-            let buffer_meta: Option<(usize, u64)> = libos!(access_buffer(&slot));
+            let buffer_meta: Option<(usize, u64)> = libos!(access_buffer(&slot, T::__fingerprint()));
+            if buffer_meta.is_some() {
+                libos::trace(TraceEvent::BufferConsumed(&slot));
+            }
 
             buffer_meta.map(|(raw_ptr, fingerprint)| {
                 if fingerprint != T::__fingerprint() {
@@ -162,11 +170,11 @@ mod refer_based_impl {
 #[cfg(feature = "file-based")]
 mod file_based_impl {
     use alloc::{format, string::String};
+    use as_hostcall::{types::TraceEvent, Verify};
     use core::{borrow::Borrow, fmt::Write};
-    use as_hostcall::Verify;
     use serde::{Deserialize, Serialize};
 
-    use crate::fs::File;
+    use crate::{fs::File, libos};
 
     #[derive(Debug)]
     pub struct DataBuffer<T>
@@ -217,6 +225,7 @@ mod file_based_impl {
             } else {
                 return None;
             };
+            libos::trace(TraceEvent::BufferConsumed(&slot));
 
             let mut result = Self {
                 inner: Default::default(),
@@ -293,6 +302,7 @@ mod file_based_impl {
                 let data_str = serde_json::to_string(&self.inner).expect("serialize failed.");
 
                 content.write_str(&data_str).expect("write failed.");
+                libos::trace(TraceEvent::BufferProduced(&self.slot));
             }
         }
     }
@@ -4,7 +4,10 @@ pub(crate) struct ArgsItem {
     pub(crate) val: heapless::String<32>,
 }
 
-pub fn get(name: &str) -> Option<&'static str> {
+/// The arguments as-visor writes at the top of the stack, found from the
+/// current frame, so it is only reachable near the entry of the function.
+#[inline(always)]
+fn args_list() -> &'static heapless::Vec<ArgsItem, 16> {
     let mut args_base_addr: usize;
     unsafe {
         core::arch::asm!(
@@ -13,9 +16,11 @@ pub fn get(name: &str) -> Option<&'static str> {
     };
     let page_size = 0x1000;
     let args_base_addr = (args_base_addr + page_size - 1) & (!page_size + 1);
-    let args_list = unsafe { &mut *(args_base_addr as *mut heapless::Vec<ArgsItem, 16>) };
+    unsafe { &*(args_base_addr as *const heapless::Vec<ArgsItem, 16>) }
+}
 
-    for item in args_list {
+pub fn get(name: &str) -> Option<&'static str> {
+    for item in args_list() {
         if item.key == name {
             return Some(item.val.as_str());
         }
@@ -23,3 +28,10 @@ pub fn get(name: &str) -> Option<&'static str> {
 
     None
 }
+
+/// All arguments as key and value.
+pub fn all() -> impl Iterator<Item = (&'static str, &'static str)> {
+    args_list()
+        .iter()
+        .map(|item| (item.key.as_str(), item.val.as_str()))
+}
@@ -4,7 +4,7 @@ use as_hostcall::{
 };
 
 use crate::{
-    io::{Read, Write},
+    io::{Read, Seek, SeekFrom, Write},
     libos::libos,
     println,
 };
@@ -35,8 +35,15 @@ impl File {
         Ok(File { raw_fd })
     }
 
-    pub fn seek(&mut self, pos: u32) -> Result<(), FdtabError> {
-        libos!(lseek(self.raw_fd, pos))
+    /// Read at `offset` without moving the offset of the file, so threads
+    /// can read a shared file at the same time.
+    pub fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize, FdtabError> {
+        libos!(pread(self.raw_fd, buf, offset))
+    }
+
+    /// Write at `offset` without moving the offset of the file.
+    pub fn write_at(&self, buf: &[u8], offset: u64) -> Result<usize, FdtabError> {
+        libos!(pwrite(self.raw_fd, buf, offset))
     }
 
     pub fn as_raw_fd(&self) -> Fd {
@@ -71,6 +78,13 @@ impl Read for File {
     }
 }
 
+impl Seek for File {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, FdtabError> {
+        let (offset, whence) = pos.to_lseek();
+        libos!(lseek(self.raw_fd, offset, whence))
+    }
+}
+
 impl Drop for File {
     fn drop(&mut self) {
         libos!(close(self.raw_fd)).expect("close failed");
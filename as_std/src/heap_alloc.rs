@@ -1,8 +1,57 @@
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    ops::Deref,
+    ptr::{null_mut, NonNull},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
 use linked_list_allocator::LockedHeap;
-use as_hostcall::SERVICE_HEAP_SIZE;
+use as_hostcall::{mm::HeapStats, SERVICE_HEAP_SIZE};
+
+/// `LockedHeap` that remembers its highest usage.
+pub struct TrackedHeap {
+    heap: LockedHeap,
+    peak: AtomicUsize,
+}
+
+impl TrackedHeap {
+    pub const fn empty() -> Self {
+        Self {
+            heap: LockedHeap::empty(),
+            peak: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl Deref for TrackedHeap {
+    type Target = LockedHeap;
+
+    fn deref(&self) -> &LockedHeap {
+        &self.heap
+    }
+}
+
+unsafe impl GlobalAlloc for TrackedHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut heap = self.heap.lock();
+        match heap.allocate_first_fit(layout) {
+            Ok(ptr) => {
+                self.peak.fetch_max(heap.used(), Ordering::Relaxed);
+                ptr.as_ptr()
+            }
+            Err(_) => null_mut(),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.heap
+            .lock()
+            .deallocate(NonNull::new_unchecked(ptr), layout)
+    }
+}
 
 #[global_allocator]
-pub static HEAP_ALLOCATOR: LockedHeap = LockedHeap::empty();
+pub static HEAP_ALLOCATOR: TrackedHeap = TrackedHeap::empty();
 
 /// Currently, all service will get a static heap region. It is work well but
 /// maybe cause wasting memory.
@@ -14,6 +63,17 @@ pub fn init_heap(heap_start: usize) {
     }
 }
 
+/// Queried by as-visor through `HEAP_STATS_SYMBOL`.
+#[no_mangle]
+pub fn heap_stats() -> HeapStats {
+    let heap = HEAP_ALLOCATOR.lock();
+    HeapStats {
+        used_bytes: heap.used(),
+        free_bytes: heap.free(),
+        peak_bytes: HEAP_ALLOCATOR.peak.load(Ordering::Relaxed),
+    }
+}
+
 #[alloc_error_handler]
 /// panic when heap allocation error occurs
 pub fn handle_alloc_error(layout: core::alloc::Layout) -> ! {
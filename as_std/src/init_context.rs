@@ -4,7 +4,11 @@
 //! To some service that depent `std`, they would use this crate directly
 //! rather than `as_std`.
 
-use as_hostcall::{types::HostCallResult as HCResult, IsolationContext};
+use as_hostcall::{
+    manifest::ABI_VERSION,
+    types::{HostCallError, HostCallResult as HCResult},
+    IsolationContext,
+};
 
 use spin::Mutex;
 
@@ -26,6 +30,12 @@ pub fn isolation_ctx() -> spin::MutexGuard<'static, IsolationContext> {
 #[allow(improper_ctypes_definitions)]
 #[no_mangle]
 pub extern "C" fn set_handler_addr(ctx: &IsolationContext) -> HCResult {
+    // Only `abi_version` is at the same offset for as-visor of another
+    // version, so nothing else is read before it matches.
+    let abi_version = unsafe { (ctx as *const IsolationContext).cast::<u32>().read() };
+    if abi_version != ABI_VERSION {
+        return Err(HostCallError::AbiVersion(ABI_VERSION));
+    }
     let mut isol_ctx = isolation_ctx_mut();
     if isol_ctx.find_handler != 0 && isol_ctx.find_handler != ctx.find_handler {
         panic!();
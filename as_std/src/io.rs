@@ -2,7 +2,9 @@ use alloc::{
     string::{String, ToString},
     vec::Vec,
 };
-use as_hostcall::fdtab::FdtabError;
+use as_hostcall::{fdtab::FdtabError, types::Whence};
+
+pub use as_hostcall::err::{AsErrno, Context, Errno, LibOSError, LibOSResult};
 
 pub trait Read {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize, FdtabError>;
@@ -49,3 +51,38 @@ pub trait Write {
         Ok(())
     }
 }
+
+/// Where to move the offset of a file to, like `std::io::SeekFrom`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeekFrom {
+    Start(u64),
+    End(i64),
+    Current(i64),
+}
+
+impl SeekFrom {
+    /// The arguments of `lseek` for this position.
+    pub fn to_lseek(self) -> (i64, Whence) {
+        match self {
+            // An offset past `i64::MAX` wraps to a negative one, which
+            // `lseek` refuses.
+            SeekFrom::Start(offset) => (offset as i64, Whence::Set),
+            SeekFrom::End(offset) => (offset, Whence::End),
+            SeekFrom::Current(offset) => (offset, Whence::Cur),
+        }
+    }
+}
+
+pub trait Seek {
+    /// Move the offset, returning the new offset from the start.
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, FdtabError>;
+
+    fn rewind(&mut self) -> Result<(), FdtabError> {
+        self.seek(SeekFrom::Start(0))?;
+        Ok(())
+    }
+
+    fn stream_position(&mut self) -> Result<u64, FdtabError> {
+        self.seek(SeekFrom::Current(0))
+    }
+}
@@ -9,6 +9,7 @@
 #![feature(generic_const_exprs)]
 #![allow(incomplete_features)]
 #![feature(const_mut_refs)]
+#![feature(thread_local)]
 
 use agent::FaaSFuncResult;
 use alloc::string::String;
@@ -1,9 +1,12 @@
-use crate::libos::USER_HOST_CALL;
+use as_hostcall::types::TraceEvent;
+
+use crate::{init_context::isolation_ctx, libos::USER_HOST_CALL};
 
 pub macro func_type {
     (metric) => (as_hostcall::types::MetricFunc),
     (fs_image) => (as_hostcall::types::FsImageFunc),
     (spawn_fault_handler) => (as_hostcall::types::SpawnFaultThreadFunc),
+    (trace) => (as_hostcall::types::TraceFunc),
     (write) => (as_hostcall::fdtab::WriteFunc),
     (open) => (as_hostcall::fdtab::OpenFunc),
     (read) => (as_hostcall::fdtab::ReadFunc),
@@ -14,13 +17,20 @@ pub macro func_type {
     (connect) => (as_hostcall::fdtab::ConnectFunc),
     (bind) => (as_hostcall::fdtab::BindFunc),
     (accept) => (as_hostcall::fdtab::AcceptFunc),
+    (readv) => (as_hostcall::fdtab::ReadvFunc),
+    (writev) => (as_hostcall::fdtab::WritevFunc),
+    (pread) => (as_hostcall::fdtab::PreadFunc),
+    (pwrite) => (as_hostcall::fdtab::PwriteFunc),
     (fatfs_open) => (as_hostcall::fatfs::FatfsOpenFunc),
     (fatfs_write) => (as_hostcall::fatfs::FatfsWriteFunc),
     (fatfs_read) => (as_hostcall::fatfs::FatfsReadFunc),
     (fatfs_close) => (as_hostcall::fatfs::FatfsCloseFunc),
     (fatfs_seek) => (as_hostcall::fatfs::FatfsSeekFunc),
     (fatfs_stat) => (as_hostcall::fatfs::FatfsStatFunc),
+    (fatfs_pread) => (as_hostcall::fatfs::FatfsPreadFunc),
+    (fatfs_pwrite) => (as_hostcall::fatfs::FatfsPwriteFunc),
     (stdout) => (as_hostcall::types::HostStdioFunc),
+    (stderr) => (as_hostcall::types::HostStdioFunc),
     (addrinfo) => (as_hostcall::socket::SmoltcpAddrInfoFunc),
     (smol_connect) => (as_hostcall::socket::SmoltcpConnectFunc),
     (send) => (as_hostcall::socket::SmoltcpSendFunc),
@@ -45,6 +55,7 @@ pub macro hostcall_id {
     (metric) => (as_hostcall::CommonHostCall::Metric),
     (fs_image) => (as_hostcall::CommonHostCall::FsImage),
     (spawn_fault_handler) => (as_hostcall::CommonHostCall::SpawnFaultThread),
+    (trace) => (as_hostcall::CommonHostCall::Trace),
     (write) => (as_hostcall::CommonHostCall::Write),
     (open) => (as_hostcall::CommonHostCall::Open),
     (read) => (as_hostcall::CommonHostCall::Read),
@@ -55,13 +66,20 @@ pub macro hostcall_id {
     (connect) => (as_hostcall::CommonHostCall::Connect),
     (bind) => (as_hostcall::CommonHostCall::Bind),
     (accept) => (as_hostcall::CommonHostCall::Accept),
+    (readv) => (as_hostcall::CommonHostCall::Readv),
+    (writev) => (as_hostcall::CommonHostCall::Writev),
+    (pread) => (as_hostcall::CommonHostCall::Pread),
+    (pwrite) => (as_hostcall::CommonHostCall::Pwrite),
     (fatfs_open) => (as_hostcall::CommonHostCall::FatfsOpen),
     (fatfs_write) => (as_hostcall::CommonHostCall::FatfsWrite),
     (fatfs_read) => (as_hostcall::CommonHostCall::FatfsRead),
     (fatfs_close) => (as_hostcall::CommonHostCall::FatfsClose),
     (fatfs_seek) => (as_hostcall::CommonHostCall::FatfsSeek),
     (fatfs_stat) => (as_hostcall::CommonHostCall::FatfsStat),
+    (fatfs_pread) => (as_hostcall::CommonHostCall::FatfsPread),
+    (fatfs_pwrite) => (as_hostcall::CommonHostCall::FatfsPwrite),
     (stdout) => (as_hostcall::CommonHostCall::Stdout),
+    (stderr) => (as_hostcall::CommonHostCall::Stderr),
     (addrinfo) => (as_hostcall::CommonHostCall::SmoltcpAddrInfo),
     (smol_connect) => (as_hostcall::CommonHostCall::SmoltcpConnect),
     (send) => (as_hostcall::CommonHostCall::SmoltcpSend),
@@ -86,11 +104,17 @@ pub macro libos {
     ($name:ident($($arg_name:expr),*)) => {
         {
             fn binding() -> func_type!($name){
-                let mut table = USER_HOST_CALL.lock();
-                unsafe { core::mem::transmute(table.get_or_find(hostcall_id!($name))) }
+                unsafe { core::mem::transmute(USER_HOST_CALL.get_or_find(hostcall_id!($name))) }
             }
             let $name = binding();
+            let tracer = crate::libos::tracer();
+            if let Some(trace) = tracer {
+                trace(isolation_ctx().isol_id, TraceEvent::HostcallBegin(hostcall_id!($name)));
+            }
             let res = $name($($arg_name),*);
+            if let Some(trace) = tracer {
+                trace(isolation_ctx().isol_id, TraceEvent::HostcallEnd(hostcall_id!($name)));
+            }
             res
         }
     }
@@ -102,6 +126,7 @@ pub macro libos_with_switch_mpk {
         {
             use core::arch::asm;
             use crate::mpk;
+            let switch_begin = mpk::rdtsc();
             let pkru = mpk::pkey_read();
             let is_privilege_level = (pkru >> 30 == 0);
             // grant access to libos. 00 00 11 ... ... 11 00
@@ -115,15 +140,25 @@ pub macro libos_with_switch_mpk {
                     in("rax") pkru,
                 );
             }
+            let grant_cycles = mpk::rdtsc() - switch_begin;
 
             fn binding() -> func_type!($name){
-                let mut table = USER_HOST_CALL.lock();
-                unsafe { core::mem::transmute(table.get_or_find(hostcall_id!($name))) }
+                unsafe { core::mem::transmute(USER_HOST_CALL.get_or_find(hostcall_id!($name))) }
             }
             let $name = binding();
+            let tracer = crate::libos::tracer();
+            if let Some(trace) = tracer {
+                let cycles = grant_cycles + mpk::LAST_DROP_CYCLES.get();
+                trace(isolation_ctx().isol_id, TraceEvent::PkeySwitch(hostcall_id!($name), cycles));
+                trace(isolation_ctx().isol_id, TraceEvent::HostcallBegin(hostcall_id!($name)));
+            }
             let res = $name($($arg_name),*);
+            if let Some(trace) = tracer {
+                trace(isolation_ctx().isol_id, TraceEvent::HostcallEnd(hostcall_id!($name)));
+            }
 
             if !is_privilege_level {
+                let switch_begin = mpk::rdtsc();
                 let pkru = mpk::drop_libos_perm(pkru);
                 // drop permission to libos. 11 00 11 ... ... 11 00
                 unsafe{
@@ -134,6 +169,74 @@ pub macro libos_with_switch_mpk {
                         in("rax") pkru,
                     );
                 }
+                mpk::LAST_DROP_CYCLES.set(mpk::rdtsc() - switch_begin);
+            }
+
+            res
+        }
+    }
+}
+
+/// Call a custom hostcall `$name` of service `$service`, exported as a
+/// function of type `$sig`:
+///
+/// ```ignore
+/// let hits = libos_custom!("kv_cache", "cache_get": fn(&str) -> Option<u64>, "hits");
+/// ```
+///
+/// The type is checked against the manifest of the service when the call
+/// is first resolved, which panics on a mismatch.
+pub macro libos_custom_call {
+    ($service:literal, $name:literal: $sig:ty $(, $arg_name:expr)* $(,)?) => {
+        {
+            use core::sync::atomic::{AtomicUsize, Ordering};
+            static ADDR: AtomicUsize = AtomicUsize::new(0);
+            let mut addr = ADDR.load(Ordering::Relaxed);
+            if addr == 0 {
+                addr = crate::libos::find_custom(as_hostcall::CustomHostCall {
+                    service: $service,
+                    name: $name,
+                    signature: core::any::type_name::<$sig>(),
+                });
+                ADDR.store(addr, Ordering::Relaxed);
+            }
+            let func: $sig = unsafe { core::mem::transmute(addr) };
+            func($($arg_name),*)
+        }
+    }
+}
+
+#[cfg(feature = "mpk")]
+pub macro libos_custom_with_switch_mpk {
+    ($service:literal, $name:literal: $sig:ty $(, $arg_name:expr)* $(,)?) => {
+        {
+            use core::arch::asm;
+            use crate::mpk;
+            let pkru = mpk::pkey_read();
+            let is_privilege_level = (pkru >> 30 == 0);
+            // grant access to libos. 00 00 11 ... ... 11 00
+            let pkru = mpk::grant_libos_perm(pkru);
+            unsafe{
+                asm!(
+                    "xor rcx, rcx",
+                    "mov rdx, rcx",
+                    "wrpkru",
+                    in("rax") pkru,
+                );
+            }
+
+            let res = libos_custom_call!($service, $name: $sig $(, $arg_name)*);
+
+            if !is_privilege_level {
+                let pkru = mpk::drop_libos_perm(pkru);
+                // drop permission to libos. 11 00 11 ... ... 11 00
+                unsafe{
+                    asm!(
+                        "xor rcx, rcx",
+                        "mov rdx, rcx",
+                        in("rax") pkru,
+                    );
+                }
             }
 
             res
@@ -1,7 +1,19 @@
-use core::arch::asm;
+use core::{
+    arch::{asm, x86_64::_rdtsc},
+    cell::Cell,
+};
 
 use as_hostcall::mpk::LIBOS_PKEY;
 
+/// Cycles of the last switch back from libos, reported with the next
+/// hostcall of the same thread, see `TraceEvent::PkeySwitch`.
+#[thread_local]
+pub static LAST_DROP_CYCLES: Cell<u64> = Cell::new(0);
+
+pub fn rdtsc() -> u64 {
+    unsafe { _rdtsc() }
+}
+
 pub fn pkey_read() -> u32 {
     // Reads the value of PKRU into EAX and clears EDX. ECX must be 0 when RDPKRU is executed; otherwise, a general-protection exception (#GP) occurs.
     let result: u32;
@@ -0,0 +1,349 @@
+//! Admission control of workflow runs. A run needs a permit of the global
+//! limit and one of its workflow's limit. Requests that can not be admitted
+//! right away wait in a bounded queue, and are rejected once the queue is
+//! full or they waited longer than the queue timeout. Once closed on
+//! shutdown, new and waiting requests are rejected.
+
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use serde_json::{json, Value};
+use tokio::sync::{AcquireError, OwnedSemaphorePermit, Semaphore};
+
+#[derive(Debug)]
+pub enum AdmissionError {
+    QueueFull(usize),
+    Timeout(Duration),
+    Closed,
+}
+
+impl Display for AdmissionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AdmissionError::QueueFull(depth) => {
+                write!(f, "admission queue is full, depth={}", depth)
+            }
+            AdmissionError::Timeout(timeout) => {
+                write!(f, "wait for admission timeout after {:?}", timeout)
+            }
+            AdmissionError::Closed => write!(f, "asvisor-d is shutting down"),
+        }
+    }
+}
+
+pub struct AdmissionConfig {
+    pub max_concurrency: usize,
+    /// Limit of workflows that do not set their own, `None` is unlimited.
+    pub max_per_workflow: Option<usize>,
+    pub max_queue: usize,
+    pub queue_timeout: Duration,
+}
+
+#[derive(Default)]
+struct AdmissionStats {
+    queued: AtomicUsize,
+    running: AtomicUsize,
+    admitted_total: AtomicU64,
+    rejected_total: AtomicU64,
+    timeout_total: AtomicU64,
+    wait_us_total: AtomicU64,
+    wait_us_max: AtomicU64,
+}
+
+pub struct Admission {
+    config: AdmissionConfig,
+    global: Arc<Semaphore>,
+    per_workflow: Mutex<HashMap<String, (usize, Arc<Semaphore>)>>,
+    stats: AdmissionStats,
+    closed: AtomicBool,
+}
+
+impl Admission {
+    pub fn new(config: AdmissionConfig) -> Arc<Self> {
+        log::info!(
+            "admission: max_concurrency={}, max_per_workflow={:?}, max_queue={}, queue_timeout={:?}",
+            config.max_concurrency,
+            config.max_per_workflow,
+            config.max_queue,
+            config.queue_timeout
+        );
+        Arc::new(Self {
+            global: Arc::new(Semaphore::new(config.max_concurrency)),
+            per_workflow: Default::default(),
+            stats: Default::default(),
+            closed: AtomicBool::new(false),
+            config,
+        })
+    }
+
+    fn workflow_semaphore(&self, workflow: &str, limit: Option<usize>) -> Option<Arc<Semaphore>> {
+        let limit = limit.or(self.config.max_per_workflow)?;
+        let mut per_workflow = self.per_workflow.lock().unwrap();
+        let entry = per_workflow
+            .entry(workflow.to_owned())
+            .or_insert_with(|| (limit, Arc::new(Semaphore::new(limit))));
+        // The limit changed with a new version. The semaphore is kept, so runs
+        // holding its permits still count against the new limit.
+        if entry.0 < limit {
+            entry.1.add_permits(limit - entry.0);
+        } else if entry.0 > limit {
+            // The extra permits are taken out as running runs release them.
+            // The semaphore is fair, so requests after this wait behind it.
+            let sem = Arc::clone(&entry.1);
+            let excess = (entry.0 - limit) as u32;
+            tokio::spawn(async move {
+                if let Ok(permits) = sem.acquire_many_owned(excess).await {
+                    permits.forget()
+                }
+            });
+        }
+        entry.0 = limit;
+
+        Some(Arc::clone(&entry.1))
+    }
+
+    fn has_permit(&self, workflow_sem: Option<&Arc<Semaphore>>) -> bool {
+        self.global.available_permits() > 0
+            && workflow_sem
+                .map(|sem| sem.available_permits() > 0)
+                .unwrap_or(true)
+    }
+
+    /// Take a place in the wait queue if the request can not be admitted
+    /// right away, fails at once if the queue is full.
+    pub fn enqueue(
+        self: &Arc<Self>,
+        workflow: &str,
+        limit: Option<usize>,
+    ) -> Result<Ticket, AdmissionError> {
+        if self.closed.load(Ordering::Acquire) {
+            return Err(AdmissionError::Closed);
+        }
+        let workflow_sem = self.workflow_semaphore(workflow, limit);
+        let queued = !self.has_permit(workflow_sem.as_ref());
+        if queued {
+            let depth = self.stats.queued.fetch_add(1, Ordering::SeqCst);
+            if depth >= self.config.max_queue {
+                self.stats.queued.fetch_sub(1, Ordering::SeqCst);
+                self.stats.rejected_total.fetch_add(1, Ordering::Relaxed);
+                return Err(AdmissionError::QueueFull(depth));
+            }
+        }
+
+        Ok(Ticket {
+            admission: Arc::clone(self),
+            workflow_sem,
+            enqueued_at: Instant::now(),
+            queued,
+        })
+    }
+
+    /// Reject new requests and wake up waiting ones with
+    /// `AdmissionError::Closed`. Permits already granted stay valid.
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::Release);
+        self.global.close();
+        for (_, sem) in self.per_workflow.lock().unwrap().values() {
+            sem.close()
+        }
+    }
+
+    pub fn stats(&self) -> Value {
+        let stats = &self.stats;
+        json!({
+            "max_concurrency": self.config.max_concurrency,
+            "max_queue": self.config.max_queue,
+            "queue_depth": stats.queued.load(Ordering::SeqCst),
+            "running": stats.running.load(Ordering::SeqCst),
+            "admitted_total": stats.admitted_total.load(Ordering::Relaxed),
+            "rejected_total": stats.rejected_total.load(Ordering::Relaxed),
+            "timeout_total": stats.timeout_total.load(Ordering::Relaxed),
+            "wait_us_total": stats.wait_us_total.load(Ordering::Relaxed),
+            "wait_us_max": stats.wait_us_max.load(Ordering::Relaxed),
+        })
+    }
+}
+
+/// A request to run, with a place in the wait queue if it waits. It leaves
+/// the queue when dropped.
+pub struct Ticket {
+    admission: Arc<Admission>,
+    workflow_sem: Option<Arc<Semaphore>>,
+    enqueued_at: Instant,
+    /// Whether it is counted in the queue depth.
+    queued: bool,
+}
+
+impl Ticket {
+    fn try_admit(&self) -> Option<(OwnedSemaphorePermit, Option<OwnedSemaphorePermit>)> {
+        let workflow = match &self.workflow_sem {
+            Some(sem) => Some(Arc::clone(sem).try_acquire_owned().ok()?),
+            None => None,
+        };
+        let global = Arc::clone(&self.admission.global)
+            .try_acquire_owned()
+            .ok()?;
+        Some((global, workflow))
+    }
+
+    /// Wait until both the workflow and the global permit are granted.
+    pub async fn admit(mut self) -> Result<Permit, AdmissionError> {
+        let stats = &self.admission.stats;
+        if let Some((global, workflow)) = self.try_admit() {
+            return Ok(self.permit(global, workflow));
+        }
+        // Permits were taken since `enqueue`, it waits after all.
+        if !self.queued {
+            stats.queued.fetch_add(1, Ordering::SeqCst);
+            self.queued = true;
+        }
+
+        let timeout = self.admission.config.queue_timeout;
+        let acquire = async {
+            // Take the workflow permit first, so runs blocked by their
+            // workflow limit do not hold global permits.
+            let workflow = match &self.workflow_sem {
+                Some(sem) => Some(Arc::clone(sem).acquire_owned().await?),
+                None => None,
+            };
+            let global = Arc::clone(&self.admission.global).acquire_owned().await?;
+            Ok::<_, AcquireError>((global, workflow))
+        };
+
+        let (global, workflow) = tokio::time::timeout(timeout, acquire)
+            .await
+            .map_err(|_| {
+                stats.timeout_total.fetch_add(1, Ordering::Relaxed);
+                AdmissionError::Timeout(timeout)
+            })?
+            .map_err(|_| AdmissionError::Closed)?;
+
+        Ok(self.permit(global, workflow))
+    }
+
+    fn permit(
+        &self,
+        global: OwnedSemaphorePermit,
+        workflow: Option<OwnedSemaphorePermit>,
+    ) -> Permit {
+        let stats = &self.admission.stats;
+        let wait_us = self.enqueued_at.elapsed().as_micros() as u64;
+        stats.wait_us_total.fetch_add(wait_us, Ordering::Relaxed);
+        stats.wait_us_max.fetch_max(wait_us, Ordering::Relaxed);
+        stats.admitted_total.fetch_add(1, Ordering::Relaxed);
+        stats.running.fetch_add(1, Ordering::SeqCst);
+
+        Permit {
+            admission: Arc::clone(&self.admission),
+            _global: global,
+            _workflow: workflow,
+        }
+    }
+}
+
+impl Drop for Ticket {
+    fn drop(&mut self) {
+        if self.queued {
+            self.admission.stats.queued.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Permission to run, released when dropped.
+pub struct Permit {
+    admission: Arc<Admission>,
+    _global: OwnedSemaphorePermit,
+    _workflow: Option<OwnedSemaphorePermit>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.admission.stats.running.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+fn test_admission(max_concurrency: usize, max_queue: usize) -> Arc<Admission> {
+    Admission::new(AdmissionConfig {
+        max_concurrency,
+        max_per_workflow: None,
+        max_queue,
+        queue_timeout: Duration::from_secs(10),
+    })
+}
+
+#[cfg(test)]
+async fn is_admitted(admit: &mut tokio::task::JoinHandle<Result<Permit, AdmissionError>>) -> bool {
+    tokio::time::timeout(Duration::from_millis(50), admit)
+        .await
+        .is_ok()
+}
+
+#[tokio::test]
+async fn admission_queue_depth_test() {
+    let admission = test_admission(1, 1);
+
+    let running = admission
+        .enqueue("hello", None)
+        .unwrap()
+        .admit()
+        .await
+        .unwrap();
+    assert_eq!(admission.stats()["queue_depth"], 0);
+    assert_eq!(admission.stats()["running"], 1);
+
+    let waiting = admission.enqueue("hello", None).unwrap();
+    assert_eq!(admission.stats()["queue_depth"], 1);
+    let mut admit = tokio::spawn(waiting.admit());
+    assert!(!is_admitted(&mut admit).await);
+
+    let err = admission.enqueue("hello", None).err().unwrap();
+    assert!(matches!(err, AdmissionError::QueueFull(1)));
+    assert_eq!(
+        crate::AppError::from(err).0,
+        axum::http::StatusCode::TOO_MANY_REQUESTS
+    );
+    assert_eq!(admission.stats()["rejected_total"], 1);
+
+    drop(running);
+    let _admitted = admit.await.unwrap().unwrap();
+    assert_eq!(admission.stats()["queue_depth"], 0);
+    assert_eq!(admission.stats()["running"], 1);
+}
+
+#[tokio::test]
+async fn admission_limit_update_test() {
+    let admission = test_admission(8, 8);
+
+    let first = admission
+        .enqueue("hello", Some(2))
+        .unwrap()
+        .admit()
+        .await
+        .unwrap();
+    let second = admission
+        .enqueue("hello", Some(2))
+        .unwrap()
+        .admit()
+        .await
+        .unwrap();
+
+    // Both runs of the old version count against the new limit of 1.
+    let mut admit = tokio::spawn(admission.enqueue("hello", Some(1)).unwrap().admit());
+    assert!(!is_admitted(&mut admit).await);
+    drop(first);
+    assert!(!is_admitted(&mut admit).await);
+    drop(second);
+    let third = admit.await.unwrap().unwrap();
+
+    let mut admit = tokio::spawn(admission.enqueue("hello", Some(2)).unwrap().admit());
+    assert!(is_admitted(&mut admit).await);
+    drop(third);
+}
@@ -0,0 +1,182 @@
+//! JSON-lines control protocol on a Unix domain socket, for local tooling
+//! such as `asvisor ctl`. Every line is a request:
+//!
+//! ```json
+//! {"id": 1, "method": "submit", "params": {"isol_name": "map_reduce"}}
+//! ```
+//!
+//! and gets one line of response, `{"id": 1, "ok": true, "result": ...}` or
+//! `{"id": 1, "ok": false, "code": 404, "error": "..."}`, where `code` is
+//! the status the HTTP API would respond with. Methods are `submit`,
+//! `status`, `cancel` and `list`.
+
+use std::path::Path;
+
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+};
+
+use crate::{job::JobId, AppError, AppResult, AppState};
+
+#[derive(Deserialize)]
+struct Request {
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Deserialize)]
+struct JobParams {
+    id: JobId,
+}
+
+fn params<T: DeserializeOwned>(params: Value) -> AppResult<T> {
+    serde_json::from_value(params).map_err(|e| AppError::bad_request(e.into()))
+}
+
+fn dispatch(state: &AppState, method: &str, params_value: Value) -> AppResult<Value> {
+    match method {
+        "submit" => state.submit_job(params(params_value)?),
+        "status" => state.job_status(params::<JobParams>(params_value)?.id),
+        "cancel" => state.cancel_job(params::<JobParams>(params_value)?.id),
+        "list" => Ok(state.jobs.list()),
+        _ => Err(AppError::bad_request(anyhow::anyhow!(
+            "unknown method: {}",
+            method
+        ))),
+    }
+}
+
+fn handle_line(state: &AppState, line: &str) -> Value {
+    let request: Request = match serde_json::from_str(line) {
+        Ok(request) => request,
+        Err(e) => {
+            return json!({
+                "id": Value::Null,
+                "ok": false,
+                "code": 400,
+                "error": format!("invalid request: {}", e),
+            })
+        }
+    };
+
+    match dispatch(state, &request.method, request.params) {
+        Ok(result) => json!({ "id": request.id, "ok": true, "result": result }),
+        Err(AppError(code, error)) => json!({
+            "id": request.id,
+            "ok": false,
+            "code": code.as_u16(),
+            "error": error,
+        }),
+    }
+}
+
+async fn serve_connection(state: AppState, stream: UnixStream) -> std::io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut response = handle_line(&state, &line).to_string();
+        response.push('\n');
+        writer.write_all(response.as_bytes()).await?;
+    }
+
+    Ok(())
+}
+
+/// Bind `path` and serve the control protocol, a stale socket file left by
+/// a previous daemon is removed.
+pub async fn serve(state: AppState, path: &Path) -> std::io::Result<()> {
+    if path.exists() {
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    log::info!("control socket listening on: {}", path.display());
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let state = state.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve_connection(state, stream).await {
+                log::warn!("control connection failed: {}", e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+async fn round_trip(client: &mut BufReader<UnixStream>, request: &str) -> Value {
+    client.write_all(request.as_bytes()).await.unwrap();
+    let mut line = String::new();
+    client.read_line(&mut line).await.unwrap();
+    serde_json::from_str(&line).unwrap()
+}
+
+#[tokio::test]
+async fn control_round_trip_test() {
+    use std::{sync::Arc, time::Duration};
+
+    use crate::{
+        admission::{Admission, AdmissionConfig},
+        job::JobTable,
+        metrics::Metrics,
+        trigger::Triggers,
+    };
+
+    let metrics = Metrics::new();
+    let state = AppState {
+        jobs: JobTable::new(Arc::clone(&metrics), None),
+        registry: Default::default(),
+        admission: Admission::new(AdmissionConfig {
+            max_concurrency: 1,
+            max_per_workflow: None,
+            max_queue: 1,
+            queue_timeout: Duration::from_secs(1),
+        }),
+        metrics,
+        triggers: Triggers::new(),
+        output_dir: None,
+    };
+    let (client, server) = UnixStream::pair().unwrap();
+    let daemon = tokio::spawn(serve_connection(state, server));
+
+    let mut client = BufReader::new(client);
+
+    assert_eq!(
+        round_trip(&mut client, "{\"id\": 1, \"method\": \"list\"}\n").await,
+        json!({ "id": 1, "ok": true, "result": [] })
+    );
+    // Blank lines are skipped.
+    let response = round_trip(
+        &mut client,
+        "\n{\"id\": \"a\", \"method\": \"status\", \"params\": {\"id\": 7}}\n",
+    )
+    .await;
+    assert_eq!(response["id"], "a");
+    assert_eq!(response["ok"], false);
+    assert_eq!(response["code"], 404);
+
+    let response = round_trip(&mut client, "{\"id\": 2, \"method\": \"status\"}\n").await;
+    assert_eq!(response["code"], 400);
+    let response = round_trip(&mut client, "not json\n").await;
+    assert_eq!(response["id"], Value::Null);
+    assert_eq!(response["code"], 400);
+    assert!(response["error"]
+        .as_str()
+        .unwrap()
+        .starts_with("invalid request:"));
+    let response = round_trip(&mut client, "{\"id\": 3, \"method\": \"reboot\"}\n").await;
+    assert_eq!(response["id"], 3);
+    assert_eq!(response["code"], 400);
+    assert_eq!(response["error"], "unknown method: reboot");
+
+    drop(client);
+    daemon.await.unwrap().unwrap();
+}
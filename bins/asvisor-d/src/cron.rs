@@ -0,0 +1,246 @@
+//! A minimal parser of 5-field cron expressions
+//! (`minute hour day-of-month month day-of-week`), evaluated in UTC. Each
+//! field supports `*`, values, ranges `a-b`, steps `*/n` and `a-b/n`, and
+//! comma separated lists of them.
+
+use anyhow::anyhow;
+
+/// Give up searching for the next fire time after this many days.
+const MAX_SEARCH_DAYS: i64 = 5 * 366;
+
+#[derive(Debug, Clone)]
+pub struct CronExpr {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Standard cron semantic: if both day fields are restricted, a day
+    /// matches if either of them matches. Like other crons, a field starting
+    /// with `*` such as `*/2` is not restricted.
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+fn parse_field(field: &str, min: u32, max: u32) -> anyhow::Result<u64> {
+    let mut mask = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>()?),
+            None => (part, 1),
+        };
+        if step == 0 {
+            return Err(anyhow!("step of '{}' is zero", part));
+        }
+
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (start.parse()?, end.parse()?)
+        } else {
+            let value = range.parse()?;
+            // `a/n` means from `a` to the end of the field.
+            (value, if part.contains('/') { max } else { value })
+        };
+        if start < min || end > max || start > end {
+            return Err(anyhow!("'{}' out of range {}-{}", part, min, max));
+        }
+
+        for value in (start..=end).step_by(step as usize) {
+            mask |= 1 << value;
+        }
+    }
+
+    Ok(mask)
+}
+
+impl CronExpr {
+    pub fn parse(expr: &str) -> anyhow::Result<Self> {
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(anyhow!("cron expression '{}' should have 5 fields", expr));
+        };
+        let parse = |field: &str, min, max| {
+            parse_field(field, min, max).map_err(|e| anyhow!("invalid cron field '{field}': {e}"))
+        };
+
+        let mut weekdays = parse(weekday, 0, 7)?;
+        // Both 0 and 7 are Sunday.
+        if weekdays & (1 << 7) != 0 {
+            weekdays |= 1;
+        }
+
+        Ok(Self {
+            minutes: parse(minute, 0, 59)?,
+            hours: parse(hour, 0, 23)?,
+            days: parse(day, 1, 31)?,
+            months: parse(month, 1, 12)?,
+            weekdays,
+            days_restricted: !day.starts_with('*'),
+            weekdays_restricted: !weekday.starts_with('*'),
+        })
+    }
+
+    fn day_matches(&self, days_since_epoch: i64) -> bool {
+        let (_, month, day) = civil_from_days(days_since_epoch);
+        if self.months & (1 << month) == 0 {
+            return false;
+        }
+
+        // 1970-01-01 is a Thursday.
+        let weekday = (days_since_epoch + 4).rem_euclid(7);
+        let day_match = self.days & (1 << day) != 0;
+        let weekday_match = self.weekdays & (1 << weekday) != 0;
+        match (self.days_restricted, self.weekdays_restricted) {
+            (true, true) => day_match || weekday_match,
+            _ => day_match && weekday_match,
+        }
+    }
+
+    /// The first matching time strictly after `after`, both in unix seconds.
+    pub fn next_after(&self, after: u64) -> Option<u64> {
+        let start_minute = after as i64 / 60 + 1;
+        let start_day = start_minute / (24 * 60);
+
+        for day in start_day..start_day + MAX_SEARCH_DAYS {
+            if !self.day_matches(day) {
+                continue;
+            }
+            let first_minute = if day == start_day {
+                start_minute % (24 * 60)
+            } else {
+                0
+            };
+            for minute_of_day in first_minute..24 * 60 {
+                let (hour, minute) = (minute_of_day / 60, minute_of_day % 60);
+                if self.hours & (1 << hour) != 0 && self.minutes & (1 << minute) != 0 {
+                    return Some(((day * 24 * 60 + minute_of_day) * 60) as u64);
+                }
+            }
+        }
+
+        None
+    }
+}
+
+/// Convert days since 1970-01-01 to a (year, month, day) date in the
+/// proleptic Gregorian calendar.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+/// The next fire time of `expr` after `after` as (year, month, day, hour,
+/// minute).
+#[cfg(test)]
+fn next(expr: &str, after: u64) -> (i64, u32, u32, u64, u64) {
+    let unix = CronExpr::parse(expr).unwrap().next_after(after).unwrap();
+    let (year, month, day) = civil_from_days((unix / 86400) as i64);
+    (year, month, day, unix % 86400 / 3600, unix % 3600 / 60)
+}
+
+/// 2024-01-01T00:00:00Z, a Monday.
+#[cfg(test)]
+const JAN_1_2024: u64 = 1704067200;
+
+#[test]
+fn parse_field_test() {
+    let mask = |values: &[u32]| values.iter().fold(0u64, |mask, v| mask | 1 << v);
+    assert_eq!(parse_field("5", 0, 59).unwrap(), mask(&[5]));
+    assert_eq!(parse_field("1-3", 0, 59).unwrap(), mask(&[1, 2, 3]));
+    assert_eq!(parse_field("*/20", 0, 59).unwrap(), mask(&[0, 20, 40]));
+    assert_eq!(parse_field("10-20/5", 0, 59).unwrap(), mask(&[10, 15, 20]));
+    assert_eq!(parse_field("50/4", 0, 59).unwrap(), mask(&[50, 54, 58]));
+    assert_eq!(
+        parse_field("1,3-4,*/30", 0, 59).unwrap(),
+        mask(&[0, 1, 3, 4, 30])
+    );
+
+    assert!(parse_field("*/0", 0, 59).is_err());
+    assert!(parse_field("60", 0, 59).is_err());
+    assert!(parse_field("0", 1, 31).is_err());
+    assert!(parse_field("5-3", 0, 59).is_err());
+    assert!(parse_field("a", 0, 59).is_err());
+    assert!(CronExpr::parse("* * * *").is_err());
+}
+
+#[test]
+fn cron_next_after_test() {
+    // Strictly after.
+    assert_eq!(next("* * * * *", JAN_1_2024), (2024, 1, 1, 0, 1));
+    assert_eq!(next("*/15 * * * *", JAN_1_2024 + 59), (2024, 1, 1, 0, 15));
+    assert_eq!(next("0 9-17/4 * * *", JAN_1_2024), (2024, 1, 1, 9, 0));
+    assert_eq!(
+        next("0 9-17/4 * * *", JAN_1_2024 + 9 * 3600),
+        (2024, 1, 1, 13, 0)
+    );
+    assert_eq!(
+        next("30 8,20 * * *", JAN_1_2024 + 9 * 3600),
+        (2024, 1, 1, 20, 30)
+    );
+}
+
+#[test]
+fn cron_rollover_test() {
+    // Into the next day, month and year.
+    assert_eq!(next("0 0 * * *", JAN_1_2024 + 60), (2024, 1, 2, 0, 0));
+    assert_eq!(next("0 0 1 * *", JAN_1_2024), (2024, 2, 1, 0, 0));
+    assert_eq!(next("0 0 1 1 *", JAN_1_2024), (2025, 1, 1, 0, 0));
+    // Months without the 31st are skipped.
+    assert_eq!(
+        next("0 0 31 * *", JAN_1_2024 + 31 * 86400),
+        (2024, 3, 31, 0, 0)
+    );
+    // Never matches.
+    assert_eq!(
+        CronExpr::parse("0 0 30 2 *")
+            .unwrap()
+            .next_after(JAN_1_2024),
+        None
+    );
+}
+
+#[test]
+fn cron_leap_year_test() {
+    assert_eq!(next("0 12 29 2 *", JAN_1_2024), (2024, 2, 29, 12, 0));
+    assert_eq!(
+        next("0 12 29 2 *", JAN_1_2024 + 60 * 86400),
+        (2028, 2, 29, 12, 0)
+    );
+    // 2024-02-28 is followed by the 29th, 2023-02-28 is not.
+    assert_eq!(
+        next("0 0 * 2-3 *", JAN_1_2024 + 58 * 86400),
+        (2024, 2, 29, 0, 0)
+    );
+    assert_eq!(
+        next("0 0 * 2-3 *", JAN_1_2024 - 307 * 86400),
+        (2023, 3, 1, 0, 0)
+    );
+}
+
+#[test]
+fn cron_weekday_test() {
+    assert_eq!(next("0 0 * * 5", JAN_1_2024), (2024, 1, 5, 0, 0));
+    // Both 0 and 7 are Sunday.
+    assert_eq!(next("0 0 * * 0", JAN_1_2024), (2024, 1, 7, 0, 0));
+    assert_eq!(next("0 0 * * 7", JAN_1_2024), (2024, 1, 7, 0, 0));
+    assert_eq!(next("0 0 * * 6-7", JAN_1_2024), (2024, 1, 6, 0, 0));
+    // Either the 3rd or a Friday if both day fields are restricted.
+    assert_eq!(next("0 0 3 * 5", JAN_1_2024), (2024, 1, 3, 0, 0));
+    assert_eq!(next("0 0 13 * 5", JAN_1_2024), (2024, 1, 5, 0, 0));
+    // A day field starting with `*` is not restricted, so both must match:
+    // the first Monday on the 1st, 11th, 21st or 31st after 2024-01-01.
+    assert_eq!(next("0 0 */10 * 1", JAN_1_2024), (2024, 3, 11, 0, 0));
+    // The first 1st on a Sunday, Wednesday or Saturday.
+    assert_eq!(next("0 0 1 * */3", JAN_1_2024), (2024, 5, 1, 0, 0));
+}
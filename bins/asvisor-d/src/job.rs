@@ -0,0 +1,325 @@
+//! In-memory table of asynchronous workflow jobs. A job owns the isolation
+//! while it runs, and keeps its result until the TTL expires.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use libasvisor::{
+    isolation::{output::OutputLine, Isolation},
+    logger, now_microsec, MetricBucket, MetricOpt,
+};
+use serde::Serialize;
+use serde_json::{json, Value};
+use tokio::{sync::Notify, task::JoinHandle};
+
+use crate::{
+    admission::Ticket,
+    metrics::Metrics,
+    output::{OutputReceiver, RunOutput},
+    registry::WorkflowDef,
+};
+
+pub type JobId = u64;
+
+/// How long a finished job is kept before being evicted.
+pub const JOB_TTL: Duration = Duration::from_secs(10 * 60);
+const REAP_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+    Pending,
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+impl JobState {
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self,
+            JobState::Succeeded | JobState::Failed | JobState::Cancelled
+        )
+    }
+}
+
+pub struct Job {
+    pub id: JobId,
+    pub workflow: Arc<WorkflowDef>,
+    pub state: JobState,
+    args: BTreeMap<String, String>,
+    submitted_at: u128,
+    finished_at: Option<Instant>,
+    isol: Option<Arc<Isolation>>,
+    metric: Option<Arc<MetricBucket>>,
+    cancel_requested: bool,
+    cancel: Arc<Notify>,
+    error: Option<String>,
+    metrics: Option<Value>,
+    pub output: Arc<RunOutput>,
+}
+
+impl Job {
+    pub fn status(&self) -> Value {
+        json!({
+            "id": self.id,
+            "isol_name": &self.workflow.name,
+            "version": self.workflow.version,
+            "state": self.state,
+            "args": &self.args,
+            "submitted_at(us)": self.submitted_at,
+            "progress": self.metric.as_ref().map(|metric| metric.progress()),
+        })
+    }
+
+    /// `None` if the job has not finished yet.
+    pub fn result(&self) -> Option<Value> {
+        if !self.state.is_finished() {
+            return None;
+        }
+
+        Some(json!({
+            "id": self.id,
+            "state": self.state,
+            "error": &self.error,
+            "metrics": &self.metrics,
+            "output": self.output.text(None),
+            "output_truncated": self.output.truncated(),
+            "output_log": self.output.log_path(),
+        }))
+    }
+
+    fn finish(&mut self, state: JobState, error: Option<String>) {
+        if let Some(err_msg) = &error {
+            logger::error!("job{} {}", self.id, err_msg);
+        }
+        self.state = state;
+        self.error = error;
+        self.finished_at = Some(Instant::now());
+        self.isol = None;
+        self.output.close();
+    }
+
+    /// Output captured so far, and a receiver of later lines if the job has
+    /// not finished.
+    pub fn follow_output(&self) -> (Vec<OutputLine>, Option<OutputReceiver>) {
+        if self.state.is_finished() {
+            let lines = self.output.capture().with_lines(|lines| lines.to_vec());
+            return (lines, None);
+        }
+
+        let (lines, rx) = self.output.follow();
+        (lines, Some(rx))
+    }
+
+    fn expired(&self, ttl: Duration) -> bool {
+        self.finished_at
+            .map(|finished| finished.elapsed() > ttl)
+            .unwrap_or(false)
+    }
+}
+
+pub struct JobTable {
+    jobs: Mutex<HashMap<JobId, Job>>,
+    next_id: AtomicU64,
+    metrics: Arc<Metrics>,
+    /// Directory of per-job output log files.
+    output_dir: Option<PathBuf>,
+    /// Notified after a job is updated.
+    updated: Notify,
+}
+
+impl JobTable {
+    pub fn new(metrics: Arc<Metrics>, output_dir: Option<PathBuf>) -> Arc<Self> {
+        Arc::new(Self {
+            jobs: Default::default(),
+            next_id: AtomicU64::new(1),
+            metrics,
+            output_dir,
+            updated: Notify::new(),
+        })
+    }
+
+    /// Queue `workflow` with an admission `ticket`, and run it in background
+    /// once admitted. The isolation is only created after admission. `args`
+    /// are passed to every app, see `IsolationConfig::extend_args`. The
+    /// returned handle completes when the job finished.
+    pub fn submit(
+        self: &Arc<Self>,
+        workflow: Arc<WorkflowDef>,
+        args: BTreeMap<String, String>,
+        ticket: Ticket,
+    ) -> anyhow::Result<(JobId, JoinHandle<()>)> {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let cancel = Arc::new(Notify::new());
+        let output = Arc::new(RunOutput::new(
+            self.output_dir.as_deref(),
+            &format!("job-{}.log", id),
+        )?);
+
+        self.jobs.lock().unwrap().insert(
+            id,
+            Job {
+                id,
+                workflow: Arc::clone(&workflow),
+                state: JobState::Pending,
+                args: args.clone(),
+                submitted_at: now_microsec!(),
+                finished_at: None,
+                isol: None,
+                metric: None,
+                cancel_requested: false,
+                cancel: Arc::clone(&cancel),
+                error: None,
+                metrics: None,
+                output: Arc::clone(&output),
+            },
+        );
+
+        let mut config = workflow.config.clone();
+        config.extend_args(&args);
+        let has_args = !args.is_empty();
+
+        let table = Arc::clone(self);
+        let handle = tokio::spawn(async move {
+            let permit = tokio::select! {
+                permit = ticket.admit() => permit,
+                _ = cancel.notified() => {
+                    table.update(id, |job| job.finish(JobState::Cancelled, None));
+                    return;
+                }
+            };
+            let _permit = match permit {
+                Ok(permit) => permit,
+                Err(e) => {
+                    table.update(id, |job| job.finish(JobState::Failed, Some(e.to_string())));
+                    return;
+                }
+            };
+
+            let (isol, loaded) = workflow.isolation(&config, has_args);
+            isol.capture_output(output.capture())
+                .expect("new isolation already captures output?");
+            table.update(id, |job| {
+                if job.cancel_requested {
+                    isol.cancel()
+                }
+                job.state = JobState::Running;
+                job.isol = Some(Arc::clone(&isol));
+                job.metric = Some(Arc::clone(&isol.metric));
+            });
+
+            let workflow_name = workflow.name.clone();
+            let run_isol = Arc::clone(&isol);
+            let result = tokio::task::spawn_blocking(move || {
+                workflow.prepare(&run_isol, &config, loaded)?;
+                run_isol.run()
+            })
+            .await
+            .map_err(|e| anyhow::anyhow!("join isolation thread failed: {e}"))
+            .and_then(|result| result);
+
+            let metrics = isol.metric.to_json(&MetricOpt::All);
+            let cancelled = isol.is_cancelled();
+            table
+                .metrics
+                .observe(&workflow_name, result.is_ok(), &isol.metric.snapshot());
+            drop(isol);
+
+            table.update(id, |job| {
+                job.metrics = metrics;
+                // A cancel that arrives after the last app finished does not
+                // stop the run, so the job still succeeded.
+                match (result, cancelled) {
+                    (Ok(()), _) => job.finish(JobState::Succeeded, None),
+                    (Err(_), true) => job.finish(JobState::Cancelled, None),
+                    (Err(e), false) => job.finish(
+                        JobState::Failed,
+                        Some(format!("isolation user function error: {}", e)),
+                    ),
+                }
+            });
+        });
+
+        Ok((id, handle))
+    }
+
+    fn update<F: FnOnce(&mut Job)>(&self, id: JobId, f: F) {
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(&id) {
+            f(job)
+        }
+        self.updated.notify_waiters()
+    }
+
+    /// Wait until every job has finished. A job finishes after its isolation
+    /// is dropped.
+    pub async fn wait_all_finished(&self) {
+        loop {
+            let updated = self.updated.notified();
+            let unfinished = self
+                .jobs
+                .lock()
+                .unwrap()
+                .values()
+                .filter(|job| !job.state.is_finished())
+                .count();
+            if unfinished == 0 {
+                return;
+            }
+            logger::info!("wait for {} unfinished jobs", unfinished);
+            updated.await
+        }
+    }
+
+    pub fn with_job<T, F: FnOnce(&Job) -> T>(&self, id: JobId, f: F) -> Option<T> {
+        self.jobs.lock().unwrap().get(&id).map(f)
+    }
+
+    /// Status of all jobs that have not been evicted, ordered by id.
+    pub fn list(&self) -> Value {
+        let jobs = self.jobs.lock().unwrap();
+        let mut list: Vec<&Job> = jobs.values().collect();
+        list.sort_by_key(|job| job.id);
+
+        json!(list.iter().map(|job| job.status()).collect::<Vec<_>>())
+    }
+
+    /// Returns the state when cancellation is requested, or `None` if the job
+    /// does not exist.
+    pub fn cancel(&self, id: JobId) -> Option<JobState> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let job = jobs.get_mut(&id)?;
+        if !job.state.is_finished() {
+            job.cancel_requested = true;
+            job.cancel.notify_one();
+            if let Some(isol) = &job.isol {
+                isol.cancel()
+            }
+        }
+
+        Some(job.state)
+    }
+
+    /// Periodically evict finished jobs older than `ttl`.
+    pub async fn reap_expired(self: Arc<Self>, ttl: Duration) {
+        let mut interval = tokio::time::interval(REAP_INTERVAL);
+        loop {
+            interval.tick().await;
+            self.jobs.lock().unwrap().retain(|id, job| {
+                let expired = job.expired(ttl);
+                if expired {
+                    logger::debug!("job{} expired, evict it", id);
+                }
+                !expired
+            });
+        }
+    }
+}
@@ -1,71 +1,634 @@
-use std::time::SystemTime;
+mod admission;
+mod control;
+mod cron;
+mod job;
+mod metrics;
+mod output;
+mod registry;
+mod shutdown;
+mod trigger;
+mod watch;
 
-use axum::{extract::Query, response::IntoResponse, routing::get, Router};
+use std::{
+    collections::BTreeMap,
+    convert::Infallible,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use admission::{Admission, AdmissionConfig, AdmissionError};
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{
+        sse::{Event, Sse},
+        IntoResponse,
+    },
+    routing::get,
+    Json, Router,
+};
+use clap::Parser;
+use futures_util::{stream, Stream, StreamExt};
+use job::{JobId, JobTable, JOB_TTL};
 use libasvisor::{
-    isolation::{config::IsolationConfig, Isolation},
+    isolation::{
+        self,
+        output::{OutputLine, Stream as OutputStream},
+    },
     logger,
 };
+use metrics::Metrics;
+use output::RunOutput;
+use registry::{Registry, Version, WorkflowDef, WorkflowOpts};
 use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::{broadcast::error::RecvError, Notify};
+use trigger::{TriggerId, TriggerSpec, Triggers};
 
 type AppResult<T> = Result<T, AppError>;
-struct AppError(String);
+struct AppError(StatusCode, String);
+
+impl AppError {
+    fn internal(msg: String) -> Self {
+        AppError(StatusCode::INTERNAL_SERVER_ERROR, msg)
+    }
+
+    fn job_not_found(id: JobId) -> Self {
+        AppError(StatusCode::NOT_FOUND, format!("job {} not found", id))
+    }
+
+    fn workflow_not_found(name: &str) -> Self {
+        AppError(
+            StatusCode::NOT_FOUND,
+            format!("workflow {} not found", name),
+        )
+    }
+
+    fn trigger_not_found(id: TriggerId) -> Self {
+        AppError(StatusCode::NOT_FOUND, format!("trigger {} not found", id))
+    }
+
+    fn bad_request(e: anyhow::Error) -> Self {
+        AppError(StatusCode::BAD_REQUEST, e.to_string())
+    }
+}
 
 impl IntoResponse for AppError {
     fn into_response(self) -> axum::response::Response {
-        self.0.into_response()
+        (self.0, self.1).into_response()
+    }
+}
+impl From<AdmissionError> for AppError {
+    fn from(value: AdmissionError) -> Self {
+        let status = match value {
+            AdmissionError::Closed => StatusCode::SERVICE_UNAVAILABLE,
+            _ => StatusCode::TOO_MANY_REQUESTS,
+        };
+        AppError(status, value.to_string())
     }
 }
 impl From<anyhow::Error> for AppError {
     fn from(value: anyhow::Error) -> Self {
-        AppError(value.to_string())
+        AppError::internal(value.to_string())
+    }
+}
+
+#[derive(Clone)]
+struct AppState {
+    jobs: Arc<JobTable>,
+    registry: Arc<Registry>,
+    admission: Arc<Admission>,
+    metrics: Arc<Metrics>,
+    triggers: Arc<Triggers>,
+    output_dir: Option<PathBuf>,
+}
+
+impl AppState {
+    /// Registered workflows take precedence over files in `isol_config/`.
+    fn workflow(&self, isol_name: String, version: Option<Version>) -> AppResult<Arc<WorkflowDef>> {
+        if let Some(def) = self.registry.get(&isol_name, version) {
+            return Ok(def);
+        }
+        if version.is_some() {
+            return Err(AppError::workflow_not_found(&isol_name));
+        }
+
+        WorkflowDef::from_file(isol_name)
+            .map(Arc::new)
+            .map_err(|e| {
+                AppError(
+                    StatusCode::BAD_REQUEST,
+                    format!("load config file failed: {}", e),
+                )
+            })
+    }
+
+    fn submit_job(&self, req: SubmitJobReq) -> AppResult<Value> {
+        log::info!("submit job: isol_name={}", req.isol_name);
+        let workflow = self.workflow(req.isol_name, req.version)?;
+        let version = workflow.version;
+        let ticket = self
+            .admission
+            .enqueue(&workflow.name, workflow.max_concurrency)?;
+        let (id, _) = self.jobs.submit(workflow, req.args, ticket)?;
+
+        Ok(json!({ "id": id, "version": version }))
+    }
+
+    fn job_status(&self, id: JobId) -> AppResult<Value> {
+        self.jobs
+            .with_job(id, |job| job.status())
+            .ok_or(AppError::job_not_found(id))
+    }
+
+    fn cancel_job(&self, id: JobId) -> AppResult<Value> {
+        let job_state = self.jobs.cancel(id).ok_or(AppError::job_not_found(id))?;
+        Ok(json!({ "id": id, "state": job_state }))
+    }
+
+    /// Stop accepting work. Requests waiting for admission are rejected,
+    /// and triggers are removed so they fire no more runs.
+    fn stop_accepting(&self) {
+        self.admission.close();
+        self.triggers.clear();
     }
 }
 
 #[derive(Deserialize)]
 struct TrigeWorkflowReq {
     isol_name: String,
+    version: Option<Version>,
 }
 
 async fn trige_workflow_handler(
-    Query(TrigeWorkflowReq { mut isol_name }): Query<TrigeWorkflowReq>,
+    State(state): State<AppState>,
+    Query(TrigeWorkflowReq { isol_name, version }): Query<TrigeWorkflowReq>,
 ) -> AppResult<String> {
     log::info!("trige_workflow_handler: isol_name={}", isol_name);
-    if !isol_name.ends_with(".json") {
-        isol_name += ".json"
-    };
-    let config = IsolationConfig::from_file(isol_name.into())
-        .map_err(|e| AppError(format!("load config file failed: {}", e)))?;
-
-    // info!("preload?:{}", args.preload);
-    let isol = Isolation::new(&config);
-    tokio::task::spawn_blocking(move || isol.run())
-        .await
-        .unwrap()
-        .map_err(|e| {
-            let err_msg = format!("isolation user function error: {}", e);
-            logger::error!("{}", err_msg);
-            AppError(err_msg)
-        })?;
+    let workflow = state.workflow(isol_name, version)?;
+    let _permit = state
+        .admission
+        .enqueue(&workflow.name, workflow.max_concurrency)?
+        .admit()
+        .await?;
+
+    let (isol, loaded) = workflow.isolation(&workflow.config, false);
+    // Synchronous runs are only captured into log files, otherwise their
+    // output goes to the daemon's stdout as before.
+    if let Some(dir) = &state.output_dir {
+        let output = RunOutput::new(Some(dir), &format!("{}-isol{}.log", workflow.name, isol.id))?;
+        isol.capture_output(output.capture())?;
+    }
+    let run_isol = Arc::clone(&isol);
+    let run_workflow = Arc::clone(&workflow);
+    let result = tokio::task::spawn_blocking(move || {
+        run_workflow.prepare(&run_isol, &run_workflow.config, loaded)?;
+        run_isol.run()
+    })
+    .await
+    .unwrap();
+    state
+        .metrics
+        .observe(&workflow.name, result.is_ok(), &isol.metric.snapshot());
+
+    result.map_err(|e| {
+        let err_msg = format!("isolation user function error: {}", e);
+        logger::error!("{}", err_msg);
+        AppError::internal(err_msg)
+    })?;
 
     Ok("ok".to_owned())
 }
 
-#[tokio::main]
-async fn main() -> std::io::Result<()> {
+#[derive(Deserialize)]
+struct SubmitJobReq {
+    isol_name: String,
+    version: Option<Version>,
+    /// Passed to every app of the workflow.
+    #[serde(default)]
+    args: BTreeMap<String, String>,
+}
+
+async fn submit_job_handler(
+    State(state): State<AppState>,
+    Json(req): Json<SubmitJobReq>,
+) -> AppResult<(StatusCode, Json<Value>)> {
+    Ok((StatusCode::ACCEPTED, Json(state.submit_job(req)?)))
+}
+
+async fn list_jobs_handler(State(state): State<AppState>) -> Json<Value> {
+    Json(state.jobs.list())
+}
+
+async fn job_status_handler(
+    State(state): State<AppState>,
+    Path(id): Path<JobId>,
+) -> AppResult<Json<Value>> {
+    state.job_status(id).map(Json)
+}
+
+async fn job_result_handler(
+    State(state): State<AppState>,
+    Path(id): Path<JobId>,
+) -> AppResult<Json<Value>> {
+    let (job_state, result) = state
+        .jobs
+        .with_job(id, |job| (job.state, job.result()))
+        .ok_or(AppError::job_not_found(id))?;
+
+    result.map(Json).ok_or(AppError(
+        StatusCode::CONFLICT,
+        format!(
+            "job {} has not finished, state={}",
+            id,
+            json!(job_state).as_str().unwrap_or_default()
+        ),
+    ))
+}
+
+#[derive(Deserialize)]
+struct JobOutputQuery {
+    stream: Option<OutputStream>,
+}
+
+/// Captured output of a job, with an `x-output-truncated` header telling
+/// whether lines were dropped from it.
+async fn job_output_handler(
+    State(state): State<AppState>,
+    Path(id): Path<JobId>,
+    Query(JobOutputQuery { stream }): Query<JobOutputQuery>,
+) -> AppResult<([(&'static str, String); 1], String)> {
+    let (truncated, text) = state
+        .jobs
+        .with_job(id, |job| (job.output.truncated(), job.output.text(stream)))
+        .ok_or(AppError::job_not_found(id))?;
+    Ok(([("x-output-truncated", truncated.to_string())], text))
+}
+
+fn output_event(line: &OutputLine) -> Event {
+    Event::default().event("output").json_data(line).unwrap()
+}
+
+/// Server-sent events of the output of a job, an `output` event per line
+/// and an `end` event once the job finished, whose data tells whether lines
+/// were dropped from the capture.
+async fn job_output_stream_handler(
+    State(state): State<AppState>,
+    Path(id): Path<JobId>,
+) -> AppResult<Sse<impl Stream<Item = Result<Event, Infallible>>>> {
+    let (lines, rx, capture) = state
+        .jobs
+        .with_job(id, |job| {
+            let (lines, rx) = job.follow_output();
+            (lines, rx, job.output.capture())
+        })
+        .ok_or(AppError::job_not_found(id))?;
+
+    let backlog = stream::iter(lines.iter().map(output_event).collect::<Vec<_>>());
+    let live = stream::unfold(rx, move |rx| async move {
+        let mut rx = rx?;
+        let event = match rx.recv().await {
+            Ok(Some(line)) => output_event(&line),
+            Ok(None) | Err(RecvError::Closed) => return None,
+            Err(RecvError::Lagged(n)) => Event::default().event("lagged").data(n.to_string()),
+        };
+        Some((event, Some(rx)))
+    });
+    let end = stream::once(async move {
+        Event::default()
+            .event("end")
+            .json_data(json!({ "truncated": capture.truncated() }))
+            .unwrap()
+    });
+
+    Ok(Sse::new(backlog.chain(live).chain(end).map(Ok)))
+}
+
+async fn cancel_job_handler(
+    State(state): State<AppState>,
+    Path(id): Path<JobId>,
+) -> AppResult<(StatusCode, Json<Value>)> {
+    Ok((StatusCode::ACCEPTED, Json(state.cancel_job(id)?)))
+}
+
+#[derive(Deserialize)]
+struct RegisterWorkflowReq {
+    name: String,
+    config: Value,
+    #[serde(flatten)]
+    opts: WorkflowOpts,
+}
+
+#[derive(Deserialize)]
+struct UpdateWorkflowReq {
+    config: Value,
+    #[serde(flatten)]
+    opts: WorkflowOpts,
+}
+
+#[derive(Deserialize)]
+struct WorkflowVersionQuery {
+    version: Option<Version>,
+}
+
+async fn register_workflow_handler(
+    State(state): State<AppState>,
+    Json(req): Json<RegisterWorkflowReq>,
+) -> AppResult<(StatusCode, Json<Value>)> {
+    let registry = Arc::clone(&state.registry);
+    let name = req.name.clone();
+    let version =
+        tokio::task::spawn_blocking(move || registry.put(&name, req.config, req.opts, false))
+            .await
+            .unwrap()
+            .map_err(AppError::bad_request)?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(json!({ "name": req.name, "version": version })),
+    ))
+}
+
+async fn update_workflow_handler(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Json(req): Json<UpdateWorkflowReq>,
+) -> AppResult<Json<Value>> {
+    if state.registry.get(&name, None).is_none() {
+        return Err(AppError::workflow_not_found(&name));
+    }
+    let registry = Arc::clone(&state.registry);
+    let put_name = name.clone();
+    let version =
+        tokio::task::spawn_blocking(move || registry.put(&put_name, req.config, req.opts, true))
+            .await
+            .unwrap()
+            .map_err(AppError::bad_request)?;
+
+    Ok(Json(json!({ "name": name, "version": version })))
+}
+
+async fn list_workflows_handler(State(state): State<AppState>) -> Json<Value> {
+    Json(state.registry.list())
+}
+
+async fn get_workflow_handler(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Query(WorkflowVersionQuery { version }): Query<WorkflowVersionQuery>,
+) -> AppResult<Json<Value>> {
+    state
+        .registry
+        .describe(&name, version)
+        .map(Json)
+        .ok_or(AppError::workflow_not_found(&name))
+}
+
+async fn delete_workflow_handler(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> AppResult<StatusCode> {
+    if state.registry.remove(&name) {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(AppError::workflow_not_found(&name))
+    }
+}
+
+async fn add_trigger_handler(
+    State(state): State<AppState>,
+    Json(spec): Json<TriggerSpec>,
+) -> AppResult<(StatusCode, Json<Value>)> {
+    let id = state
+        .triggers
+        .add(state.clone(), spec)
+        .map_err(AppError::bad_request)?;
+
+    Ok((StatusCode::CREATED, Json(json!({ "id": id }))))
+}
+
+async fn list_triggers_handler(State(state): State<AppState>) -> Json<Value> {
+    Json(state.triggers.list())
+}
+
+async fn get_trigger_handler(
+    State(state): State<AppState>,
+    Path(id): Path<TriggerId>,
+) -> AppResult<Json<Value>> {
+    state
+        .triggers
+        .describe(id)
+        .map(Json)
+        .ok_or(AppError::trigger_not_found(id))
+}
+
+async fn delete_trigger_handler(
+    State(state): State<AppState>,
+    Path(id): Path<TriggerId>,
+) -> AppResult<StatusCode> {
+    if state.triggers.remove(id) {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(AppError::trigger_not_found(id))
+    }
+}
+
+async fn admission_stats_handler(State(state): State<AppState>) -> Json<Value> {
+    Json(state.admission.stats())
+}
+
+async fn metrics_handler(State(state): State<AppState>) -> impl IntoResponse {
+    (
+        [(
+            axum::http::header::CONTENT_TYPE,
+            "text/plain; version=0.0.4; charset=utf-8",
+        )],
+        state.metrics.render(&state.admission.stats()),
+    )
+}
+
+/// The daemon of AlloyStack, runs workflows on HTTP requests.
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Address of the HTTP API.
+    #[arg(long, default_value = "0.0.0.0:8000")]
+    listen: SocketAddr,
+
+    /// Also serve the JSON-lines control protocol on this Unix domain
+    /// socket, `asvisor ctl` uses /tmp/asvisor-d.sock by default.
+    #[arg(long)]
+    control_socket: Option<PathBuf>,
+
+    /// Max workflows running at the same time, defaults to the number of CPUs.
+    #[arg(long)]
+    max_concurrency: Option<usize>,
+
+    /// Max running instances of one workflow, unlimited if not set.
+    #[arg(long)]
+    max_per_workflow: Option<usize>,
+
+    /// Max requests waiting for admission.
+    #[arg(long, default_value_t = 64)]
+    max_queue: usize,
+
+    /// Max time a request waits for admission, in milliseconds.
+    #[arg(long, default_value_t = 10_000)]
+    queue_timeout_ms: u64,
+
+    /// Also write captured stdout and stderr of every run to a log file in
+    /// this directory.
+    #[arg(long)]
+    output_dir: Option<PathBuf>,
+
+    /// Seconds to wait for running workflows on SIGINT or SIGTERM, the
+    /// daemon exits without cleaning them up after that.
+    #[arg(long, default_value_t = 30)]
+    shutdown_timeout_secs: u64,
+}
+
+fn main() -> std::io::Result<()> {
     logger::init();
+    let args = Args::parse();
+    let signal = shutdown::listen()?;
+
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?
+        .block_on(serve(args, signal))
+}
+
+async fn serve(
+    args: Args,
+    signal: tokio::sync::oneshot::Receiver<nix::sys::signal::Signal>,
+) -> std::io::Result<()> {
     let start = SystemTime::now();
 
-    let app = Router::new().route("/workflow", get(trige_workflow_handler));
+    let max_concurrency = args.max_concurrency.unwrap_or_else(|| {
+        std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+    });
+    if let Some(dir) = &args.output_dir {
+        std::fs::create_dir_all(dir)?;
+    }
+    let metrics = Metrics::new();
+    let state = AppState {
+        jobs: JobTable::new(Arc::clone(&metrics), args.output_dir.clone()),
+        registry: Default::default(),
+        admission: Admission::new(AdmissionConfig {
+            max_concurrency,
+            max_per_workflow: args.max_per_workflow,
+            max_queue: args.max_queue,
+            queue_timeout: Duration::from_millis(args.queue_timeout_ms),
+        }),
+        metrics,
+        triggers: Triggers::new(),
+        output_dir: args.output_dir,
+    };
+    tokio::spawn(Arc::clone(&state.jobs).reap_expired(JOB_TTL));
+    let control = args.control_socket.map(|path| {
+        let state = state.clone();
+        let serve_path = path.clone();
+        let task = tokio::spawn(async move {
+            if let Err(e) = control::serve(state, &serve_path).await {
+                logger::error!("control socket {} failed: {}", serve_path.display(), e);
+            }
+        });
+        (path, task)
+    });
+
+    let app = Router::new()
+        .route("/workflow", get(trige_workflow_handler))
+        .route("/jobs", get(list_jobs_handler).post(submit_job_handler))
+        .route(
+            "/jobs/:id",
+            get(job_status_handler).delete(cancel_job_handler),
+        )
+        .route("/jobs/:id/result", get(job_result_handler))
+        .route("/jobs/:id/output", get(job_output_handler))
+        .route("/jobs/:id/output/stream", get(job_output_stream_handler))
+        .route(
+            "/triggers",
+            get(list_triggers_handler).post(add_trigger_handler),
+        )
+        .route(
+            "/triggers/:id",
+            get(get_trigger_handler).delete(delete_trigger_handler),
+        )
+        .route("/admission", get(admission_stats_handler))
+        .route("/metrics", get(metrics_handler))
+        .route(
+            "/workflows",
+            get(list_workflows_handler).post(register_workflow_handler),
+        )
+        .route(
+            "/workflows/:name",
+            get(get_workflow_handler)
+                .put(update_workflow_handler)
+                .delete(delete_workflow_handler),
+        )
+        .with_state(state.clone());
 
-    let addr = "0.0.0.0:8000";
-    let server = axum::Server::bind(&addr.parse().unwrap()).serve(app.into_make_service());
+    let addr = args.listen;
+    let stop_server = Arc::new(Notify::new());
+    let server_stopped = Arc::clone(&stop_server);
+    let server = axum::Server::try_bind(&addr)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::AddrInUse, e))?
+        .serve(app.into_make_service())
+        .with_graceful_shutdown(async move { server_stopped.notified().await });
 
     log::info!(
         "listenning on: {}, init time: {}us",
         addr,
         SystemTime::now().duration_since(start).unwrap().as_micros()
     );
-    server.await.unwrap();
+    let mut server = tokio::spawn(server);
+    let signal = tokio::select! {
+        result = &mut server => {
+            result
+                .expect("server task panicked")
+                .map_err(std::io::Error::other)?;
+            return Ok(());
+        }
+        signal = signal => signal.expect("signal thread exited"),
+    };
+
+    let timeout = Duration::from_secs(args.shutdown_timeout_secs);
+    logger::warn!(
+        "received {}, shutting down, wait at most {:?}",
+        signal,
+        timeout
+    );
+    state.stop_accepting();
+    stop_server.notify_one();
+    if let Some((path, task)) = control {
+        task.abort();
+        if let Err(e) = std::fs::remove_file(&path) {
+            logger::warn!("remove control socket {} failed: {}", path.display(), e);
+        }
+    }
+
+    // In-flight requests of the HTTP API include synchronous runs, and jobs
+    // drop their isolations before they finish, which runs the `drop`
+    // symbols of services.
+    let drain = async {
+        let _ = server.await;
+        state.jobs.wait_all_finished().await;
+    };
+    if tokio::time::timeout(timeout, drain).await.is_err() {
+        let cancelled = isolation::cancel_all();
+        logger::error!(
+            "isolations {:?} still running after {:?}, exit without cleanup",
+            cancelled,
+            timeout
+        );
+        std::process::exit(128 + signal as i32)
+    }
+    logger::info!("shutdown complete");
 
     Ok(())
 }
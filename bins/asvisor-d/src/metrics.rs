@@ -0,0 +1,530 @@
+//! Runtime metrics of the daemon in the Prometheus text exposition format.
+//! Every finished isolation is folded into the counters and histograms here,
+//! and gauges are read when `/metrics` is scraped.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{Arc, Mutex},
+};
+
+use libasvisor::{get_current_vm_rss_kb, IsolMetricSnapshot};
+use serde_json::Value;
+
+/// Upper bounds in seconds, shared by all duration histograms.
+const DURATION_BUCKETS: &[f64] = &[
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1., 2.5, 5., 10., 30., 60.,
+];
+
+type Labels = Vec<(&'static str, String)>;
+
+fn format_labels(labels: &Labels) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+    let labels: Vec<String> = labels
+        .iter()
+        .map(|(k, v)| format!("{}=\"{}\"", k, v.replace('\\', "\\\\").replace('"', "\\\"")))
+        .collect();
+    format!("{{{}}}", labels.join(","))
+}
+
+struct Histogram {
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            counts: vec![0; DURATION_BUCKETS.len()],
+            sum: 0.,
+            count: 0,
+        }
+    }
+}
+
+impl Histogram {
+    fn observe(&mut self, secs: f64) {
+        for (bound, count) in DURATION_BUCKETS.iter().zip(self.counts.iter_mut()) {
+            if secs <= *bound {
+                *count += 1
+            }
+        }
+        self.sum += secs;
+        self.count += 1;
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &Labels) {
+        for (bound, count) in DURATION_BUCKETS.iter().zip(&self.counts) {
+            let mut labels = labels.clone();
+            labels.push(("le", bound.to_string()));
+            let _ = writeln!(out, "{}_bucket{} {}", name, format_labels(&labels), count);
+        }
+        let mut inf_labels = labels.clone();
+        inf_labels.push(("le", "+Inf".to_owned()));
+        let _ = writeln!(
+            out,
+            "{}_bucket{} {}",
+            name,
+            format_labels(&inf_labels),
+            self.count
+        );
+        let _ = writeln!(out, "{}_sum{} {}", name, format_labels(labels), self.sum);
+        let _ = writeln!(
+            out,
+            "{}_count{} {}",
+            name,
+            format_labels(labels),
+            self.count
+        );
+    }
+}
+
+/// A metric family keyed by its label values.
+struct Family<T> {
+    name: &'static str,
+    help: &'static str,
+    series: BTreeMap<Vec<String>, T>,
+    label_names: &'static [&'static str],
+}
+
+impl<T: Default> Family<T> {
+    fn new(name: &'static str, help: &'static str, label_names: &'static [&'static str]) -> Self {
+        Self {
+            name,
+            help,
+            series: BTreeMap::new(),
+            label_names,
+        }
+    }
+
+    fn get(&mut self, values: &[&str]) -> &mut T {
+        let key = values.iter().map(|v| v.to_string()).collect();
+        self.series.entry(key).or_default()
+    }
+
+    fn labels(&self, values: &[String]) -> Labels {
+        self.label_names
+            .iter()
+            .copied()
+            .zip(values.iter().cloned())
+            .collect()
+    }
+}
+
+impl Family<u64> {
+    fn render(&self, out: &mut String) {
+        let _ = writeln!(out, "# HELP {} {}", self.name, self.help);
+        let _ = writeln!(out, "# TYPE {} counter", self.name);
+        for (values, val) in &self.series {
+            let labels = format_labels(&self.labels(values));
+            let _ = writeln!(out, "{}{} {}", self.name, labels, val);
+        }
+    }
+}
+
+impl Family<f64> {
+    fn render(&self, out: &mut String, kind: &str) {
+        let _ = writeln!(out, "# HELP {} {}", self.name, self.help);
+        let _ = writeln!(out, "# TYPE {} {}", self.name, kind);
+        for (values, val) in &self.series {
+            let labels = format_labels(&self.labels(values));
+            let _ = writeln!(out, "{}{} {}", self.name, labels, val);
+        }
+    }
+}
+
+impl Family<Histogram> {
+    fn render(&self, out: &mut String) {
+        let _ = writeln!(out, "# HELP {} {}", self.name, self.help);
+        let _ = writeln!(out, "# TYPE {} histogram", self.name);
+        for (values, histogram) in &self.series {
+            histogram.render(out, self.name, &self.labels(values));
+        }
+    }
+}
+
+struct MetricsInner {
+    workflow_runs: Family<u64>,
+    workflow_duration: Family<Histogram>,
+    app_init_duration: Family<Histogram>,
+    app_run_duration: Family<Histogram>,
+    app_starts: Family<u64>,
+    services_loaded: Family<u64>,
+    service_loads: Family<u64>,
+    hostcall_calls: Family<u64>,
+    hostcall_duration: Family<f64>,
+    hostcall_duration_max: Family<f64>,
+    buffer_alloc_bytes: Family<u64>,
+    buffer_transferred_bytes: Family<u64>,
+}
+
+pub struct Metrics {
+    inner: Mutex<MetricsInner>,
+}
+
+impl Metrics {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            inner: Mutex::new(MetricsInner {
+                workflow_runs: Family::new(
+                    "asvisor_workflow_runs_total",
+                    "Finished workflow runs.",
+                    &["workflow", "status"],
+                ),
+                workflow_duration: Family::new(
+                    "asvisor_workflow_duration_seconds",
+                    "End-to-end latency of successful workflow runs.",
+                    &["workflow"],
+                ),
+                app_init_duration: Family::new(
+                    "asvisor_app_init_duration_seconds",
+                    "Time from loading an app module to its thread starting to run.",
+                    &["app"],
+                ),
+                app_run_duration: Family::new(
+                    "asvisor_app_run_duration_seconds",
+                    "Run time of app instance threads.",
+                    &["app"],
+                ),
+                app_starts: Family::new(
+                    "asvisor_app_starts_total",
+                    "App instance starts, cold if the module was loaded on demand for it.",
+                    &["app", "start"],
+                ),
+                services_loaded: Family::new(
+                    "asvisor_services_loaded_total",
+                    "LibOS services loaded by workflow runs.",
+                    &["workflow"],
+                ),
+                service_loads: Family::new(
+                    "asvisor_service_loads_total",
+                    "LibOS service loads, on_demand if loaded while the workflow was running.",
+                    &["service", "load"],
+                ),
+                hostcall_calls: Family::new(
+                    "asvisor_hostcall_calls_total",
+                    "Hostcalls made by apps of workflows registered with hostcall_stats.",
+                    &["app", "hostcall"],
+                ),
+                hostcall_duration: Family::new(
+                    "asvisor_hostcall_duration_seconds_total",
+                    "Total time apps spent in hostcalls.",
+                    &["app", "hostcall"],
+                ),
+                hostcall_duration_max: Family::new(
+                    "asvisor_hostcall_duration_seconds_max",
+                    "Longest hostcall of apps.",
+                    &["app", "hostcall"],
+                ),
+                buffer_alloc_bytes: Family::new(
+                    "asvisor_buffer_alloc_bytes_total",
+                    "Bytes of DataBuffer allocated.",
+                    &["workflow"],
+                ),
+                buffer_transferred_bytes: Family::new(
+                    "asvisor_buffer_transferred_bytes_total",
+                    "Bytes of DataBuffer handed from producers to consumers.",
+                    &["workflow"],
+                ),
+            }),
+        })
+    }
+
+    /// Fold the metrics of a finished isolation of `workflow` in.
+    pub fn observe(&self, workflow: &str, succeeded: bool, snapshot: &IsolMetricSnapshot) {
+        let mut inner = self.inner.lock().unwrap();
+        let status = if succeeded { "succeeded" } else { "failed" };
+        *inner.workflow_runs.get(&[workflow, status]) += 1;
+        if let (true, Some(dur)) = (succeeded, snapshot.total_dur_us) {
+            inner
+                .workflow_duration
+                .get(&[workflow])
+                .observe(dur as f64 / 1e6);
+        }
+
+        *inner.services_loaded.get(&[workflow]) += snapshot.load_service_num as u64;
+        *inner.buffer_alloc_bytes.get(&[workflow]) += snapshot.buffer_alloc_bytes as u64;
+        *inner.buffer_transferred_bytes.get(&[workflow]) +=
+            snapshot.buffer_transferred_bytes as u64;
+
+        for (app, hostcalls) in &snapshot.hostcalls {
+            for (hostcall, stat) in hostcalls {
+                *inner.hostcall_calls.get(&[app, hostcall]) += stat.count;
+                *inner.hostcall_duration.get(&[app, hostcall]) += stat.total_ns as f64 / 1e9;
+                let max = inner.hostcall_duration_max.get(&[app, hostcall]);
+                *max = max.max(stat.max_ns as f64 / 1e9);
+            }
+        }
+
+        for svc in &snapshot.services {
+            if svc.threads.is_empty() {
+                let load = if svc.loaded_on_demand {
+                    "on_demand"
+                } else {
+                    "preloaded"
+                };
+                *inner.service_loads.get(&[&svc.name, load]) += 1;
+            }
+            for thread in &svc.threads {
+                let start = if thread.cold { "cold" } else { "warm" };
+                *inner.app_starts.get(&[&svc.name, start]) += 1;
+                inner
+                    .app_init_duration
+                    .get(&[&svc.name])
+                    .observe(thread.init_dur_ms as f64 / 1e3);
+                inner
+                    .app_run_duration
+                    .get(&[&svc.name])
+                    .observe(thread.run_dur_ms as f64 / 1e3);
+            }
+        }
+    }
+
+    /// Render all metrics, `admission` is the json of `Admission::stats`.
+    pub fn render(&self, admission: &Value) -> String {
+        let mut out = String::new();
+        {
+            let inner = self.inner.lock().unwrap();
+            inner.workflow_runs.render(&mut out);
+            inner.workflow_duration.render(&mut out);
+            inner.app_init_duration.render(&mut out);
+            inner.app_run_duration.render(&mut out);
+            inner.app_starts.render(&mut out);
+            inner.services_loaded.render(&mut out);
+            inner.service_loads.render(&mut out);
+            inner.hostcall_calls.render(&mut out);
+            inner.hostcall_duration.render(&mut out, "counter");
+            inner.hostcall_duration_max.render(&mut out, "gauge");
+            inner.buffer_alloc_bytes.render(&mut out);
+            inner.buffer_transferred_bytes.render(&mut out);
+        }
+
+        let gauges = [
+            (
+                "asvisor_admission_queue_depth",
+                "gauge",
+                "Requests waiting for admission.",
+                admission["queue_depth"].as_f64(),
+            ),
+            (
+                "asvisor_admission_running",
+                "gauge",
+                "Admitted workflow runs in progress.",
+                admission["running"].as_f64(),
+            ),
+            (
+                "asvisor_admission_rejected_total",
+                "counter",
+                "Requests rejected because the wait queue was full.",
+                admission["rejected_total"].as_f64(),
+            ),
+            (
+                "asvisor_admission_timeout_total",
+                "counter",
+                "Requests that timed out in the wait queue.",
+                admission["timeout_total"].as_f64(),
+            ),
+            (
+                "asvisor_admission_wait_seconds_total",
+                "counter",
+                "Total time admitted requests spent in the wait queue.",
+                admission["wait_us_total"].as_f64().map(|us| us / 1e6),
+            ),
+            (
+                "asvisor_admission_wait_seconds_max",
+                "gauge",
+                "Longest time a request spent in the wait queue.",
+                admission["wait_us_max"].as_f64().map(|us| us / 1e6),
+            ),
+            (
+                "asvisor_process_resident_memory_bytes",
+                "gauge",
+                "Resident set size of the daemon.",
+                Some((get_current_vm_rss_kb() * 1024) as f64),
+            ),
+        ];
+        for (name, kind, help, val) in gauges {
+            if let Some(val) = val {
+                let _ = writeln!(out, "# HELP {} {}", name, help);
+                let _ = writeln!(out, "# TYPE {} {}", name, kind);
+                let _ = writeln!(out, "{} {}", name, val);
+            }
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+const METRICS_RENDER_GOLDEN: &str = r#"# HELP asvisor_workflow_runs_total Finished workflow runs.
+# TYPE asvisor_workflow_runs_total counter
+asvisor_workflow_runs_total{workflow="wf",status="failed"} 1
+asvisor_workflow_runs_total{workflow="wf",status="succeeded"} 1
+# HELP asvisor_workflow_duration_seconds End-to-end latency of successful workflow runs.
+# TYPE asvisor_workflow_duration_seconds histogram
+asvisor_workflow_duration_seconds_bucket{workflow="wf",le="0.001"} 0
+asvisor_workflow_duration_seconds_bucket{workflow="wf",le="0.0025"} 0
+asvisor_workflow_duration_seconds_bucket{workflow="wf",le="0.005"} 0
+asvisor_workflow_duration_seconds_bucket{workflow="wf",le="0.01"} 0
+asvisor_workflow_duration_seconds_bucket{workflow="wf",le="0.025"} 1
+asvisor_workflow_duration_seconds_bucket{workflow="wf",le="0.05"} 1
+asvisor_workflow_duration_seconds_bucket{workflow="wf",le="0.1"} 1
+asvisor_workflow_duration_seconds_bucket{workflow="wf",le="0.25"} 1
+asvisor_workflow_duration_seconds_bucket{workflow="wf",le="0.5"} 1
+asvisor_workflow_duration_seconds_bucket{workflow="wf",le="1"} 1
+asvisor_workflow_duration_seconds_bucket{workflow="wf",le="2.5"} 1
+asvisor_workflow_duration_seconds_bucket{workflow="wf",le="5"} 1
+asvisor_workflow_duration_seconds_bucket{workflow="wf",le="10"} 1
+asvisor_workflow_duration_seconds_bucket{workflow="wf",le="30"} 1
+asvisor_workflow_duration_seconds_bucket{workflow="wf",le="60"} 1
+asvisor_workflow_duration_seconds_bucket{workflow="wf",le="+Inf"} 1
+asvisor_workflow_duration_seconds_sum{workflow="wf"} 0.02
+asvisor_workflow_duration_seconds_count{workflow="wf"} 1
+# HELP asvisor_app_init_duration_seconds Time from loading an app module to its thread starting to run.
+# TYPE asvisor_app_init_duration_seconds histogram
+asvisor_app_init_duration_seconds_bucket{app="app",le="0.001"} 0
+asvisor_app_init_duration_seconds_bucket{app="app",le="0.0025"} 2
+asvisor_app_init_duration_seconds_bucket{app="app",le="0.005"} 2
+asvisor_app_init_duration_seconds_bucket{app="app",le="0.01"} 2
+asvisor_app_init_duration_seconds_bucket{app="app",le="0.025"} 2
+asvisor_app_init_duration_seconds_bucket{app="app",le="0.05"} 2
+asvisor_app_init_duration_seconds_bucket{app="app",le="0.1"} 2
+asvisor_app_init_duration_seconds_bucket{app="app",le="0.25"} 2
+asvisor_app_init_duration_seconds_bucket{app="app",le="0.5"} 2
+asvisor_app_init_duration_seconds_bucket{app="app",le="1"} 2
+asvisor_app_init_duration_seconds_bucket{app="app",le="2.5"} 2
+asvisor_app_init_duration_seconds_bucket{app="app",le="5"} 2
+asvisor_app_init_duration_seconds_bucket{app="app",le="10"} 2
+asvisor_app_init_duration_seconds_bucket{app="app",le="30"} 2
+asvisor_app_init_duration_seconds_bucket{app="app",le="60"} 2
+asvisor_app_init_duration_seconds_bucket{app="app",le="+Inf"} 2
+asvisor_app_init_duration_seconds_sum{app="app"} 0.004
+asvisor_app_init_duration_seconds_count{app="app"} 2
+# HELP asvisor_app_run_duration_seconds Run time of app instance threads.
+# TYPE asvisor_app_run_duration_seconds histogram
+asvisor_app_run_duration_seconds_bucket{app="app",le="0.001"} 0
+asvisor_app_run_duration_seconds_bucket{app="app",le="0.0025"} 0
+asvisor_app_run_duration_seconds_bucket{app="app",le="0.005"} 0
+asvisor_app_run_duration_seconds_bucket{app="app",le="0.01"} 0
+asvisor_app_run_duration_seconds_bucket{app="app",le="0.025"} 0
+asvisor_app_run_duration_seconds_bucket{app="app",le="0.05"} 0
+asvisor_app_run_duration_seconds_bucket{app="app",le="0.1"} 0
+asvisor_app_run_duration_seconds_bucket{app="app",le="0.25"} 0
+asvisor_app_run_duration_seconds_bucket{app="app",le="0.5"} 2
+asvisor_app_run_duration_seconds_bucket{app="app",le="1"} 2
+asvisor_app_run_duration_seconds_bucket{app="app",le="2.5"} 2
+asvisor_app_run_duration_seconds_bucket{app="app",le="5"} 2
+asvisor_app_run_duration_seconds_bucket{app="app",le="10"} 2
+asvisor_app_run_duration_seconds_bucket{app="app",le="30"} 2
+asvisor_app_run_duration_seconds_bucket{app="app",le="60"} 2
+asvisor_app_run_duration_seconds_bucket{app="app",le="+Inf"} 2
+asvisor_app_run_duration_seconds_sum{app="app"} 0.6
+asvisor_app_run_duration_seconds_count{app="app"} 2
+# HELP asvisor_app_starts_total App instance starts, cold if the module was loaded on demand for it.
+# TYPE asvisor_app_starts_total counter
+asvisor_app_starts_total{app="app",start="cold"} 2
+# HELP asvisor_services_loaded_total LibOS services loaded by workflow runs.
+# TYPE asvisor_services_loaded_total counter
+asvisor_services_loaded_total{workflow="wf"} 4
+# HELP asvisor_service_loads_total LibOS service loads, on_demand if loaded while the workflow was running.
+# TYPE asvisor_service_loads_total counter
+asvisor_service_loads_total{service="fdtab",load="on_demand"} 2
+# HELP asvisor_hostcall_calls_total Hostcalls made by apps of workflows registered with hostcall_stats.
+# TYPE asvisor_hostcall_calls_total counter
+asvisor_hostcall_calls_total{app="app",hostcall="write"} 6
+# HELP asvisor_hostcall_duration_seconds_total Total time apps spent in hostcalls.
+# TYPE asvisor_hostcall_duration_seconds_total counter
+asvisor_hostcall_duration_seconds_total{app="app",hostcall="write"} 0.003
+# HELP asvisor_hostcall_duration_seconds_max Longest hostcall of apps.
+# TYPE asvisor_hostcall_duration_seconds_max gauge
+asvisor_hostcall_duration_seconds_max{app="app",hostcall="write"} 0.001
+# HELP asvisor_buffer_alloc_bytes_total Bytes of DataBuffer allocated.
+# TYPE asvisor_buffer_alloc_bytes_total counter
+asvisor_buffer_alloc_bytes_total{workflow="wf"} 8192
+# HELP asvisor_buffer_transferred_bytes_total Bytes of DataBuffer handed from producers to consumers.
+# TYPE asvisor_buffer_transferred_bytes_total counter
+asvisor_buffer_transferred_bytes_total{workflow="wf"} 2048
+# HELP asvisor_admission_queue_depth Requests waiting for admission.
+# TYPE asvisor_admission_queue_depth gauge
+asvisor_admission_queue_depth 1
+# HELP asvisor_admission_running Admitted workflow runs in progress.
+# TYPE asvisor_admission_running gauge
+asvisor_admission_running 2
+# HELP asvisor_admission_rejected_total Requests rejected because the wait queue was full.
+# TYPE asvisor_admission_rejected_total counter
+asvisor_admission_rejected_total 3
+# HELP asvisor_admission_timeout_total Requests that timed out in the wait queue.
+# TYPE asvisor_admission_timeout_total counter
+asvisor_admission_timeout_total 4
+# HELP asvisor_admission_wait_seconds_total Total time admitted requests spent in the wait queue.
+# TYPE asvisor_admission_wait_seconds_total counter
+asvisor_admission_wait_seconds_total 1.5
+# HELP asvisor_admission_wait_seconds_max Longest time a request spent in the wait queue.
+# TYPE asvisor_admission_wait_seconds_max gauge
+asvisor_admission_wait_seconds_max 1
+# HELP asvisor_process_resident_memory_bytes Resident set size of the daemon.
+# TYPE asvisor_process_resident_memory_bytes gauge
+"#;
+
+#[test]
+fn metrics_render_test() {
+    use libasvisor::{HostcallStatSnapshot, SvcMetricSnapshot, SvcThreadSnapshot};
+    use serde_json::json;
+
+    let metrics = Metrics::new();
+    let snapshot = IsolMetricSnapshot {
+        total_dur_us: Some(20_000),
+        load_service_num: 2,
+        hostcalls: BTreeMap::from([(
+            "app".to_owned(),
+            BTreeMap::from([(
+                "write".to_owned(),
+                HostcallStatSnapshot {
+                    count: 3,
+                    total_ns: 1_500_000,
+                    max_ns: 1_000_000,
+                },
+            )]),
+        )]),
+        buffer_alloc_bytes: 4096,
+        buffer_transferred_bytes: 1024,
+        services: vec![
+            SvcMetricSnapshot {
+                name: "fdtab".to_owned(),
+                loaded_on_demand: true,
+                threads: vec![],
+            },
+            SvcMetricSnapshot {
+                name: "app".to_owned(),
+                loaded_on_demand: false,
+                threads: vec![SvcThreadSnapshot {
+                    init_dur_ms: 2,
+                    run_dur_ms: 300,
+                    cold: true,
+                }],
+            },
+        ],
+    };
+    metrics.observe("wf", true, &snapshot);
+    metrics.observe("wf", false, &snapshot);
+
+    let admission = json!({
+        "queue_depth": 1,
+        "running": 2,
+        "rejected_total": 3,
+        "timeout_total": 4,
+        "wait_us_total": 1_500_000,
+        "wait_us_max": 1_000_000,
+    });
+    // The resident memory of the test process varies.
+    let rendered: String = metrics
+        .render(&admission)
+        .lines()
+        .filter(|line| !line.starts_with("asvisor_process_resident_memory_bytes "))
+        .map(|line| format!("{line}\n"))
+        .collect();
+    assert_eq!(rendered, METRICS_RENDER_GOLDEN);
+}
@@ -0,0 +1,89 @@
+//! Captured stdout and stderr of workflow runs. Lines are kept by the
+//! `OutputCapture` of the isolation, and broadcast to streaming clients
+//! while the run is in progress.
+
+use std::{
+    fmt::Write,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use libasvisor::isolation::output::{OutputCapture, OutputLine, Stream};
+use tokio::sync::broadcast;
+
+/// Lines buffered per streaming client before it lags behind.
+const STREAM_CAPACITY: usize = 1024;
+
+/// `None` is sent once the run finished.
+pub type OutputReceiver = broadcast::Receiver<Option<OutputLine>>;
+
+pub struct RunOutput {
+    capture: Arc<OutputCapture>,
+    tx: broadcast::Sender<Option<OutputLine>>,
+    log_path: Option<PathBuf>,
+}
+
+impl RunOutput {
+    /// Capture output of a run, also written to `<output_dir>/<file_name>`
+    /// if the daemon has an output directory.
+    pub fn new(output_dir: Option<&Path>, file_name: &str) -> anyhow::Result<Self> {
+        let (tx, _) = broadcast::channel(STREAM_CAPACITY);
+        let sink_tx = tx.clone();
+        let mut capture = OutputCapture::new().sink(Box::new(move |line| {
+            // No receiver is not an error, nobody is streaming.
+            let _ = sink_tx.send(Some(line.clone()));
+        }));
+        let log_path = output_dir.map(|dir| dir.join(file_name));
+        if let Some(path) = &log_path {
+            capture = capture.log_file(path)?;
+        }
+
+        Ok(Self {
+            capture: Arc::new(capture),
+            tx,
+            log_path,
+        })
+    }
+
+    /// The file every line is also written to, which has the whole output
+    /// even if the capture is truncated.
+    pub fn log_path(&self) -> Option<&Path> {
+        self.log_path.as_deref()
+    }
+
+    /// Whether lines were dropped from the capture, see `log_path`.
+    pub fn truncated(&self) -> bool {
+        self.capture.truncated()
+    }
+
+    pub fn capture(&self) -> Arc<OutputCapture> {
+        Arc::clone(&self.capture)
+    }
+
+    /// Tell streaming clients that no more lines will come.
+    pub fn close(&self) {
+        let _ = self.tx.send(None);
+    }
+
+    /// Captured lines, optionally only those of `stream`.
+    pub fn text(&self, stream: Option<Stream>) -> String {
+        self.capture.with_lines(|lines| {
+            let mut text = String::new();
+            for line in lines
+                .iter()
+                .filter(|line| stream.map(|s| s == line.stream).unwrap_or(true))
+            {
+                let _ = writeln!(text, "{}", line);
+            }
+            text
+        })
+    }
+
+    /// Lines captured so far, and a receiver of later lines. The receiver
+    /// is subscribed while the capture is locked, so no line is missed or
+    /// received twice.
+    pub fn follow(&self) -> (Vec<OutputLine>, OutputReceiver) {
+        self.capture
+            .with_lines(|lines| (lines.to_vec(), self.tx.subscribe()))
+    }
+}
@@ -0,0 +1,211 @@
+//! Workflow definitions registered at runtime. Every update creates a new
+//! version, and jobs hold an `Arc` of the version they were submitted with,
+//! so updating or deleting a workflow never affects in-flight jobs.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex, RwLock},
+};
+
+use anyhow::anyhow;
+use libasvisor::isolation::{config::IsolationConfig, Isolation};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+pub type Version = u32;
+
+pub struct WorkflowDef {
+    pub name: String,
+    pub version: Version,
+    /// Load every module at registration, and before every later run, like
+    /// `asvisor --preload`.
+    pub preload: bool,
+    /// Max running instances of this workflow, overrides the daemon default.
+    pub max_concurrency: Option<usize>,
+    /// Collect the hostcall stats exported by `Metrics`, which costs every
+    /// hostcall of its apps two calls into as-visor.
+    pub hostcall_stats: bool,
+    pub config: IsolationConfig,
+    raw: Value,
+    /// Isolation loaded at registration, until a run takes it.
+    warm: Mutex<Option<Arc<Isolation>>>,
+}
+
+impl WorkflowDef {
+    /// Build a definition straight from `isol_config/<name>.json`, used for
+    /// workflows that were never registered.
+    pub fn from_file(name: String) -> anyhow::Result<Self> {
+        let mut file_name = name.clone();
+        if !file_name.ends_with(".json") {
+            file_name += ".json"
+        };
+        let config = IsolationConfig::from_file(file_name.into())?;
+
+        Ok(Self {
+            name,
+            version: 0,
+            preload: false,
+            max_concurrency: None,
+            hostcall_stats: false,
+            raw: Value::Null,
+            config,
+            warm: Mutex::new(None),
+        })
+    }
+
+    /// An isolation for a run of `config`, the workflow config extended by
+    /// the args of the run, and whether its modules are loaded. The
+    /// isolation loaded at registration goes to the first run without
+    /// args, as args are fixed when an isolation is created.
+    pub fn isolation(&self, config: &IsolationConfig, has_args: bool) -> (Arc<Isolation>, bool) {
+        if !has_args {
+            if let Some(isol) = self.warm.lock().unwrap().take() {
+                isol.metric.restart();
+                return (isol, true);
+            }
+        }
+
+        (new_isolation(config, self.hostcall_stats), false)
+    }
+
+    /// Load the modules of `isol` before running unless they already are.
+    pub fn prepare(
+        &self,
+        isol: &Isolation,
+        config: &IsolationConfig,
+        loaded: bool,
+    ) -> anyhow::Result<()> {
+        if self.preload && !loaded {
+            isol.preload(config)?
+        }
+        Ok(())
+    }
+
+    fn describe(&self) -> Value {
+        json!({
+            "name": &self.name,
+            "version": self.version,
+            "preload": self.preload,
+            "max_concurrency": self.max_concurrency,
+            "hostcall_stats": self.hostcall_stats,
+            "config": &self.raw,
+        })
+    }
+}
+
+/// An isolation of `config`, which collects the hostcall stats exported by
+/// `Metrics` if `hostcall_stats`.
+fn new_isolation(config: &IsolationConfig, hostcall_stats: bool) -> Arc<Isolation> {
+    let isol = Isolation::new(config);
+    if hostcall_stats {
+        isol.metric.enable_hostcall_stats();
+    }
+    isol
+}
+
+/// Options of a workflow given at registration.
+#[derive(Deserialize)]
+pub struct WorkflowOpts {
+    #[serde(default)]
+    pub preload: bool,
+    pub max_concurrency: Option<usize>,
+    #[serde(default)]
+    pub hostcall_stats: bool,
+}
+
+#[derive(Default)]
+pub struct Registry {
+    workflows: RwLock<HashMap<String, BTreeMap<Version, Arc<WorkflowDef>>>>,
+}
+
+impl Registry {
+    /// Validate `raw` and store it as the next version of `name`. With
+    /// `must_exist` this is an update and fails for unknown workflows. With
+    /// `opts.preload` every module is loaded here, so load errors fail the
+    /// registration and the first run is warm. It blocks meanwhile.
+    pub fn put(
+        &self,
+        name: &str,
+        raw: Value,
+        opts: WorkflowOpts,
+        must_exist: bool,
+    ) -> anyhow::Result<Version> {
+        let config = IsolationConfig::from_value(raw.clone())
+            .map_err(|e| anyhow!("parse config failed: {e}"))?;
+        config
+            .validate()
+            .map_err(|e| anyhow!("invalid config: {e}"))?;
+        // Loaded before the registry is locked, it may take a while.
+        let warm = if opts.preload {
+            let isol = new_isolation(&config, opts.hostcall_stats);
+            isol.preload(&config)
+                .map_err(|e| anyhow!("preload failed: {e}"))?;
+            Some(isol)
+        } else {
+            None
+        };
+
+        let mut workflows = self.workflows.write().unwrap();
+        if must_exist && !workflows.contains_key(name) {
+            return Err(anyhow!("workflow {} not registered", name));
+        }
+        if !must_exist && workflows.contains_key(name) {
+            return Err(anyhow!("workflow {} already registered", name));
+        }
+
+        let versions = workflows.entry(name.to_owned()).or_default();
+        let version = versions.keys().last().map(|v| v + 1).unwrap_or(1);
+        versions.insert(
+            version,
+            Arc::new(WorkflowDef {
+                name: name.to_owned(),
+                version,
+                preload: opts.preload,
+                max_concurrency: opts.max_concurrency,
+                hostcall_stats: opts.hostcall_stats,
+                config,
+                raw,
+                warm: Mutex::new(warm),
+            }),
+        );
+        log::info!("register workflow {} version {}", name, version);
+
+        Ok(version)
+    }
+
+    /// The requested version, or the latest one if `version` is `None`.
+    pub fn get(&self, name: &str, version: Option<Version>) -> Option<Arc<WorkflowDef>> {
+        let workflows = self.workflows.read().unwrap();
+        let versions = workflows.get(name)?;
+        let def = match version {
+            Some(version) => versions.get(&version),
+            None => versions.values().last(),
+        };
+
+        def.map(Arc::clone)
+    }
+
+    pub fn describe(&self, name: &str, version: Option<Version>) -> Option<Value> {
+        self.get(name, version).map(|def| def.describe())
+    }
+
+    pub fn list(&self) -> Value {
+        let workflows = self.workflows.read().unwrap();
+        let list: Vec<Value> = workflows
+            .iter()
+            .map(|(name, versions)| {
+                json!({
+                    "name": name,
+                    "latest": versions.keys().last(),
+                    "versions": versions.keys().collect::<Vec<_>>(),
+                })
+            })
+            .collect();
+
+        json!(list)
+    }
+
+    pub fn remove(&self, name: &str) -> bool {
+        self.workflows.write().unwrap().remove(name).is_some()
+    }
+}
@@ -0,0 +1,31 @@
+//! SIGINT and SIGTERM handling. The signals are blocked in every thread and
+//! waited for by a dedicated one, the first is reported to start a graceful
+//! shutdown, a second one makes the daemon exit at once.
+
+use nix::sys::signal::{SigSet, Signal};
+use tokio::sync::oneshot;
+
+use libasvisor::logger;
+
+/// Must be called before the tokio runtime is built, threads inherit the
+/// signal mask of the thread that spawns them.
+pub fn listen() -> std::io::Result<oneshot::Receiver<Signal>> {
+    let mut signals = SigSet::empty();
+    signals.add(Signal::SIGINT);
+    signals.add(Signal::SIGTERM);
+    signals.thread_block()?;
+
+    let (tx, rx) = oneshot::channel();
+    std::thread::Builder::new()
+        .name("shutdown".to_owned())
+        .spawn(move || {
+            let first = signals.wait().expect("wait for signals failed");
+            let _ = tx.send(first);
+
+            let second = signals.wait().expect("wait for signals failed");
+            logger::error!("received {} again, exit without cleanup", second);
+            std::process::exit(128 + second as i32)
+        })?;
+
+    Ok(rx)
+}
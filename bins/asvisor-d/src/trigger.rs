@@ -0,0 +1,460 @@
+//! Triggers, which submit a job of a workflow on a cron expression, a fixed
+//! interval, or a new file in a watched directory. When a run is still in
+//! progress at fire time, the trigger's overlap policy decides to skip the
+//! new run or queue it. Every new file is queued regardless, so none is
+//! missed.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    future::Future,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::anyhow;
+use libasvisor::{logger, utils::REPOS_ROOT_PATH};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::{
+    sync::mpsc::{self, UnboundedReceiver},
+    task::JoinHandle,
+};
+
+use crate::{
+    cron::CronExpr,
+    job::JobId,
+    registry::{Version, WorkflowDef},
+    watch::{self, Watcher},
+    AppState,
+};
+
+pub type TriggerId = u64;
+
+const MIN_INTERVAL: Duration = Duration::from_secs(1);
+/// Image used by the fatfs service when the workflow does not set one.
+const DEFAULT_FS_IMAGE: &str = "fs_images/fatfs.img";
+/// Max runs waiting behind the running one with `OverlapPolicy::Queue`, and
+/// new files waiting before the watcher stops taking more.
+const MAX_QUEUED_RUNS: usize = 8;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum OverlapPolicy {
+    #[default]
+    Skip,
+    Queue,
+}
+
+fn default_path_arg() -> String {
+    "input_file".to_owned()
+}
+
+fn default_poll_secs() -> u64 {
+    2
+}
+
+/// Run the workflow for every new file in a directory.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct WatchSpec {
+    /// A host directory, or a directory inside the workflow's fs image with
+    /// `in_image`.
+    pub path: String,
+    #[serde(default)]
+    pub in_image: bool,
+    /// Name of the arg the path of the new file is passed in.
+    #[serde(default = "default_path_arg")]
+    pub arg: String,
+    /// Poll interval of directories inside the fs image.
+    #[serde(default = "default_poll_secs")]
+    pub poll_secs: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TriggerSpec {
+    pub workflow: String,
+    /// Pin a version of a registered workflow, the latest one if not set.
+    pub version: Option<Version>,
+    /// Cron expression in UTC. Exactly one of `cron`, `interval_secs` and
+    /// `watch` is set.
+    pub cron: Option<String>,
+    pub interval_secs: Option<u64>,
+    pub watch: Option<WatchSpec>,
+    /// Passed to every app of the workflow.
+    #[serde(default)]
+    pub args: BTreeMap<String, String>,
+    #[serde(default)]
+    pub overlap: OverlapPolicy,
+}
+
+enum Schedule {
+    Interval(Duration),
+    Cron(CronExpr),
+}
+
+impl Schedule {
+    /// The next fire time after `after`, both since the unix epoch.
+    fn next_after(&self, after: Duration) -> Option<Duration> {
+        match self {
+            Schedule::Interval(interval) => Some(after + *interval),
+            Schedule::Cron(expr) => expr.next_after(after.as_secs()).map(Duration::from_secs),
+        }
+    }
+}
+
+enum Source {
+    Schedule(Schedule),
+    Watch {
+        _watcher: Watcher,
+        files: UnboundedReceiver<String>,
+        arg: String,
+    },
+}
+
+impl Source {
+    fn from_spec(spec: &TriggerSpec, workflow: &WorkflowDef) -> anyhow::Result<Self> {
+        match (&spec.cron, spec.interval_secs, &spec.watch) {
+            (Some(expr), None, None) => {
+                Ok(Source::Schedule(Schedule::Cron(CronExpr::parse(expr)?)))
+            }
+            (None, Some(secs), None) => {
+                let interval = Duration::from_secs(secs);
+                if interval < MIN_INTERVAL {
+                    return Err(anyhow!("interval should be at least {:?}", MIN_INTERVAL));
+                }
+                Ok(Source::Schedule(Schedule::Interval(interval)))
+            }
+            (None, None, Some(watch)) => {
+                let (tx, files) = mpsc::unbounded_channel();
+                let watcher = if watch.in_image {
+                    let image = workflow.config.fs_image.as_deref();
+                    let image = REPOS_ROOT_PATH.join(image.unwrap_or(DEFAULT_FS_IMAGE));
+                    let interval = Duration::from_secs(watch.poll_secs).max(MIN_INTERVAL);
+                    watch::watch_image_dir(image, watch.path.clone(), interval, tx)?
+                } else {
+                    watch::watch_host_dir(PathBuf::from(&watch.path), tx)?
+                };
+
+                Ok(Source::Watch {
+                    _watcher: watcher,
+                    files,
+                    arg: watch.arg.clone(),
+                })
+            }
+            _ => Err(anyhow!(
+                "exactly one of cron, interval_secs and watch should be set"
+            )),
+        }
+    }
+}
+
+fn since_epoch() -> Duration {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap()
+}
+
+#[derive(Default)]
+struct TriggerStats {
+    fired: AtomicU64,
+    skipped: AtomicU64,
+    failed: AtomicU64,
+    running: AtomicBool,
+    queued: AtomicUsize,
+    next_fire_at: AtomicU64,
+    last_job: Mutex<Option<JobId>>,
+}
+
+struct Trigger {
+    spec: TriggerSpec,
+    stats: Arc<TriggerStats>,
+    task: JoinHandle<()>,
+}
+
+impl Trigger {
+    fn describe(&self, id: TriggerId) -> Value {
+        let stats = &self.stats;
+        json!({
+            "id": id,
+            "spec": &self.spec,
+            "fired": stats.fired.load(Ordering::Relaxed),
+            "skipped": stats.skipped.load(Ordering::Relaxed),
+            "failed": stats.failed.load(Ordering::Relaxed),
+            "running": stats.running.load(Ordering::SeqCst),
+            "queued": stats.queued.load(Ordering::SeqCst),
+            "next_fire_at(s)": Some(stats.next_fire_at.load(Ordering::Relaxed)).filter(|t| *t > 0),
+            "last_job": *stats.last_job.lock().unwrap(),
+        })
+    }
+}
+
+impl Drop for Trigger {
+    fn drop(&mut self) {
+        // The runner ends once the ticker drops its sender, after the run in
+        // progress.
+        self.task.abort()
+    }
+}
+
+pub struct Triggers {
+    triggers: Mutex<HashMap<TriggerId, Trigger>>,
+    next_id: AtomicU64,
+}
+
+impl Triggers {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            triggers: Default::default(),
+            next_id: AtomicU64::new(1),
+        })
+    }
+
+    pub fn add(&self, state: AppState, spec: TriggerSpec) -> anyhow::Result<TriggerId> {
+        let workflow = state
+            .workflow(spec.workflow.clone(), spec.version)
+            .map_err(|e| anyhow!(e.1))?;
+        let source = Source::from_spec(&spec, &workflow)?;
+
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let stats = Arc::new(TriggerStats::default());
+        let task = tokio::spawn(run_trigger(
+            state,
+            id,
+            spec.clone(),
+            source,
+            Arc::clone(&stats),
+        ));
+        logger::info!("add trigger{} of workflow {}", id, spec.workflow);
+
+        self.triggers
+            .lock()
+            .unwrap()
+            .insert(id, Trigger { spec, stats, task });
+        Ok(id)
+    }
+
+    pub fn describe(&self, id: TriggerId) -> Option<Value> {
+        let triggers = self.triggers.lock().unwrap();
+        triggers.get(&id).map(|trigger| trigger.describe(id))
+    }
+
+    pub fn list(&self) -> Value {
+        let triggers = self.triggers.lock().unwrap();
+        let list: Vec<Value> = triggers
+            .iter()
+            .map(|(id, trigger)| trigger.describe(*id))
+            .collect();
+
+        json!(list)
+    }
+
+    pub fn remove(&self, id: TriggerId) -> bool {
+        self.triggers.lock().unwrap().remove(&id).is_some()
+    }
+
+    pub fn clear(&self) {
+        self.triggers.lock().unwrap().clear()
+    }
+}
+
+/// Queue a run with `args`, or skip it according to the overlap policy.
+/// Used for schedules only.
+fn fire(
+    id: TriggerId,
+    spec: &TriggerSpec,
+    stats: &TriggerStats,
+    tx: &mpsc::Sender<BTreeMap<String, String>>,
+    args: BTreeMap<String, String>,
+) {
+    stats.fired.fetch_add(1, Ordering::Relaxed);
+
+    let busy = stats.running.load(Ordering::SeqCst) || stats.queued.load(Ordering::SeqCst) > 0;
+    if busy && spec.overlap == OverlapPolicy::Skip {
+        logger::info!("trigger{} skip a run, the last one is in progress", id);
+        stats.skipped.fetch_add(1, Ordering::Relaxed);
+        return;
+    }
+
+    stats.queued.fetch_add(1, Ordering::SeqCst);
+    if tx.try_send(args).is_err() {
+        logger::warn!("trigger{} skip a run, {} runs queued", id, MAX_QUEUED_RUNS);
+        stats.queued.fetch_sub(1, Ordering::SeqCst);
+        stats.skipped.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Queue a run for every new file from `files`. Unlike `fire`, it waits
+/// for room in the queue instead of skipping the file.
+async fn watch_files(
+    id: TriggerId,
+    spec: &TriggerSpec,
+    stats: &TriggerStats,
+    tx: &mpsc::Sender<BTreeMap<String, String>>,
+    files: &mut UnboundedReceiver<String>,
+    arg: &str,
+) {
+    while let Some(path) = files.recv().await {
+        logger::info!("trigger{} new file: {}", id, path);
+        stats.fired.fetch_add(1, Ordering::Relaxed);
+        let mut args = spec.args.clone();
+        args.insert(arg.to_owned(), path);
+
+        stats.queued.fetch_add(1, Ordering::SeqCst);
+        if tx.send(args).await.is_err() {
+            // The runner is gone with the trigger.
+            stats.queued.fetch_sub(1, Ordering::SeqCst);
+            return;
+        }
+    }
+}
+
+/// Run the args of queued runs one at a time with `run`.
+fn spawn_runner<F, Fut>(
+    stats: Arc<TriggerStats>,
+    mut rx: mpsc::Receiver<BTreeMap<String, String>>,
+    mut run: F,
+) -> JoinHandle<()>
+where
+    F: FnMut(BTreeMap<String, String>) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send,
+{
+    tokio::spawn(async move {
+        while let Some(args) = rx.recv().await {
+            stats.queued.fetch_sub(1, Ordering::SeqCst);
+            stats.running.store(true, Ordering::SeqCst);
+            run(args).await;
+            stats.running.store(false, Ordering::SeqCst);
+        }
+    })
+}
+
+async fn run_trigger(
+    state: AppState,
+    id: TriggerId,
+    spec: TriggerSpec,
+    source: Source,
+    stats: Arc<TriggerStats>,
+) {
+    let (tx, rx) = mpsc::channel(MAX_QUEUED_RUNS);
+    let runner_spec = Arc::new(spec.clone());
+    let runner_stats = Arc::clone(&stats);
+    spawn_runner(Arc::clone(&stats), rx, move |args| {
+        let (state, spec, stats) = (
+            state.clone(),
+            Arc::clone(&runner_spec),
+            Arc::clone(&runner_stats),
+        );
+        async move { run_once(&state, id, &spec, args, &stats).await }
+    });
+
+    match source {
+        Source::Schedule(schedule) => {
+            let mut last_fire = since_epoch();
+            loop {
+                // Fires missed while the daemon was busy are not made up for.
+                let Some(next) = schedule.next_after(last_fire.max(since_epoch())) else {
+                    logger::warn!("trigger{} will never fire again, stop it", id);
+                    return;
+                };
+                stats.next_fire_at.store(next.as_secs(), Ordering::Relaxed);
+                tokio::time::sleep(next.saturating_sub(since_epoch())).await;
+                last_fire = next;
+                fire(id, &spec, &stats, &tx, spec.args.clone());
+            }
+        }
+        Source::Watch {
+            _watcher,
+            mut files,
+            arg,
+        } => {
+            watch_files(id, &spec, &stats, &tx, &mut files, &arg).await;
+            logger::warn!("trigger{} watcher stopped", id);
+        }
+    }
+}
+
+/// Submit a job of the trigger's workflow and wait for it to finish.
+async fn run_once(
+    state: &AppState,
+    id: TriggerId,
+    spec: &TriggerSpec,
+    args: BTreeMap<String, String>,
+    stats: &TriggerStats,
+) {
+    let result = async {
+        let workflow = state
+            .workflow(spec.workflow.clone(), spec.version)
+            .map_err(|e| anyhow!(e.1))?;
+        let ticket = state
+            .admission
+            .enqueue(&workflow.name, workflow.max_concurrency)
+            .map_err(|e| anyhow!(e.to_string()))?;
+        let (job_id, handle) = state.jobs.submit(workflow, args, ticket)?;
+        logger::info!("trigger{} submit job{}", id, job_id);
+        *stats.last_job.lock().unwrap() = Some(job_id);
+
+        handle
+            .await
+            .map_err(|e| anyhow!("job{} task failed: {}", job_id, e))
+    }
+    .await;
+
+    if let Err(e) = result {
+        logger::error!("trigger{} run workflow {} failed: {}", id, spec.workflow, e);
+        stats.failed.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[tokio::test]
+async fn watch_queues_every_file_test() {
+    use tokio::sync::{oneshot, Notify};
+
+    // Files are not skipped even with the default `OverlapPolicy::Skip`.
+    let spec: TriggerSpec =
+        serde_json::from_value(json!({ "workflow": "wf", "watch": { "path": "/tmp" } })).unwrap();
+    let stats = Arc::new(TriggerStats::default());
+    let (tx, rx) = mpsc::channel(1);
+
+    // The first run lasts until all files arrived.
+    let ran = Arc::new(Mutex::new(Vec::new()));
+    let release = Arc::new(Notify::new());
+    let (started_tx, started_rx) = oneshot::channel();
+    let mut started_tx = Some(started_tx);
+    let runner = {
+        let (ran, release) = (Arc::clone(&ran), Arc::clone(&release));
+        spawn_runner(Arc::clone(&stats), rx, move |args| {
+            ran.lock().unwrap().push(args["input_file"].clone());
+            let started_tx = started_tx.take();
+            let release = Arc::clone(&release);
+            async move {
+                if let Some(started_tx) = started_tx {
+                    started_tx.send(()).unwrap();
+                    release.notified().await;
+                }
+            }
+        })
+    };
+
+    let (files_tx, mut files) = mpsc::unbounded_channel();
+    files_tx.send("a".to_owned()).unwrap();
+    let watcher = {
+        let stats = Arc::clone(&stats);
+        tokio::spawn(async move {
+            watch_files(1, &spec, &stats, &tx, &mut files, "input_file").await;
+        })
+    };
+    started_rx.await.unwrap();
+    for file in ["b", "c", "d"] {
+        files_tx.send(file.to_owned()).unwrap();
+    }
+    drop(files_tx);
+    release.notify_one();
+
+    watcher.await.unwrap();
+    runner.await.unwrap();
+    assert_eq!(*ran.lock().unwrap(), ["a", "b", "c", "d"]);
+    assert_eq!(stats.fired.load(Ordering::Relaxed), 4);
+    assert_eq!(stats.skipped.load(Ordering::Relaxed), 0);
+    assert_eq!(stats.queued.load(Ordering::SeqCst), 0);
+}
@@ -0,0 +1,154 @@
+//! Sources of file events for directory-watch triggers. A host directory is
+//! watched with inotify, a directory inside a FAT fs image is polled since
+//! the image is only changed by services of running isolations.
+//!
+//! Watchers run on their own threads, and send the path of every new file
+//! until they are dropped.
+
+use std::{
+    collections::HashSet,
+    fs::File,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use anyhow::anyhow;
+use libasvisor::logger;
+use nix::{
+    errno::Errno,
+    sys::inotify::{AddWatchFlags, InitFlags, Inotify},
+};
+use tokio::sync::mpsc::UnboundedSender;
+
+/// How often a watcher checks if it was stopped, also the poll interval of
+/// host directories.
+const STOP_CHECK_INTERVAL: Duration = Duration::from_millis(200);
+
+pub struct Watcher {
+    stop: Arc<AtomicBool>,
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release)
+    }
+}
+
+/// Watch `dir` on the host, a file is reported once it is closed after
+/// writing or moved into `dir`.
+pub fn watch_host_dir(dir: PathBuf, tx: UnboundedSender<String>) -> anyhow::Result<Watcher> {
+    let inotify = Inotify::init(InitFlags::IN_CLOEXEC | InitFlags::IN_NONBLOCK)?;
+    inotify
+        .add_watch(
+            &dir,
+            AddWatchFlags::IN_CLOSE_WRITE | AddWatchFlags::IN_MOVED_TO,
+        )
+        .map_err(|e| anyhow!("watch {} failed: {}", dir.display(), e))?;
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let thread_stop = Arc::clone(&stop);
+    thread::Builder::new()
+        .name(format!("watch-{}", dir.display()))
+        .spawn(move || {
+            while !thread_stop.load(Ordering::Acquire) {
+                let events = match inotify.read_events() {
+                    Ok(events) => events,
+                    Err(Errno::EAGAIN) => {
+                        thread::sleep(STOP_CHECK_INTERVAL);
+                        continue;
+                    }
+                    Err(e) => {
+                        logger::error!("read inotify events of {} failed: {}", dir.display(), e);
+                        return;
+                    }
+                };
+                for event in events {
+                    if event.mask.contains(AddWatchFlags::IN_ISDIR) {
+                        continue;
+                    }
+                    if let Some(name) = event.name {
+                        let path = dir.join(name).to_string_lossy().into_owned();
+                        if tx.send(path).is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+        })?;
+
+    Ok(Watcher { stop })
+}
+
+fn list_image_dir(image: &PathBuf, dir: &str) -> anyhow::Result<HashSet<String>> {
+    // Opened read only, the file system is never changed by the daemon.
+    let fs = fatfs::FileSystem::new(File::open(image)?, fatfs::FsOptions::new())?;
+    let root = fs.root_dir();
+    let dir = dir.trim_matches('/');
+    let dir = if dir.is_empty() {
+        root
+    } else {
+        root.open_dir(dir)?
+    };
+
+    let mut files = HashSet::new();
+    for entry in dir.iter() {
+        let entry = entry?;
+        if entry.is_file() {
+            files.insert(entry.file_name());
+        }
+    }
+
+    Ok(files)
+}
+
+/// Poll `dir` inside the fs `image` every `interval`. Files that exist when
+/// the watch starts are not reported.
+pub fn watch_image_dir(
+    image: PathBuf,
+    dir: String,
+    interval: Duration,
+    tx: UnboundedSender<String>,
+) -> anyhow::Result<Watcher> {
+    let mut known = list_image_dir(&image, &dir)
+        .map_err(|e| anyhow!("list {} in {} failed: {}", dir, image.display(), e))?;
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let thread_stop = Arc::clone(&stop);
+    thread::Builder::new()
+        .name(format!("watch-image-{}", dir))
+        .spawn(move || {
+            let mut waited = Duration::ZERO;
+            while !thread_stop.load(Ordering::Acquire) {
+                thread::sleep(STOP_CHECK_INTERVAL);
+                waited += STOP_CHECK_INTERVAL;
+                if waited < interval {
+                    continue;
+                }
+                waited = Duration::ZERO;
+
+                // The image may be in the middle of a write by a service,
+                // just try again at the next poll.
+                let files = match list_image_dir(&image, &dir) {
+                    Ok(files) => files,
+                    Err(e) => {
+                        logger::warn!("list {} in {} failed: {}", dir, image.display(), e);
+                        continue;
+                    }
+                };
+                for name in files.difference(&known) {
+                    let path = format!("{}/{}", dir.trim_end_matches('/'), name);
+                    if tx.send(path).is_err() {
+                        return;
+                    }
+                }
+                known = files;
+            }
+        })?;
+
+    Ok(Watcher { stop })
+}
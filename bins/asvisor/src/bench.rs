@@ -0,0 +1,494 @@
+//! `asvisor bench`, a load generator that runs a workflow many times in a
+//! fresh isolation each. Runs go one after another by default. With a
+//! target rate or an arrival trace the load is open loop: a run starts at
+//! its arrival time whether or not earlier ones finished, and its latency
+//! counts from the arrival, so time spent waiting for a free slot shows up.
+
+use std::{
+    fs,
+    path::PathBuf,
+    sync::{mpsc, Arc},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+use libasvisor::{
+    isolation::{config::IsolationConfig, output::OutputCapture, Isolation},
+    IsolMetricSnapshot,
+};
+use serde_json::{json, Value};
+
+use crate::{
+    metrics_out::{self, percentile, MetricsOutArgs},
+    overrides::ConfigOverrides,
+    shutdown::Shutdown,
+};
+
+/// Same as the tokio runtime of `multi_workflow`, apps run on the thread of
+/// their isolation.
+const RUN_STACK_SIZE: usize = 8 * 1024 * 1024;
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum Arrival {
+    /// Arrivals evenly spaced at the target rate.
+    Uniform,
+    /// Exponentially distributed gaps with the target rate as mean.
+    Poisson,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct BenchArgs {
+    /// Config file of the workflow.
+    config: PathBuf,
+
+    /// Number of runs. With --trace, replay at most this many arrivals.
+    #[arg(short = 'n', long, default_value_t = 100)]
+    requests: usize,
+
+    /// Target arrival rate, in runs per second.
+    #[arg(long)]
+    rate: Option<f64>,
+
+    /// Arrival process of --rate.
+    #[arg(long, value_enum, default_value_t = Arrival::Poisson)]
+    arrival: Arrival,
+
+    /// Replay arrival times in milliseconds since the start. Either a json
+    /// object per line with an `invoke` list, like the `*.trace` files in
+    /// `baseline/alu-flask`, or a number per line.
+    #[arg(long, conflicts_with = "rate")]
+    trace: Option<PathBuf>,
+
+    /// Seed of the Poisson arrivals.
+    #[arg(long, default_value_t = 1)]
+    seed: u64,
+
+    /// Max runs at the same time, later arrivals wait for a slot. Each run
+    /// takes a linker namespace, of which glibc only has 16.
+    #[arg(long, default_value_t = 8)]
+    max_in_flight: usize,
+
+    /// Preload all modules before each run starts, so loading is not part
+    /// of the run.
+    #[arg(long, default_value_t = false)]
+    preload: bool,
+
+    /// Let apps print to stdout and stderr instead of discarding it.
+    #[arg(long, default_value_t = false)]
+    show_output: bool,
+
+    /// Print the report as json.
+    #[arg(long, default_value_t = false)]
+    json: bool,
+
+    #[command(flatten)]
+    overrides: ConfigOverrides,
+
+    #[command(flatten)]
+    metrics_out: MetricsOutArgs,
+}
+
+/// xorshift64*, enough to draw arrival gaps without another dependency.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self(seed.max(1))
+    }
+
+    /// Uniform in (0, 1].
+    fn next_f64(&mut self) -> f64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        let bits = self.0.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 11;
+        (bits + 1) as f64 / (1u64 << 53) as f64
+    }
+}
+
+fn parse_trace(path: &PathBuf) -> anyhow::Result<Vec<Duration>> {
+    let content = fs::read_to_string(path)?;
+    let mut offsets_ms = Vec::new();
+    for line in content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+    {
+        if let Ok(ms) = line.parse::<f64>() {
+            offsets_ms.push(ms);
+            continue;
+        }
+        let value: Value = serde_json::from_str(line)
+            .map_err(|e| anyhow!("invalid line in {}: {}", path.display(), e))?;
+        if let Some(invoke) = value["invoke"].as_array() {
+            offsets_ms.extend(invoke.iter().filter_map(Value::as_f64));
+        }
+    }
+    if offsets_ms.is_empty() {
+        return Err(anyhow!("no arrival in {}", path.display()));
+    }
+
+    offsets_ms.sort_by(f64::total_cmp);
+    let first = offsets_ms[0];
+    Ok(offsets_ms
+        .into_iter()
+        .map(|ms| Duration::from_secs_f64((ms - first) / 1000.))
+        .collect())
+}
+
+/// Arrival times since the start, `None` to run one after another.
+fn schedule(args: &BenchArgs) -> anyhow::Result<Option<Vec<Duration>>> {
+    if let Some(trace) = &args.trace {
+        let mut arrivals = parse_trace(trace)?;
+        arrivals.truncate(args.requests);
+        return Ok(Some(arrivals));
+    }
+    let Some(rate) = args.rate else {
+        return Ok(None);
+    };
+    if !rate.is_finite() || rate <= 0. {
+        return Err(anyhow!("rate should be positive"));
+    }
+
+    let mut rng = Rng::new(args.seed);
+    let mut at = 0.;
+    let arrivals = (0..args.requests)
+        .map(|_| {
+            let arrival = Duration::from_secs_f64(at);
+            at += match args.arrival {
+                Arrival::Uniform => 1. / rate,
+                Arrival::Poisson => -rng.next_f64().ln() / rate,
+            };
+            arrival
+        })
+        .collect();
+
+    Ok(Some(arrivals))
+}
+
+struct Sample {
+    latency: Duration,
+    error: Option<String>,
+    metric: IsolMetricSnapshot,
+}
+
+fn run_once(
+    config: &IsolationConfig,
+    preload: bool,
+    show_output: bool,
+) -> (Option<String>, IsolMetricSnapshot) {
+    let isol = Isolation::new(config);
+    if !show_output {
+        // Kept in memory and dropped with the isolation.
+        isol.capture_output(Arc::new(OutputCapture::new()))
+            .expect("new isolation already captures output?");
+    }
+    let result = if preload {
+        isol.preload(config).and_then(|_| isol.run())
+    } else {
+        isol.run()
+    };
+
+    (result.err().map(|e| e.to_string()), isol.metric.snapshot())
+}
+
+fn run_sequential(args: &BenchArgs, config: &IsolationConfig, shutdown: &Shutdown) -> Vec<Sample> {
+    let mut samples = Vec::with_capacity(args.requests);
+    for _ in 0..args.requests {
+        if shutdown.signal().is_some() {
+            break;
+        }
+        let start = Instant::now();
+        let (error, metric) = run_once(config, args.preload, args.show_output);
+        samples.push(Sample {
+            latency: start.elapsed(),
+            error,
+            metric,
+        });
+    }
+
+    samples
+}
+
+fn run_open_loop(
+    args: &BenchArgs,
+    config: &IsolationConfig,
+    arrivals: &[Duration],
+    shutdown: &Shutdown,
+) -> anyhow::Result<Vec<Sample>> {
+    // A slot is a token in the channel, taken before a run starts and put
+    // back once it ends.
+    let (slot_tx, slot_rx) = mpsc::sync_channel(args.max_in_flight.max(1));
+    for _ in 0..args.max_in_flight.max(1) {
+        slot_tx.send(())?;
+    }
+
+    let start = Instant::now();
+    let mut handles: Vec<JoinHandle<Sample>> = Vec::with_capacity(arrivals.len());
+    for (idx, arrival) in arrivals.iter().enumerate() {
+        thread::sleep(arrival.saturating_sub(start.elapsed()));
+        slot_rx.recv()?;
+        if shutdown.signal().is_some() {
+            break;
+        }
+
+        let config = config.clone();
+        let (preload, show_output) = (args.preload, args.show_output);
+        let scheduled_at = start + *arrival;
+        let slot_tx = slot_tx.clone();
+        let handle = thread::Builder::new()
+            .name(format!("bench-{}", idx))
+            .stack_size(RUN_STACK_SIZE)
+            .spawn(move || {
+                let (error, metric) = run_once(&config, preload, show_output);
+                let latency = scheduled_at.elapsed();
+                let _ = slot_tx.send(());
+                Sample {
+                    latency,
+                    error,
+                    metric,
+                }
+            })?;
+        handles.push(handle);
+    }
+
+    Ok(handles
+        .into_iter()
+        .map(|handle| handle.join().expect("bench thread panicked"))
+        .collect())
+}
+
+/// `null` if there is no value, e.g. all runs failed.
+fn latency_json(mut values: Vec<f64>) -> Value {
+    if values.is_empty() {
+        return Value::Null;
+    }
+    values.sort_by(f64::total_cmp);
+    let mean = values.iter().sum::<f64>() / values.len() as f64;
+    json!({
+        "mean": mean,
+        "p50": percentile(&values, 50.),
+        "p90": percentile(&values, 90.),
+        "p99": percentile(&values, 99.),
+        "p999": percentile(&values, 99.9),
+        "max": values[values.len() - 1],
+    })
+}
+
+/// Init and run durations of app threads, split by whether loading the
+/// module was on the thread's critical path.
+fn start_breakdown(samples: &[Sample]) -> Value {
+    let mut breakdown = json!({});
+    for cold in [true, false] {
+        let threads: Vec<_> = samples
+            .iter()
+            .flat_map(|sample| &sample.metric.services)
+            .flat_map(|svc| &svc.threads)
+            .filter(|thread| thread.cold == cold)
+            .collect();
+        let avg = |f: fn(&libasvisor::SvcThreadSnapshot) -> u128| {
+            threads.iter().map(|thread| f(thread)).sum::<u128>() as f64
+                / threads.len().max(1) as f64
+        };
+        breakdown[if cold { "cold" } else { "warm" }] = json!({
+            "threads": threads.len(),
+            "avg_init_dur(ms)": avg(|thread| thread.init_dur_ms),
+            "avg_run_dur(ms)": avg(|thread| thread.run_dur_ms),
+        });
+    }
+
+    breakdown
+}
+
+fn report(samples: &[Sample], elapsed: Duration) -> Value {
+    let succeeded: Vec<_> = samples.iter().filter(|s| s.error.is_none()).collect();
+    let mut errors: Vec<_> = samples.iter().filter_map(|s| s.error.clone()).collect();
+    errors.sort();
+    errors.dedup();
+
+    json!({
+        "requests": samples.len(),
+        "succeeded": succeeded.len(),
+        "failed": samples.len() - succeeded.len(),
+        "errors": errors,
+        "elapsed(s)": elapsed.as_secs_f64(),
+        "throughput(rps)": succeeded.len() as f64 / elapsed.as_secs_f64().max(f64::EPSILON),
+        "latency(ms)": latency_json(
+            succeeded.iter().map(|s| s.latency.as_secs_f64() * 1000.).collect()
+        ),
+        "isolation_dur(ms)": latency_json(
+            succeeded
+                .iter()
+                .filter_map(|s| s.metric.total_dur_us)
+                .map(|us| us as f64 / 1000.)
+                .collect()
+        ),
+        "load_service_num": succeeded.iter().map(|s| s.metric.load_service_num).sum::<u32>(),
+        "app_threads": start_breakdown(samples),
+    })
+}
+
+fn print_report(report: &Value) {
+    println!(
+        "requests: {}, succeeded: {}, failed: {}, elapsed: {:.3}s, throughput: {:.2} rps",
+        report["requests"],
+        report["succeeded"],
+        report["failed"],
+        report["elapsed(s)"].as_f64().unwrap_or_default(),
+        report["throughput(rps)"].as_f64().unwrap_or_default(),
+    );
+    for key in ["latency(ms)", "isolation_dur(ms)"] {
+        let stats = &report[key];
+        if stats.is_null() {
+            continue;
+        }
+        let field = |name: &str| stats[name].as_f64().unwrap_or_default();
+        println!(
+            "{:<18} mean {:>9.3}  p50 {:>9.3}  p90 {:>9.3}  p99 {:>9.3}  p999 {:>9.3}  max {:>9.3}",
+            key,
+            field("mean"),
+            field("p50"),
+            field("p90"),
+            field("p99"),
+            field("p999"),
+            field("max"),
+        );
+    }
+    for kind in ["cold", "warm"] {
+        let stats = &report["app_threads"][kind];
+        println!(
+            "{} app threads: {}, avg init {:.3}ms, avg run {:.3}ms",
+            kind,
+            stats["threads"],
+            stats["avg_init_dur(ms)"].as_f64().unwrap_or_default(),
+            stats["avg_run_dur(ms)"].as_f64().unwrap_or_default(),
+        );
+    }
+    for error in report["errors"].as_array().into_iter().flatten() {
+        println!("error: {}", error.as_str().unwrap_or_default());
+    }
+}
+
+pub fn run(args: BenchArgs, shutdown: &Shutdown) -> anyhow::Result<()> {
+    let mut config = IsolationConfig::from_file(args.config.clone())
+        .map_err(|e| anyhow!("read {} failed: {}", args.config.display(), e))?;
+    args.overrides.apply(&mut config)?;
+    let arrivals = schedule(&args)?;
+
+    let start = Instant::now();
+    let samples = match &arrivals {
+        Some(arrivals) => run_open_loop(&args, &config, arrivals, shutdown)?,
+        None => run_sequential(&args, &config, shutdown),
+    };
+    let report = report(&samples, start.elapsed());
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print_report(&report);
+    }
+    let workflow = metrics_out::workflow_name(&args.config);
+    args.metrics_out.write(
+        samples
+            .iter()
+            .map(|sample| (workflow.as_str(), &sample.metric)),
+    )
+}
+
+#[cfg(test)]
+fn bench_args(args: &[&str]) -> BenchArgs {
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        bench: BenchArgs,
+    }
+    let args = ["bench", "workflow.json"].iter().chain(args);
+    Cli::parse_from(args).bench
+}
+
+#[cfg(test)]
+fn write_trace(name: &str, content: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("asvisor-{}-{}", std::process::id(), name));
+    fs::write(&path, content).unwrap();
+    path
+}
+
+#[test]
+fn rng_test() {
+    let draw = |seed, n| {
+        let mut rng = Rng::new(seed);
+        (0..n).map(|_| rng.next_f64()).collect::<Vec<_>>()
+    };
+
+    assert_eq!(draw(7, 100), draw(7, 100));
+    assert_ne!(draw(7, 100), draw(8, 100));
+    // A zero state would only ever give zeros.
+    assert_eq!(draw(0, 100), draw(1, 100));
+
+    let values = draw(42, 100_000);
+    assert!(values.iter().all(|v| *v > 0. && *v <= 1.));
+    let mean = values.iter().sum::<f64>() / values.len() as f64;
+    assert!((mean - 0.5).abs() < 0.01, "mean {}", mean);
+}
+
+#[test]
+fn schedule_test() {
+    assert!(schedule(&bench_args(&[])).unwrap().is_none());
+    assert!(schedule(&bench_args(&["--rate", "0"])).is_err());
+
+    let arrivals = schedule(&bench_args(&[
+        "-n",
+        "5",
+        "--rate",
+        "100",
+        "--arrival",
+        "uniform",
+    ]))
+    .unwrap()
+    .unwrap();
+    let ms: Vec<u128> = arrivals.iter().map(Duration::as_millis).collect();
+    assert_eq!(ms, [0, 10, 20, 30, 40]);
+
+    let arrivals = schedule(&bench_args(&["-n", "10000", "--rate", "1000"]))
+        .unwrap()
+        .unwrap();
+    assert_eq!(arrivals.len(), 10000);
+    assert_eq!(arrivals[0], Duration::ZERO);
+    assert!(arrivals.windows(2).all(|w| w[0] <= w[1]));
+    let mean_gap = arrivals[9999].as_secs_f64() / 9999.;
+    assert!((mean_gap - 0.001).abs() < 0.00005, "mean gap {}", mean_gap);
+}
+
+#[test]
+fn parse_trace_test() {
+    let path = write_trace(
+        "trace",
+        "5\n\n{\"invoke\": [3, 1]}\n{\"app\": \"a\"}\n 2.5 \n",
+    );
+    let ms: Vec<f64> = parse_trace(&path)
+        .unwrap()
+        .iter()
+        .map(|d| d.as_secs_f64() * 1000.)
+        .collect();
+    assert_eq!(ms, [0., 1.5, 2., 4.]);
+
+    // --requests replays the first arrivals.
+    let args = bench_args(&["-n", "2", "--trace", path.to_str().unwrap()]);
+    assert_eq!(schedule(&args).unwrap().unwrap().len(), 2);
+    fs::remove_file(path).unwrap();
+
+    let path = write_trace("malformed", "1\nnot json\n");
+    let err = parse_trace(&path).unwrap_err().to_string();
+    assert!(err.starts_with("invalid line in"), "{}", err);
+    fs::remove_file(path).unwrap();
+
+    let path = write_trace("empty", "\n{\"invoke\": []}\n");
+    let err = parse_trace(&path).unwrap_err().to_string();
+    assert!(err.starts_with("no arrival in"), "{}", err);
+    fs::remove_file(path).unwrap();
+
+    assert!(parse_trace(&PathBuf::from("/nonexistent/trace")).is_err());
+}
@@ -0,0 +1,137 @@
+//! `asvisor ctl`, a client of the JSON-lines control socket of `asvisor-d`.
+
+use std::{
+    collections::BTreeMap,
+    io::{BufRead, BufReader, Write},
+    os::unix::net::UnixStream,
+    path::PathBuf,
+};
+
+use anyhow::anyhow;
+use serde_json::{json, Value};
+
+const DEFAULT_SOCKET: &str = "/tmp/asvisor-d.sock";
+
+fn parse_key_value(s: &str) -> Result<(String, String), String> {
+    s.split_once('=')
+        .map(|(k, v)| (k.to_owned(), v.to_owned()))
+        .ok_or_else(|| format!("'{}' should be key=value", s))
+}
+
+#[derive(clap::Args, Debug)]
+pub struct CtlArgs {
+    /// Control socket of the daemon, see `asvisor-d --control-socket`.
+    #[arg(long, default_value = DEFAULT_SOCKET)]
+    socket: PathBuf,
+
+    #[command(subcommand)]
+    command: CtlCommand,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum CtlCommand {
+    /// Submit a job of a workflow.
+    Submit {
+        isol_name: String,
+
+        /// Version of a registered workflow, the latest one if not set.
+        #[arg(long)]
+        version: Option<u32>,
+
+        /// key=value passed to every app, can be repeated.
+        #[arg(long = "arg", value_parser = parse_key_value)]
+        args: Vec<(String, String)>,
+    },
+    /// Show the status of a job.
+    Status { id: u64 },
+    /// Cancel a job.
+    Cancel { id: u64 },
+    /// List all jobs.
+    List,
+}
+
+fn request(socket: &PathBuf, method: &str, params: Value) -> anyhow::Result<Value> {
+    let stream = UnixStream::connect(socket)
+        .map_err(|e| anyhow!("connect {} failed: {}", socket.display(), e))?;
+    exchange(&stream, method, params)
+}
+
+/// Send a request on `stream` and read its response.
+fn exchange(mut stream: &UnixStream, method: &str, params: Value) -> anyhow::Result<Value> {
+    writeln!(
+        stream,
+        "{}",
+        json!({ "id": 1, "method": method, "params": params })
+    )?;
+
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    let response: Value = serde_json::from_str(&line)
+        .map_err(|e| anyhow!("invalid response '{}': {}", line.trim_end(), e))?;
+
+    if response["ok"].as_bool() == Some(true) {
+        Ok(response["result"].clone())
+    } else {
+        Err(anyhow!(
+            "{} (code {})",
+            response["error"].as_str().unwrap_or_default(),
+            response["code"]
+        ))
+    }
+}
+
+pub fn run(args: CtlArgs) -> anyhow::Result<()> {
+    let (method, params) = match args.command {
+        CtlCommand::Submit {
+            isol_name,
+            version,
+            args,
+        } => (
+            "submit",
+            json!({
+                "isol_name": isol_name,
+                "version": version,
+                "args": args.into_iter().collect::<BTreeMap<_, _>>(),
+            }),
+        ),
+        CtlCommand::Status { id } => ("status", json!({ "id": id })),
+        CtlCommand::Cancel { id } => ("cancel", json!({ "id": id })),
+        CtlCommand::List => ("list", Value::Null),
+    };
+
+    let result = request(&args.socket, method, params)?;
+    println!("{}", serde_json::to_string_pretty(&result)?);
+    Ok(())
+}
+
+#[test]
+fn ctl_exchange_test() {
+    let (client, server) = UnixStream::pair().unwrap();
+    let daemon = std::thread::spawn(move || {
+        let mut reader = BufReader::new(&server);
+        let mut requests = Vec::new();
+        for response in [
+            json!({ "id": 1, "ok": true, "result": [] }).to_string(),
+            json!({ "id": 1, "ok": false, "code": 404, "error": "job 7 not found" }).to_string(),
+            "not json".to_owned(),
+        ] {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            requests.push(serde_json::from_str::<Value>(&line).unwrap());
+            writeln!(&server, "{}", response).unwrap();
+        }
+        requests
+    });
+
+    assert_eq!(exchange(&client, "list", Value::Null).unwrap(), json!([]));
+    let err = exchange(&client, "status", json!({ "id": 7 })).unwrap_err();
+    assert_eq!(err.to_string(), "job 7 not found (code 404)");
+    let err = exchange(&client, "list", Value::Null).unwrap_err();
+    assert!(err.to_string().starts_with("invalid response 'not json'"));
+
+    let requests = daemon.join().unwrap();
+    assert_eq!(
+        requests[1],
+        json!({ "id": 1, "method": "status", "params": { "id": 7 } })
+    );
+}
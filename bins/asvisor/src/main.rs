@@ -1,12 +1,19 @@
-use std::{sync::Arc, thread::sleep, time::Duration};
+mod bench;
+mod ctl;
+mod metrics_out;
+mod overrides;
+mod shutdown;
 
-use clap::{arg, Parser};
+use std::{fs, iter::zip, path::PathBuf, sync::Arc, thread::sleep, time::Duration};
+
+use clap::{arg, Parser, Subcommand};
 use derive_more::Display;
 
 use libasvisor::{
     isolation::{config::IsolationConfig, get_isol, Isolation},
-    logger,
+    logger, trace,
 };
+use shutdown::Shutdown;
 
 #[derive(clap::ValueEnum, Clone, Display, Debug)]
 pub enum MetricOpt {
@@ -47,13 +54,48 @@ struct Args {
     #[arg(long, default_value_t = MetricOpt::None)]
     metrics: MetricOpt,
 
+    /// Count and time hostcalls of each app, shown in the isolation metrics.
+    #[arg(long, default_value_t = false)]
+    hostcall_stats: bool,
+
+    /// Write a Chrome Trace Event file of the execution, which can be opened
+    /// in Perfetto or chrome://tracing.
+    #[arg(long)]
+    trace: Option<PathBuf>,
+
+    /// Log every I/O, network and buffer hostcall of apps to stderr, with its
+    /// arguments and result, like strace.
+    #[arg(long, default_value_t = false)]
+    trace_hostcalls: bool,
+
     /// block after workflow execution.
     #[arg(short, long, default_value_t = false)]
     non_exit: bool,
+
+    /// Seconds to wait for running isolations on SIGINT or SIGTERM.
+    #[arg(long, default_value_t = 30)]
+    shutdown_timeout_secs: u64,
+
+    #[command(flatten)]
+    overrides: overrides::ConfigOverrides,
+
+    #[command(flatten)]
+    metrics_out: metrics_out::MetricsOutArgs,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Control a running asvisor-d through its control socket.
+    Ctl(ctl::CtlArgs),
+    /// Run a workflow many times and report latency percentiles.
+    Bench(bench::BenchArgs),
 }
 
 fn build_all_isol(args: &Args) -> Vec<Arc<Isolation>> {
-    let configs: Vec<_> = if !args.files.is_empty() {
+    let mut configs: Vec<_> = if !args.files.is_empty() {
         args.files
             .iter()
             .map(|f| {
@@ -70,8 +112,25 @@ fn build_all_isol(args: &Args) -> Vec<Arc<Isolation>> {
         ]
     };
 
+    for config in &mut configs {
+        args.overrides
+            .apply(config)
+            .unwrap_or_else(|e| panic!("apply config overrides failed, err={}", e))
+    }
+
     // info!("preload?:{}", args.preload);
     let isols: Vec<_> = configs.iter().map(Isolation::new).collect();
+    for isol in &isols {
+        if args.trace.is_some() {
+            isol.metric.enable_trace();
+        }
+        if args.hostcall_stats {
+            isol.metric.enable_hostcall_stats();
+        }
+        if args.trace_hostcalls {
+            isol.trace_hostcalls();
+        }
+    }
 
     if args.preload {
         for (idx, isol) in isols.iter().enumerate() {
@@ -90,7 +149,9 @@ fn msvisor_start(isol: &Arc<Isolation>) {
         log::error!("isol{} run failed. err={e:?}", isol.id);
         // if in debug mod, error will lead to exit.
         #[cfg(debug_assertions)]
-        panic!("isol{} run failed. err={e:?}", isol.id);
+        if !isol.is_cancelled() {
+            panic!("isol{} run failed. err={e:?}", isol.id);
+        }
     }
 }
 
@@ -117,6 +178,25 @@ async fn async_msvisor_start(isols: &[Arc<Isolation>]) {
 fn main() {
     logger::init();
     let args = Args::parse();
+    if let Some(Command::Ctl(ctl_args)) = args.command {
+        if let Err(e) = ctl::run(ctl_args) {
+            eprintln!("asvisor ctl: {}", e);
+            std::process::exit(1)
+        }
+        return;
+    }
+
+    let mut shutdown = Shutdown::install(Duration::from_secs(args.shutdown_timeout_secs))
+        .expect("install signal handler failed.");
+    if let Some(Command::Bench(bench_args)) = args.command {
+        let result = bench::run(bench_args, &shutdown);
+        shutdown.runs_finished();
+        if let Err(e) = result {
+            eprintln!("asvisor bench: {}", e);
+            std::process::exit(1)
+        }
+        return;
+    }
     let isols = build_all_isol(&args);
 
     #[cfg(feature = "multi_workflow")]
@@ -134,6 +214,7 @@ fn main() {
         }
         msvisor_start(&isols[0])
     }
+    shutdown.runs_finished();
 
     for isol in &isols {
         log::debug!(
@@ -147,9 +228,43 @@ fn main() {
         }
     }
 
+    let workflows: Vec<_> = if args.files.is_empty() {
+        vec![metrics_out::workflow_name("base_config.json".as_ref())]
+    } else {
+        args.files
+            .iter()
+            .map(|file| metrics_out::workflow_name(file.as_ref()))
+            .collect()
+    };
+    let snapshots: Vec<_> = isols.iter().map(|isol| isol.metric.snapshot()).collect();
+    if let Err(e) = args
+        .metrics_out
+        .write(zip(workflows.iter().map(String::as_str), &snapshots))
+    {
+        log::error!("{}", e)
+    }
+
+    if let Some(path) = &args.trace {
+        let events = isols
+            .iter()
+            .flat_map(|isol| isol.metric.trace_events(isol.id))
+            .collect();
+        let content =
+            serde_json::to_string(&trace::chrome_trace(events)).expect("serialize trace failed?");
+        fs::write(path, content)
+            .unwrap_or_else(|e| panic!("write trace {} failed, err={}", path.display(), e))
+    }
+
     if args.non_exit {
-        loop {
+        while shutdown.signal().is_none() {
             sleep(Duration::from_secs(1));
         }
     }
+
+    if let Some(signal) = shutdown.signal() {
+        // Drop isolations here, process::exit does not run destructors.
+        drop(isols);
+        log::info!("shutdown after {}", signal);
+        std::process::exit(128 + signal as i32)
+    }
 }
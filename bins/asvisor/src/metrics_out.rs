@@ -0,0 +1,358 @@
+//! `--metrics-out`, metrics of every isolation of a command in a file with a
+//! stable schema, so analysis does not have to scrape the `--metrics` json
+//! from logs. Each row is one of two kinds, with the same columns:
+//!
+//! - `sample`: one phase of one isolation, with `value_ms`.
+//! - `summary`: the samples of a phase aggregated across isolations, with
+//!   `count`, `mean_ms` and percentiles.
+//!
+//! Phases are `total` of an isolation, and `init` and `run` of each app
+//! thread of a service, split by whether loading the module was on the
+//! critical path of the thread (`cold`).
+//!
+//! Every row has the `schema_version` of its columns. The file is
+//! truncated unless `--metrics-append` is set, which adds the rows of
+//! several commands to one file.
+
+use std::{
+    collections::BTreeMap,
+    fs::OpenOptions,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::anyhow;
+use libasvisor::IsolMetricSnapshot;
+use serde_json::{json, Value};
+
+/// Bumped when `COLUMNS` change.
+const SCHEMA_VERSION: u32 = 1;
+
+/// Columns of both kinds of rows, in the order of the CSV header and of the
+/// keys in json lines. Columns a row does not have are `null` in json lines
+/// and empty in CSV.
+const COLUMNS: [&str; 16] = [
+    "schema_version",
+    "kind",
+    "workflow",
+    "isolation",
+    "scope",
+    "name",
+    "phase",
+    "thread",
+    "cold",
+    "value_ms",
+    "count",
+    "mean_ms",
+    "p50_ms",
+    "p90_ms",
+    "p99_ms",
+    "max_ms",
+];
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum MetricsFormat {
+    /// A json object per line.
+    Jsonl,
+    Csv,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct MetricsOutArgs {
+    /// Write the metrics of every isolation and their aggregation to a file.
+    #[arg(long)]
+    metrics_out: Option<PathBuf>,
+
+    /// Format of --metrics-out, csv for a `.csv` path and json lines
+    /// otherwise by default.
+    #[arg(long, value_enum, requires = "metrics_out")]
+    metrics_format: Option<MetricsFormat>,
+
+    /// Append to --metrics-out instead of truncating it. The CSV header is
+    /// only written to an empty file.
+    #[arg(long, default_value_t = false, requires = "metrics_out")]
+    metrics_append: bool,
+}
+
+/// Nearest-rank percentile of sorted, non-empty `values`.
+pub fn percentile(sorted: &[f64], p: f64) -> f64 {
+    let rank = (p / 100. * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// Identifies the samples aggregated into one summary row.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct PhaseKey {
+    workflow: String,
+    scope: &'static str,
+    name: String,
+    phase: &'static str,
+    cold: Option<bool>,
+}
+
+struct Sample {
+    key: PhaseKey,
+    isolation: usize,
+    thread: Option<usize>,
+    value_ms: f64,
+}
+
+impl Sample {
+    fn to_row(&self) -> Value {
+        json!({
+            "schema_version": SCHEMA_VERSION,
+            "kind": "sample",
+            "workflow": self.key.workflow,
+            "isolation": self.isolation,
+            "scope": self.key.scope,
+            "name": self.key.name,
+            "phase": self.key.phase,
+            "thread": self.thread,
+            "cold": self.key.cold,
+            "value_ms": self.value_ms,
+        })
+    }
+}
+
+fn samples(workflow: &str, isolation: usize, metric: &IsolMetricSnapshot) -> Vec<Sample> {
+    let mut samples = vec![];
+    if let Some(total_dur_us) = metric.total_dur_us {
+        samples.push(Sample {
+            key: PhaseKey {
+                workflow: workflow.to_owned(),
+                scope: "isolation",
+                name: workflow.to_owned(),
+                phase: "total",
+                cold: None,
+            },
+            isolation,
+            thread: None,
+            value_ms: total_dur_us as f64 / 1000.,
+        });
+    }
+    for svc in &metric.services {
+        for (idx, thread) in svc.threads.iter().enumerate() {
+            for (phase, dur_ms) in [("init", thread.init_dur_ms), ("run", thread.run_dur_ms)] {
+                samples.push(Sample {
+                    key: PhaseKey {
+                        workflow: workflow.to_owned(),
+                        scope: "service",
+                        name: svc.name.clone(),
+                        phase,
+                        cold: Some(thread.cold),
+                    },
+                    isolation,
+                    thread: Some(idx),
+                    value_ms: dur_ms as f64,
+                });
+            }
+        }
+    }
+
+    samples
+}
+
+fn summary_rows(samples: &[Sample]) -> Vec<Value> {
+    let mut phases: BTreeMap<&PhaseKey, Vec<f64>> = BTreeMap::new();
+    for sample in samples {
+        phases.entry(&sample.key).or_default().push(sample.value_ms)
+    }
+
+    phases
+        .into_iter()
+        .map(|(key, mut values)| {
+            values.sort_by(f64::total_cmp);
+            json!({
+                "schema_version": SCHEMA_VERSION,
+                "kind": "summary",
+                "workflow": key.workflow,
+                "scope": key.scope,
+                "name": key.name,
+                "phase": key.phase,
+                "cold": key.cold,
+                "count": values.len(),
+                "mean_ms": values.iter().sum::<f64>() / values.len() as f64,
+                "p50_ms": percentile(&values, 50.),
+                "p90_ms": percentile(&values, 90.),
+                "p99_ms": percentile(&values, 99.),
+                "max_ms": values[values.len() - 1],
+            })
+        })
+        .collect()
+}
+
+fn csv_field(value: &Value) -> String {
+    let field = match value {
+        Value::Null => return String::new(),
+        Value::String(s) => s.clone(),
+        value => value.to_string(),
+    };
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field
+    }
+}
+
+fn write_rows(
+    path: &Path,
+    format: MetricsFormat,
+    append: bool,
+    rows: &[Value],
+) -> anyhow::Result<()> {
+    let file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(append)
+        .truncate(!append)
+        .open(path)?;
+    let empty = file.metadata()?.len() == 0;
+    let mut out = BufWriter::new(file);
+    if let (MetricsFormat::Csv, true) = (format, empty) {
+        writeln!(out, "{}", COLUMNS.join(","))?;
+    }
+    for row in rows {
+        match format {
+            // Written by hand, `serde_json::Map` sorts its keys.
+            MetricsFormat::Jsonl => {
+                let fields: Vec<_> = COLUMNS
+                    .iter()
+                    .map(|column| format!("{}:{}", json!(column), row[column]))
+                    .collect();
+                writeln!(out, "{{{}}}", fields.join(","))?
+            }
+            MetricsFormat::Csv => {
+                let fields: Vec<_> = COLUMNS
+                    .iter()
+                    .map(|column| csv_field(&row[column]))
+                    .collect();
+                writeln!(out, "{}", fields.join(","))?
+            }
+        }
+    }
+    out.flush()?;
+
+    Ok(())
+}
+
+impl MetricsOutArgs {
+    /// Write the metrics of `isolations`, the workflow each ran and its
+    /// metrics, if --metrics-out is set.
+    pub fn write<'a>(
+        &self,
+        isolations: impl IntoIterator<Item = (&'a str, &'a IsolMetricSnapshot)>,
+    ) -> anyhow::Result<()> {
+        let Some(path) = &self.metrics_out else {
+            return Ok(());
+        };
+        let format = self.metrics_format.unwrap_or_else(|| {
+            match path.extension().and_then(|ext| ext.to_str()) {
+                Some("csv") => MetricsFormat::Csv,
+                _ => MetricsFormat::Jsonl,
+            }
+        });
+
+        let samples: Vec<_> = isolations
+            .into_iter()
+            .enumerate()
+            .flat_map(|(idx, (workflow, metric))| samples(workflow, idx, metric))
+            .collect();
+        let mut rows: Vec<_> = samples.iter().map(Sample::to_row).collect();
+        rows.extend(summary_rows(&samples));
+        write_rows(path, format, self.metrics_append, &rows)
+            .map_err(|e| anyhow!("write metrics to {} failed: {}", path.display(), e))
+    }
+}
+
+/// Name of the workflow of a config file in the metrics.
+pub fn workflow_name(config: &Path) -> String {
+    config
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| config.display().to_string())
+}
+
+#[test]
+fn percentile_test() {
+    let values: Vec<f64> = (1..=100).map(f64::from).collect();
+    assert_eq!(percentile(&values, 50.), 50.);
+    assert_eq!(percentile(&values, 99.), 99.);
+    assert_eq!(percentile(&values, 100.), 100.);
+    assert_eq!(percentile(&values, 0.), 1.);
+    assert_eq!(percentile(&[3.], 99.), 3.);
+}
+
+#[cfg(test)]
+fn sample(name: &str, thread: usize, value_ms: f64) -> Sample {
+    Sample {
+        key: PhaseKey {
+            workflow: "wf".to_owned(),
+            scope: "service",
+            name: name.to_owned(),
+            phase: "run",
+            cold: Some(false),
+        },
+        isolation: 0,
+        thread: Some(thread),
+        value_ms,
+    }
+}
+
+#[test]
+fn csv_field_test() {
+    assert_eq!(csv_field(&Value::Null), "");
+    assert_eq!(csv_field(&json!(1.5)), "1.5");
+    assert_eq!(csv_field(&json!(true)), "true");
+    assert_eq!(csv_field(&json!("plain")), "plain");
+    assert_eq!(csv_field(&json!("a,b")), "\"a,b\"");
+    assert_eq!(csv_field(&json!("say \"hi\"")), "\"say \"\"hi\"\"\"");
+    assert_eq!(csv_field(&json!("two\nlines")), "\"two\nlines\"");
+}
+
+#[test]
+fn summary_rows_test() {
+    let mut samples: Vec<_> = (1..=10).map(|v| sample("app", v, v as f64)).collect();
+    samples.push(sample("other", 0, 4.));
+
+    let rows = summary_rows(&samples);
+    assert_eq!(rows.len(), 2);
+    let row = &rows[0];
+    assert_eq!(row["schema_version"], SCHEMA_VERSION);
+    assert_eq!(row["kind"], "summary");
+    assert_eq!(row["name"], "app");
+    assert_eq!(row["count"], 10);
+    assert_eq!(row["mean_ms"], 5.5);
+    assert_eq!(row["p50_ms"], 5.);
+    assert_eq!(row["p90_ms"], 9.);
+    assert_eq!(row["p99_ms"], 10.);
+    assert_eq!(row["max_ms"], 10.);
+    assert_eq!(rows[1]["name"], "other");
+    assert_eq!(rows[1]["mean_ms"], 4.);
+}
+
+#[test]
+fn write_rows_test() {
+    let dir = std::env::temp_dir();
+    let rows = vec![sample("a,b", 1, 2.).to_row()];
+
+    let path = dir.join(format!("asvisor-{}-metrics.jsonl", std::process::id()));
+    write_rows(&path, MetricsFormat::Jsonl, false, &rows).unwrap();
+    let line = std::fs::read_to_string(&path).unwrap();
+    let keys: Vec<String> = line
+        .split(['{', ','])
+        .filter_map(|field| field.split_once("\":"))
+        .map(|(key, _)| key.trim_start_matches('"').to_owned())
+        .collect();
+    assert_eq!(keys, COLUMNS);
+    assert!(line.starts_with("{\"schema_version\":1,\"kind\":\"sample\","));
+    std::fs::remove_file(&path).unwrap();
+
+    let path = dir.join(format!("asvisor-{}-metrics.csv", std::process::id()));
+    std::fs::write(&path, "stale\n").unwrap();
+    write_rows(&path, MetricsFormat::Csv, false, &rows).unwrap();
+    write_rows(&path, MetricsFormat::Csv, true, &rows).unwrap();
+    let content = std::fs::read_to_string(&path).unwrap();
+    let row = "1,sample,wf,0,service,\"a,b\",run,1,false,2.0,,,,,,";
+    assert_eq!(content, format!("{}\n{row}\n{row}\n", COLUMNS.join(",")));
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,75 @@
+//! Args of apps set on the command line, applied on top of the loaded
+//! `IsolationConfig` so a variant of a workflow does not need another
+//! config file.
+
+use std::{collections::BTreeMap, fs, path::PathBuf};
+
+use anyhow::anyhow;
+use libasvisor::isolation::config::IsolationConfig;
+use serde_json::Value;
+
+fn parse_arg<T: std::str::FromStr>(s: &str) -> Result<(T, String, String), String> {
+    let (target, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("'{}' should be target.key=value", s))?;
+    let (target, key) = target
+        .split_once('.')
+        .ok_or_else(|| format!("'{}' should be target.key=value", s))?;
+    let target = target
+        .parse()
+        .map_err(|_| format!("invalid target '{}' in '{}'", target, s))?;
+
+    Ok((target, key.to_owned(), value.to_owned()))
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ConfigOverrides {
+    /// Set an arg of every instance of an app, as app.key=value. Can be
+    /// repeated.
+    #[arg(long = "set", value_parser = parse_arg::<String>)]
+    app_args: Vec<(String, String, String)>,
+
+    /// Set an arg of all apps of the Nth group, as N.key=value. Can be
+    /// repeated.
+    #[arg(long = "group-arg", value_parser = parse_arg::<usize>)]
+    group_args: Vec<(usize, String, String)>,
+
+    /// Json object of args passed to every app in groups. Values that are
+    /// not strings are passed as json.
+    #[arg(long)]
+    input: Option<PathBuf>,
+}
+
+impl ConfigOverrides {
+    fn input_args(&self) -> anyhow::Result<BTreeMap<String, String>> {
+        let Some(path) = &self.input else {
+            return Ok(BTreeMap::new());
+        };
+        let content = fs::read_to_string(path)
+            .map_err(|e| anyhow!("read input {} failed: {}", path.display(), e))?;
+        let input: BTreeMap<String, Value> = serde_json::from_str(&content)
+            .map_err(|e| anyhow!("input {} should be a json object: {}", path.display(), e))?;
+
+        Ok(input
+            .into_iter()
+            .map(|(key, value)| match value {
+                Value::String(s) => (key, s),
+                value => (key, value.to_string()),
+            })
+            .collect())
+    }
+
+    /// From the least to the most specific: `--input`, `--group-arg`, then
+    /// `--set`.
+    pub fn apply(&self, config: &mut IsolationConfig) -> anyhow::Result<()> {
+        config.extend_args(&self.input_args()?);
+        for (group, key, value) in &self.group_args {
+            config.set_group_arg(*group, key, value)?;
+        }
+        for (app, key, value) in &self.app_args {
+            config.set_app_arg(app, key, value)?;
+        }
+
+        Ok(())
+    }
+}
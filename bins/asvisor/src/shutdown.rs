@@ -0,0 +1,81 @@
+//! Shutdown on SIGINT and SIGTERM. The first signal cancels all isolations,
+//! which stop before their next app or group, and the process exits once
+//! they are dropped, so that services run their `drop` symbols. Isolations
+//! still running after the timeout, or a second signal, make the process
+//! exit at once without cleanup.
+
+use std::{
+    sync::{
+        atomic::{AtomicI32, Ordering},
+        mpsc::{self, RecvTimeoutError, Sender},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use libasvisor::{isolation, logger};
+use nix::sys::signal::{SigSet, Signal};
+
+pub struct Shutdown {
+    signal: Arc<AtomicI32>,
+    runs_finished: Option<Sender<()>>,
+}
+
+impl Shutdown {
+    /// Block the signals and wait for them on a dedicated thread. Must be
+    /// called before any other thread is spawned, threads inherit the mask.
+    pub fn install(timeout: Duration) -> anyhow::Result<Self> {
+        let mut signals = SigSet::empty();
+        signals.add(Signal::SIGINT);
+        signals.add(Signal::SIGTERM);
+        signals.thread_block()?;
+
+        let signal = Arc::new(AtomicI32::new(0));
+        let (runs_finished, finished_rx) = mpsc::channel::<()>();
+        let handler_signal = Arc::clone(&signal);
+        thread::Builder::new()
+            .name("shutdown".to_owned())
+            .spawn(move || {
+                let first = signals.wait().expect("wait for signals failed");
+                handler_signal.store(first as i32, Ordering::Release);
+                let cancelled = isolation::cancel_all();
+                logger::warn!(
+                    "received {}, cancel isolations {:?}, wait at most {:?}",
+                    first,
+                    cancelled,
+                    timeout
+                );
+
+                thread::spawn(move || {
+                    if finished_rx.recv_timeout(timeout) == Err(RecvTimeoutError::Timeout) {
+                        logger::error!(
+                            "isolations still running after {:?}, exit without cleanup",
+                            timeout
+                        );
+                        std::process::exit(128 + first as i32)
+                    }
+                });
+
+                let second = signals.wait().expect("wait for signals failed");
+                logger::error!("received {} again, exit without cleanup", second);
+                std::process::exit(128 + second as i32)
+            })?;
+
+        Ok(Self {
+            signal,
+            runs_finished: Some(runs_finished),
+        })
+    }
+
+    /// The signal received, if any.
+    pub fn signal(&self) -> Option<Signal> {
+        Signal::try_from(self.signal.load(Ordering::Acquire)).ok()
+    }
+
+    /// All runs returned, the timeout no longer applies to dropping the
+    /// isolations.
+    pub fn runs_finished(&mut self) {
+        self.runs_finished.take();
+    }
+}
@@ -31,6 +31,8 @@ fn main() {
         groups: vec![group],
         fs_image: Some("fs_images/fatfs.img".to_owned()),
         with_libos: None,
+        routes: Default::default(),
+        policies: Default::default(),
     };
 
     config1
@@ -6,14 +6,22 @@ use std::collections::HashMap;
 use lazy_static::lazy_static;
 use ext4_rs::{Ext4InodeRef, LinuxStat, InodeFileType};
 use as_hostcall::{
-    fdtab::{FdtabError, FdtabResult},
-    types::{DirEntry, Fd, OpenFlags, OpenMode, Size, Stat},
+    err::Errno as LibOSErrno,
+    fdtab::{readv_by, writev_by, FdtabError, FdtabResult},
+    types::{DirEntry, Fd, OpenFlags, OpenMode, Size, Stat, Whence},
 };
 use ext4_rs::*;
 
 #[cfg(feature = "ramdisk")]
 use crate::ramdisk::{BlockDriverOps, AxBlockDevice};
 
+fn ext4_err(op: &str, e: Ext4Error) -> FdtabError {
+    FdtabError::Ext4Error(
+        LibOSErrno::from_code_or_io(e.error() as i32),
+        format!("{} failed: {:?}", op, e),
+    )
+}
+
 // RamDisk设备包装器，将ramdisk设备适配到ext4的Device trait
 #[cfg(feature = "ramdisk")]
 struct RamDiskDevice {
@@ -122,20 +130,35 @@ impl FileWrapper {
     }
 
     fn read(&mut self, ext4: &Ext4, buf: &mut [u8]) -> Result<usize, Ext4Error> {
-        if !self.readable { return Err(Ext4Error::new(Errno::EACCES)); }
-        let len = ext4.read_at(self.inode_ref.inode_num, self.offset, buf)?;
+        let len = self.read_at(ext4, self.offset, buf)?;
         self.offset += len;
         Ok(len)
     }
 
     fn write(&mut self, ext4: &Ext4, buf: &[u8]) -> Result<usize, Ext4Error> {
-        if !self.writable { return Err(Ext4Error::new(Errno::EACCES)); }
-        let len = ext4.write_at(self.inode_ref.inode_num, self.offset, buf)?;
+        let len = self.write_at(ext4, self.offset, buf)?;
         self.offset += len;
         Ok(len)
     }
 
-    fn seek(&mut self, pos: usize) { self.offset = pos; }
+    fn read_at(&self, ext4: &Ext4, offset: usize, buf: &mut [u8]) -> Result<usize, Ext4Error> {
+        if !self.readable { return Err(Ext4Error::new(Errno::EACCES)); }
+        ext4.read_at(self.inode_ref.inode_num, offset, buf)
+    }
+
+    fn write_at(&self, ext4: &Ext4, offset: usize, buf: &[u8]) -> Result<usize, Ext4Error> {
+        if !self.writable { return Err(Ext4Error::new(Errno::EACCES)); }
+        ext4.write_at(self.inode_ref.inode_num, offset, buf)
+    }
+
+    fn seek(&mut self, offset: i64, whence: Whence) -> Result<u64, Ext4Error> {
+        let size = self.stat().st_size() as u64;
+        let pos = whence
+            .resolve(offset, self.offset as u64, size)
+            .ok_or(Ext4Error::new(Errno::EINVAL))?;
+        self.offset = pos as usize;
+        Ok(pos)
+    }
 
     fn stat(&self) -> LinuxStat {
         LinuxStat::from_inode_ref(&self.inode_ref)
@@ -254,13 +277,13 @@ pub fn open(path: &str, flags: OpenFlags, mode: OpenMode) -> FdtabResult<Fd> {
     #[cfg(feature = "lock")]
     let _lock = GLOBAL_LOCK.lock();
 
-    let ext4 = get_global_ext4().ok_or(FdtabError::FsError("global fs not initialized".to_string()))?;
+    let ext4 = get_global_ext4().ok_or(FdtabError::FsError(LibOSErrno::EIO, "global fs not initialized".to_string()))?;
     let readable = mode.contains(OpenMode::RD) || mode.contains(OpenMode::RDWR);
     let writable = mode.contains(OpenMode::WR) || mode.contains(OpenMode::RDWR);
     // 临时用"r"，可根据flags/mode完善
     let flags_str = "r";
     let inode_num = ext4.ext4_file_open(path, flags_str)
-        .map_err(|e| FdtabError::Ext4Error(format!("open failed: {:?}", e)))?;
+        .map_err(|e| ext4_err("open", e))?;
     let inode_ref = ext4.get_inode_ref(inode_num);
     let fd = alloc_fd(inode_ref, readable, writable);
     Ok(fd)
@@ -275,10 +298,10 @@ pub fn read(fd: Fd, buf: &mut [u8]) -> FdtabResult<Size> {
     let mut fdtab = FD_TABLE.lock();
     if let Some(file_wrapper) = fdtab.get_mut(&fd) {
         file_wrapper.read(get_global_ext4().as_ref().unwrap(), buf)
-            .map_err(|e| FdtabError::Ext4Error(format!("read failed: {:?}", e)))
+            .map_err(|e| ext4_err("read", e))
             .map(Size::from)
     } else {
-        Err(FdtabError::FsError("invalid fd".into()))
+        Err(FdtabError::NoExistFd(fd))
     }
 }
 
@@ -290,7 +313,7 @@ pub fn stat(fd: Fd) -> FdtabResult<Stat> {
         let stat = convert(&file_wrapper.stat());
         Ok(stat)
     } else {
-        Err(FdtabError::FsError("invalid fd".into()))
+        Err(FdtabError::NoExistFd(fd))
     }
 }
 
@@ -303,22 +326,58 @@ pub fn write(fd: Fd, buf: &[u8]) -> FdtabResult<Size> {
 
     // 处理标准输出和标准错误
     if fd == 1 || fd == 2 {
-        println!("ext4_apis::write: writing to stdout/stderr, calling libos!(stdout)");
-        let result = libos!(stdout(buf));
-        println!("ext4_apis::write: stdout result: {}", result);
+        let result = if fd == 2 {
+            libos!(stderr(buf))
+        } else {
+            libos!(stdout(buf))
+        };
         return Ok(Size::from(result));
     }
 
     let mut fdtab = FD_TABLE.lock();
     if let Some(file_wrapper) = fdtab.get_mut(&fd) {
         file_wrapper.write(get_global_ext4().as_ref().unwrap(), buf)
-            .map_err(|e| FdtabError::Ext4Error(format!("write failed: {:?}", e)))
+            .map_err(|e| ext4_err("write", e))
             .map(Size::from)
     } else {
-        Err(FdtabError::FsError("invalid fd".into()))
+        Err(FdtabError::NoExistFd(fd))
     }
 }
 
+// readv
+#[no_mangle]
+pub fn readv(fd: Fd, bufs: &mut [&mut [u8]]) -> FdtabResult<Size> {
+    readv_by(bufs, |buf| read(fd, buf))
+}
+
+// writev
+#[no_mangle]
+pub fn writev(fd: Fd, bufs: &[&[u8]]) -> FdtabResult<Size> {
+    writev_by(bufs, |buf| write(fd, buf))
+}
+
+// pread
+#[no_mangle]
+pub fn pread(fd: Fd, buf: &mut [u8], offset: u64) -> FdtabResult<Size> {
+    let _ = *INIT_DONE;
+    #[cfg(feature = "lock")]
+    let _lock = GLOBAL_LOCK.lock();
+    let file_wrapper = get_file_wrapper(fd).ok_or(FdtabError::NoExistFd(fd))?;
+    file_wrapper.read_at(get_global_ext4().as_ref().unwrap(), offset as usize, buf)
+        .map_err(|e| ext4_err("pread", e))
+}
+
+// pwrite
+#[no_mangle]
+pub fn pwrite(fd: Fd, buf: &[u8], offset: u64) -> FdtabResult<Size> {
+    let _ = *INIT_DONE;
+    #[cfg(feature = "lock")]
+    let _lock = GLOBAL_LOCK.lock();
+    let file_wrapper = get_file_wrapper(fd).ok_or(FdtabError::NoExistFd(fd))?;
+    file_wrapper.write_at(get_global_ext4().as_ref().unwrap(), offset as usize, buf)
+        .map_err(|e| ext4_err("pwrite", e))
+}
+
 // close
 #[no_mangle]
 pub fn close(fd: Fd) -> FdtabResult<()> {
@@ -331,16 +390,17 @@ pub fn close(fd: Fd) -> FdtabResult<()> {
 
 // lseek
 #[no_mangle]
-pub fn lseek(fd: Fd, pos: u32) -> FdtabResult<()> {
+pub fn lseek(fd: Fd, offset: i64, whence: Whence) -> FdtabResult<u64> {
     let _ = *INIT_DONE;
     #[cfg(feature = "lock")]
     let _lock = GLOBAL_LOCK.lock();
     let mut fdtab = FD_TABLE.lock();
     if let Some(file_wrapper) = fdtab.get_mut(&fd) {
-        file_wrapper.seek(pos as usize);
-        Ok(())
+        file_wrapper
+            .seek(offset, whence)
+            .map_err(|e| ext4_err("lseek", e))
     } else {
-        Err(FdtabError::FsError("invalid fd".into()))
+        Err(FdtabError::NoExistFd(fd))
     }
 }
 
@@ -350,9 +410,9 @@ pub fn readdir(path: &str) -> FdtabResult<Vec<DirEntry>> {
     let _ = *INIT_DONE;
     #[cfg(feature = "lock")]
     let _lock = GLOBAL_LOCK.lock();
-    let ext4 = get_global_ext4().ok_or(FdtabError::FsError("global fs not initialized".to_string()))?;
+    let ext4 = get_global_ext4().ok_or(FdtabError::FsError(LibOSErrno::EIO, "global fs not initialized".to_string()))?;
     let dir_inode_num = ext4.ext4_dir_open(path)
-        .map_err(|e| FdtabError::Ext4Error(format!("readdir failed: {:?}", e)))?;
+        .map_err(|e| ext4_err("readdir", e))?;
     let entries = ext4.ext4_dir_get_entries(dir_inode_num);
     let mut result = Vec::new();
     for entry in entries {
@@ -18,4 +18,26 @@ pub use ext4_apis::{
     lseek,
     stat,
     readdir,
+    readv,
+    writev,
+    pread,
+    pwrite,
 };
+
+as_hostcall::service_manifest! {
+    name: "ext4fdtab",
+    provides: [
+        "write",
+        "read",
+        "open",
+        "close",
+        "lseek",
+        "stat",
+        "readdir",
+        "readv",
+        "writev",
+        "pread",
+        "pwrite",
+    ],
+    depends: ["stdio"],
+}
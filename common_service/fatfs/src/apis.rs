@@ -1,14 +1,26 @@
 use std::{
-    io::{Read, Seek, Write},
+    io::{self, ErrorKind, Read, Seek, SeekFrom, Write},
     mem::ManuallyDrop,
 };
 
 use as_hostcall::{
+    err::Errno,
     fatfs::{FatfsError, FatfsResult},
-    types::{Fd, OpenFlags, Size, Stat, TimeSpec},
+    types::{Fd, OpenFlags, Size, Stat, TimeSpec, Whence},
 };
 
-use crate::{get_fs_ref, FTABLE};
+use crate::{get_fs_ref, File, FTABLE};
+
+fn io_err(e: io::Error) -> FatfsError {
+    let errno = match e.kind() {
+        ErrorKind::NotFound => Errno::ENOENT,
+        ErrorKind::AlreadyExists => Errno::EEXIST,
+        ErrorKind::PermissionDenied => Errno::EACCES,
+        ErrorKind::InvalidInput => Errno::EINVAL,
+        _ => e.raw_os_error().map_or(Errno::EIO, Errno::from_code_or_io),
+    };
+    FatfsError::HostIOErr(errno, e.to_string())
+}
 
 #[no_mangle]
 pub fn fatfs_read(fd: Fd, buf: &mut [u8]) -> FatfsResult<Size> {
@@ -27,7 +39,7 @@ pub fn fatfs_read(fd: Fd, buf: &mut [u8]) -> FatfsResult<Size> {
                 read_size += size;
                 buf = &mut buf[size..]
             }
-            Err(e) => Err(FatfsError::HostIOErr(e.to_string()))?,
+            Err(e) => Err(io_err(e))?,
         }
     }
 
@@ -35,17 +47,66 @@ pub fn fatfs_read(fd: Fd, buf: &mut [u8]) -> FatfsResult<Size> {
 }
 
 #[no_mangle]
-pub fn fatfs_seek(fd: Fd, pos: u32) -> FatfsResult<()> {
-    // println!("fatfs: try seek to {}", pos);
+pub fn fatfs_seek(fd: Fd, offset: i64, whence: Whence) -> FatfsResult<u64> {
     let mut table = FTABLE
         .lock()
         .map_err(|e| FatfsError::AcquireLockErr(e.to_string()))?;
 
     let f = table.get_file_mut(fd).ok_or(FatfsError::BadInputFd(fd))?;
-    f.seek(std::io::SeekFrom::Start(pos as u64))
-        .map_err(|e| FatfsError::HostIOErr(e.to_string()))?;
+    let pos = match whence {
+        Whence::Set => SeekFrom::Start(
+            u64::try_from(offset).map_err(|_| io_err(ErrorKind::InvalidInput.into()))?,
+        ),
+        Whence::Cur => SeekFrom::Current(offset),
+        Whence::End => SeekFrom::End(offset),
+    };
+
+    f.seek(pos).map_err(io_err)
+}
+
+/// Run `f` with the file at `offset`, then move it back to where it was.
+/// The table stays locked meanwhile, so no other hostcall sees the offset
+/// move.
+fn at_offset<T>(
+    fd: Fd,
+    offset: u64,
+    f: impl FnOnce(&mut File<'static>) -> io::Result<T>,
+) -> FatfsResult<T> {
+    let mut table = FTABLE
+        .lock()
+        .map_err(|e| FatfsError::AcquireLockErr(e.to_string()))?;
+
+    let file = table.get_file_mut(fd).ok_or(FatfsError::BadInputFd(fd))?;
 
-    Ok(())
+    let pos = file.stream_position().map_err(io_err)?;
+    file.seek(SeekFrom::Start(offset)).map_err(io_err)?;
+    let res = f(file);
+    file.seek(SeekFrom::Start(pos)).map_err(io_err)?;
+
+    res.map_err(io_err)
+}
+
+#[no_mangle]
+pub fn fatfs_pread(fd: Fd, buf: &mut [u8], offset: u64) -> FatfsResult<Size> {
+    at_offset(fd, offset, |file| {
+        let mut read_size = 0;
+        while read_size < buf.len() {
+            match file.read(&mut buf[read_size..])? {
+                0 => break,
+                size => read_size += size,
+            }
+        }
+        Ok(read_size)
+    })
+}
+
+#[no_mangle]
+pub fn fatfs_pwrite(fd: Fd, buf: &[u8], offset: u64) -> FatfsResult<Size> {
+    at_offset(fd, offset, |file| {
+        file.write_all(buf)?;
+        file.flush()?;
+        Ok(buf.len())
+    })
 }
 
 #[no_mangle]
@@ -56,9 +117,7 @@ pub fn fatfs_stat(fd: Fd) -> FatfsResult<Stat> {
 
     let f = table.get_file_mut(fd).ok_or(FatfsError::BadInputFd(fd))?;
 
-    let st_size = f
-        .stream_len()
-        .map_err(|e| FatfsError::HostIOErr(e.to_string()))? as Size;
+    let st_size = f.stream_len().map_err(io_err)? as Size;
     Ok(Stat {
         st_dev: 0,
         st_ino: 0,
@@ -100,7 +159,7 @@ pub fn fatfs_open(p: &str, flags: OpenFlags) -> FatfsResult<Fd> {
     } else {
         root_dir.open_file(p)
     }
-    .map_err(|_e| FatfsError::Unknown)?;
+    .map_err(io_err)?;
 
     let fd = {
         let file = ManuallyDrop::new(Box::new(file));
@@ -118,11 +177,11 @@ pub fn fatfs_write(fd: Fd, buf: &[u8]) -> FatfsResult<Size> {
 
     let file = table.get_file_mut(fd).ok_or(FatfsError::BadInputFd(fd))?;
 
-    let _ = || -> Result<(), std::io::Error> {
+    || -> Result<(), std::io::Error> {
         file.write_all(buf)?;
         file.flush()
     }()
-    .map_err(|e| FatfsError::HostIOErr(e.to_string()));
+    .map_err(io_err)?;
 
     Ok(buf.len())
 }
@@ -19,6 +19,21 @@ type FatfsHandle = usize;
 pub mod apis;
 pub mod drop_fs;
 
+as_hostcall::service_manifest! {
+    name: "fatfs",
+    provides: [
+        "fatfs_open",
+        "fatfs_write",
+        "fatfs_read",
+        "fatfs_close",
+        "fatfs_seek",
+        "fatfs_stat",
+        "fatfs_pread",
+        "fatfs_pwrite",
+    ],
+    depends: [],
+}
+
 fn get_fs_image_path() -> PathBuf {
     let image_path = match libos!(fs_image(as_std::init_context::isolation_ctx().isol_id)) {
         Some(s) => s,
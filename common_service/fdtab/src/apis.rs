@@ -2,8 +2,8 @@ use core::net::SocketAddrV4;
 
 use alloc::borrow::ToOwned;
 use as_hostcall::{
-    fdtab::{FdtabError, FdtabResult},
-    types::{Fd, OpenFlags, OpenMode, Size, SockFd, Stat},
+    fdtab::{readv_by, writev_by, FdtabError, FdtabResult},
+    types::{Fd, OpenFlags, OpenMode, Size, SockFd, Stat, Whence},
 };
 use as_std::libos::libos;
 
@@ -33,7 +33,8 @@ pub fn read(fd: Fd, buf: &mut [u8]) -> FdtabResult<Size> {
 pub fn write(fd: Fd, buf: &[u8]) -> FdtabResult<Size> {
     match fd {
         0 => Err(FdtabError::BadInputFd(">0".to_owned(), fd))?,
-        1 | 2 => return Ok(libos!(stdout(buf))),
+        1 => return Ok(libos!(stdout(buf))),
+        2 => return Ok(libos!(stderr(buf))),
         _ => {}
     }
 
@@ -52,23 +53,80 @@ pub fn write(fd: Fd, buf: &[u8]) -> FdtabResult<Size> {
 }
 
 #[no_mangle]
-pub fn lseek(fd: Fd, pos: u32) -> FdtabResult<()> {
+pub fn readv(fd: Fd, bufs: &mut [&mut [u8]]) -> FdtabResult<Size> {
+    readv_by(bufs, |buf| read(fd, buf))
+}
+
+#[no_mangle]
+pub fn writev(fd: Fd, bufs: &[&[u8]]) -> FdtabResult<Size> {
+    writev_by(bufs, |buf| write(fd, buf))
+}
+
+#[no_mangle]
+pub fn pread(fd: Fd, buf: &mut [u8], offset: u64) -> FdtabResult<Size> {
+    if let 0..=2 = fd {
+        Err(FdtabError::BadInputFd(">2".to_owned(), fd))?
+    }
+
+    FD_TABLE.with_file(fd, |file| -> FdtabResult<Size> {
+        let file = file.ok_or(FdtabError::NoExistFd(fd))?;
+
+        if !file.can_read() {
+            Err(FdtabError::NoReadPerm(fd))?
+        }
+
+        Ok(match file.src {
+            DataSource::FatFS(raw_fd) => libos!(fatfs_pread(raw_fd, buf, offset))?,
+            DataSource::Net(_) => Err(FdtabError::UndefinedOperation {
+                op: "pread".to_owned(),
+                fd,
+                fd_type: "Net".to_owned(),
+            })?,
+        })
+    })
+}
+
+#[no_mangle]
+pub fn pwrite(fd: Fd, buf: &[u8], offset: u64) -> FdtabResult<Size> {
     if let 0..=2 = fd {
         Err(FdtabError::BadInputFd(">2".to_owned(), fd))?
     }
 
-    FD_TABLE.with_file(fd, |file| -> FdtabResult<()> {
+    FD_TABLE.with_file(fd, |file| -> FdtabResult<Size> {
         let file = file.ok_or(FdtabError::NoExistFd(fd))?;
 
-        match file.src {
-            DataSource::FatFS(raw_fd) => libos!(fatfs_seek(raw_fd, pos))?,
+        if !file.can_write() {
+            Err(FdtabError::NoWritePerm(fd))?
+        }
+
+        Ok(match file.src {
+            DataSource::FatFS(raw_fd) => libos!(fatfs_pwrite(raw_fd, buf, offset))?,
+            DataSource::Net(_) => Err(FdtabError::UndefinedOperation {
+                op: "pwrite".to_owned(),
+                fd,
+                fd_type: "Net".to_owned(),
+            })?,
+        })
+    })
+}
+
+#[no_mangle]
+pub fn lseek(fd: Fd, offset: i64, whence: Whence) -> FdtabResult<u64> {
+    if let 0..=2 = fd {
+        Err(FdtabError::BadInputFd(">2".to_owned(), fd))?
+    }
+
+    FD_TABLE.with_file(fd, |file| -> FdtabResult<u64> {
+        let file = file.ok_or(FdtabError::NoExistFd(fd))?;
+
+        Ok(match file.src {
+            DataSource::FatFS(raw_fd) => libos!(fatfs_seek(raw_fd, offset, whence))?,
             DataSource::Net(_) => Err(FdtabError::UndefinedOperation {
                 op: "lseek".to_owned(),
                 fd,
                 fd_type: "Net".to_owned(),
             })?,
-        };
-        Ok(())
+        })
     })
 }
 
@@ -12,6 +12,26 @@ use as_std::{self, println};
 
 pub mod apis;
 
+as_hostcall::service_manifest! {
+    name: "fdtab",
+    provides: [
+        "write",
+        "read",
+        "open",
+        "close",
+        "lseek",
+        "stat",
+        "connect",
+        "bind",
+        "accept",
+        "readv",
+        "writev",
+        "pread",
+        "pwrite",
+    ],
+    depends: ["fatfs", "socket", "stdio"],
+}
+
 #[derive(Clone)]
 enum DataSource {
     FatFS(Fd),
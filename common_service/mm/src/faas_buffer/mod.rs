@@ -1,18 +1,26 @@
 extern crate alloc;
 
-use core::{alloc::Layout, ptr::NonNull};
+use core::{
+    alloc::Layout,
+    ptr::NonNull,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
 use alloc::{borrow::ToOwned, string::String};
 
 use linked_list_allocator::LockedHeap;
-use as_hostcall::{mm::MMResult, SERVICE_HEAP_SIZE};
+use as_hostcall::{
+    mm::{BufferStats, MMResult},
+    SERVICE_HEAP_SIZE,
+};
 
 use hashbrown::HashMap;
 use lazy_static::lazy_static;
 use spin::Mutex;
 
 lazy_static! {
-    static ref BUFFER_REGISTER: Mutex<HashMap<String, (usize, u64)>> = Mutex::new(HashMap::new());
+    static ref BUFFER_REGISTER: Mutex<HashMap<String, (usize, u64, usize)>> =
+        Mutex::new(HashMap::new());
     static ref BUFFER_ALLOCATOR: LockedHeap = unsafe {
         LockedHeap::new(
             as_std::init_context::ISOLATION_CTX.lock().heap_range.1 as *mut u8,
@@ -21,6 +29,10 @@ lazy_static! {
     };
 }
 
+static ALLOC_BYTES: AtomicUsize = AtomicUsize::new(0);
+static TRANSFERRED_BYTES: AtomicUsize = AtomicUsize::new(0);
+static IN_USE_BYTES: AtomicUsize = AtomicUsize::new(0);
+
 #[no_mangle]
 pub fn buffer_alloc(slot: &str, l: Layout, fingerprint: u64) -> MMResult<usize> {
     let addr = BUFFER_ALLOCATOR
@@ -32,7 +44,9 @@ pub fn buffer_alloc(slot: &str, l: Layout, fingerprint: u64) -> MMResult<usize>
 
     BUFFER_REGISTER
         .lock()
-        .insert(slot.to_owned(), (addr, fingerprint));
+        .insert(slot.to_owned(), (addr, fingerprint, l.size()));
+    ALLOC_BYTES.fetch_add(l.size(), Ordering::Relaxed);
+    IN_USE_BYTES.fetch_add(l.size(), Ordering::Relaxed);
 
     // as_std::println!("buffer_alloc layout={:?}, addr=0x{:x}", l, addr);
 
@@ -40,13 +54,22 @@ pub fn buffer_alloc(slot: &str, l: Layout, fingerprint: u64) -> MMResult<usize>
 }
 
 #[no_mangle]
-pub fn access_buffer(slot: &str) -> Option<(usize, u64)> {
+pub fn access_buffer(slot: &str, fingerprint: u64) -> Option<(usize, u64)> {
     let mut register = BUFFER_REGISTER.lock();
     // as_std::println!("buffer register: ");
     // for (k, v) in register.iter() {
     //     as_std::println!("  {}: {:?}", k, v);
     // }
-    register.remove(slot)
+    match register.get(slot) {
+        Some(&(addr, registered, _)) if registered != fingerprint => {
+            return Some((addr, registered))
+        }
+        _ => {}
+    }
+    register.remove(slot).map(|(addr, fingerprint, size)| {
+        TRANSFERRED_BYTES.fetch_add(size, Ordering::Relaxed);
+        (addr, fingerprint)
+    })
 }
 
 #[no_mangle]
@@ -56,4 +79,14 @@ pub fn buffer_dealloc(addr: usize, l: Layout) {
             .lock()
             .deallocate(NonNull::new(addr as *mut u8).unwrap(), l)
     }
+    IN_USE_BYTES.fetch_sub(l.size(), Ordering::Relaxed);
+}
+
+#[no_mangle]
+pub fn buffer_stats() -> BufferStats {
+    BufferStats {
+        alloc_bytes: ALLOC_BYTES.load(Ordering::Relaxed),
+        transferred_bytes: TRANSFERRED_BYTES.load(Ordering::Relaxed),
+        in_use_bytes: IN_USE_BYTES.load(Ordering::Relaxed),
+    }
 }
@@ -2,3 +2,16 @@
 
 pub mod faas_buffer;
 pub mod mmap;
+
+as_hostcall::service_manifest! {
+    name: "mm",
+    provides: [
+        "buffer_alloc",
+        "access_buffer",
+        "buffer_dealloc",
+        "libos_mmap",
+        "libos_munmap",
+        "libos_mprotect",
+    ],
+    depends: ["mmap_file_backend"],
+}
@@ -5,6 +5,7 @@ use core::alloc::Layout;
 use alloc::borrow::ToOwned;
 use libc::c_void;
 use as_hostcall::{
+    err::Errno,
     mm::{MMError, MMResult, ProtFlags},
     types::Fd,
 };
@@ -12,6 +13,12 @@ use as_std::libos::libos;
 
 const PAGE_SIZE: usize = 0x1000;
 
+/// An error of the libc call that just failed.
+fn libc_err(msg: &str) -> MMError {
+    let code = unsafe { *libc::__errno_location() };
+    MMError::LibcErr(Errno::from_code_or_io(code), msg.to_owned())
+}
+
 #[no_mangle]
 pub fn libos_mmap(addr: usize, length: usize, prot: ProtFlags, fd: Fd) -> MMResult<usize> {
     if length % PAGE_SIZE > 0 {
@@ -32,7 +39,7 @@ pub fn libos_mmap(addr: usize, length: usize, prot: ProtFlags, fd: Fd) -> MMResu
             }
         };
         if libc::munmap(addr, length) != 0 {
-            Err(MMError::LibcErr("munmap failed".to_owned()))?
+            Err(libc_err("munmap failed"))?
         }
         if libc::mmap(
             addr,
@@ -43,7 +50,7 @@ pub fn libos_mmap(addr: usize, length: usize, prot: ProtFlags, fd: Fd) -> MMResu
             0,
         ) != addr
         {
-            Err(MMError::LibcErr("mmap failed".to_owned()))?
+            Err(libc_err("mmap failed"))?
         }
 
         addr as usize
@@ -74,7 +81,7 @@ pub fn libos_munmap(mem_region: &mut [u8], file_based: bool) -> MMResult<()> {
             libc::PROT_READ | libc::PROT_WRITE,
         ) != 0
         {
-            Err(MMError::LibcErr("mprotect failed".to_owned()))?
+            Err(libc_err("mprotect failed"))?
         };
         alloc::alloc::dealloc(
             mem_region.as_mut_ptr(),
@@ -95,7 +102,7 @@ pub fn libos_mprotect(addr: usize, length: usize, prot: ProtFlags) -> MMResult<(
             trans_protflag(prot),
         ) != 0
         {
-            Err(MMError::LibcErr("mprotect failed".to_owned()))?
+            Err(libc_err("mprotect failed"))?
         };
     }
     Ok(())
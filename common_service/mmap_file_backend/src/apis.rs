@@ -73,6 +73,22 @@ pub fn file_page_fault_handler() {
     // println!("page fault handler exit.");
 }
 
+/// Unregister all regions, the fault handler thread exits once it sees no
+/// region left.
+#[no_mangle]
+pub fn stop_file_page_fault_handler() {
+    let _lock = acquire_register();
+    if read_notify_pipe().map(|pipe| pipe.is_none()).unwrap_or(true) {
+        return;
+    }
+
+    // The handler checks regions with the lock held before every wait, so
+    // it can not miss the clear.
+    if let Ok(mut regions) = acquire_regions_or_notify() {
+        regions.clear()
+    }
+}
+
 #[no_mangle]
 pub fn register_file_backend(mm_region: &mut [c_void], file_fd: Fd) -> MmapFileResult<()> {
     let _lock = acquire_register();
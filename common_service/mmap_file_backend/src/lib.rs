@@ -11,6 +11,7 @@ use nix::fcntl::{fcntl, FcntlArg, OFlag};
 use userfaultfd::{Event, Uffd};
 
 use as_hostcall::{
+    err::Errno,
     mmap_file_backend::{MmapFileErr, MmapFileResult},
     types::Fd,
 };
@@ -18,6 +19,12 @@ use as_std::libos::libos;
 
 pub mod apis;
 
+as_hostcall::service_manifest! {
+    name: "mmap_file_backend",
+    provides: ["register_file_backend", "unregister_file_backend", "file_page_fault_handler"],
+    depends: ["fdtab"],
+}
+
 #[repr(C, align(4096))]
 struct Page([u8; PAGE_SIZE]);
 
@@ -30,13 +37,13 @@ struct NotifyPipe {
 
 impl NotifyPipe {
     fn create() -> MmapFileResult<Self> {
-        let (recevier, sender) = || -> Result<(i32, i32), Box<dyn core::error::Error>> {
+        let (recevier, sender) = || -> nix::Result<(i32, i32)> {
             let (recevier, sender) = nix::unistd::pipe()?;
             fcntl(sender, FcntlArg::F_SETFL(OFlag::O_NONBLOCK))?;
 
             Ok((recevier, sender))
         }()
-        .map_err(|e| MmapFileErr::NixErr(e.to_string()))?;
+        .map_err(|e| MmapFileErr::NixErr(Errno::from_code_or_io(e as i32), e.to_string()))?;
 
         Ok(Self { recevier, sender })
     }
@@ -72,13 +79,14 @@ struct RegisterdMemRegion {
     src_fd: Fd,
 }
 
-fn read_at_offset(fd: Fd, offset: u32, page: *mut u8) -> MmapFileResult<()> {
+fn read_at_offset(fd: Fd, offset: u64, page: *mut u8) -> MmapFileResult<()> {
     // Copy the page pointed to by 'page' into the faulting region. Vary the contents that are
     // copied in, so that it is more obvious that each fault is handled separately.
     let page = unsafe { from_raw_parts_mut(page, PAGE_SIZE) };
 
-    libos!(lseek(fd, offset))?;
-    let _read_size = libos!(read(fd, page))?;
+    // Positional, so faults of regions mapping the same fd do not race on
+    // its offset.
+    let _read_size = libos!(pread(fd, page, offset))?;
     // println!(
     //     "src_file aligned_offset={}, read {} bytes",
     //     offset, read_size
@@ -100,7 +108,7 @@ fn do_page_fault(page: *mut u8, region: &RegisterdMemRegion) -> MmapFileResult<(
         // );
         let offset = addr as usize - region.start_addr;
         let aligned_offset = offset & (!PAGE_SIZE + 1);
-        read_at_offset(region.src_fd, aligned_offset as u32, page)?;
+        read_at_offset(region.src_fd, aligned_offset as u64, page)?;
 
         let dst = (addr as usize & !(PAGE_SIZE - 1)) as *mut c_void;
         let _copy = unsafe { uffd.copy(page as usize as *mut c_void, dst, PAGE_SIZE, true) }
@@ -10,3 +10,21 @@ extern crate alloc;
 pub mod sfs_apis;
 pub mod img2sfs;
 
+as_hostcall::service_manifest! {
+    name: "rcore_sfsfdtab",
+    provides: [
+        "write",
+        "read",
+        "open",
+        "close",
+        "lseek",
+        "stat",
+        "readdir",
+        "readv",
+        "writev",
+        "pread",
+        "pwrite",
+    ],
+    depends: ["stdio"],
+}
+
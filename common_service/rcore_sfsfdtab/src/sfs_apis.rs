@@ -2,17 +2,33 @@ use std::path::PathBuf;
 use as_std::libos::libos;
 use spin::Mutex;
 use alloc::sync::Arc;
-use rcore_fs::vfs::{FileSystem, FileType};
+use rcore_fs::vfs::{FileSystem, FileType, FsError};
 use rcore_fs_mountfs::MountFS;
 use rcore_fs_sfs::SimpleFileSystem;
 use rcore_fs::dev::Device;
 use as_hostcall::{
-    fdtab::{FdtabError, FdtabResult},
-    types::{DirEntry, Fd, OpenFlags, OpenMode, Size, Stat},
+    err::Errno,
+    fdtab::{readv_by, writev_by, FdtabError, FdtabResult},
+    types::{DirEntry, Fd, OpenFlags, OpenMode, Size, Stat, Whence},
 };
 use crate::img2sfs::img_to_sfs_bridge;
 use alloc::collections::BTreeMap;
 
+fn fs_err(op: &str, e: FsError) -> FdtabError {
+    let errno = match e {
+        FsError::NotSupported => Errno::EOPNOTSUPP,
+        FsError::NotFile | FsError::IsDir => Errno::EISDIR,
+        FsError::NotDir => Errno::ENOTDIR,
+        FsError::EntryNotFound => Errno::ENOENT,
+        FsError::EntryExist => Errno::EEXIST,
+        FsError::InvalidParam => Errno::EINVAL,
+        FsError::NoDeviceSpace => Errno::ENOSPC,
+        FsError::DirNotEmpty => Errno::ENOTEMPTY,
+        _ => Errno::EIO,
+    };
+    FdtabError::FsError(errno, format!("{} failed: {:?}", op, e))
+}
+
 #[cfg(feature = "ramdisk")]
 use crate::ramdisk::{BlockDriverOps, AxBlockDevice};
 
@@ -108,12 +124,23 @@ impl FileWrapper {
     }
 
     fn read(&mut self, buf: &mut [u8]) -> Result<usize, rcore_fs::vfs::FsError> {
+        let len = self.read_at(self.offset, buf)?;
+        self.offset += len;
+        Ok(len)
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, rcore_fs::vfs::FsError> {
         if !self.readable {
             return Err(rcore_fs::vfs::FsError::InvalidParam);
         }
-        let len = self.inode.read_at(self.offset, buf)?;
-        self.offset += len;
-        Ok(len)
+        self.inode.read_at(offset, buf)
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize, rcore_fs::vfs::FsError> {
+        if !self.writable {
+            return Err(rcore_fs::vfs::FsError::InvalidParam);
+        }
+        self.inode.write_at(offset, buf)
     }
 
     fn write(&mut self, buf: &[u8]) -> Result<usize, rcore_fs::vfs::FsError> {
@@ -128,9 +155,13 @@ impl FileWrapper {
         Ok(len)
     }
 
-    fn seek(&mut self, pos: usize) -> Result<(), rcore_fs::vfs::FsError> {
-        self.offset = pos;
-        Ok(())
+    fn seek(&mut self, offset: i64, whence: Whence) -> Result<u64, rcore_fs::vfs::FsError> {
+        let size = self.inode.metadata()?.size as u64;
+        let pos = whence
+            .resolve(offset, self.offset as u64, size)
+            .ok_or(rcore_fs::vfs::FsError::InvalidParam)?;
+        self.offset = pos as usize;
+        Ok(pos)
     }
 
     fn metadata(&self) -> Result<rcore_fs::vfs::Metadata, rcore_fs::vfs::FsError> {
@@ -321,7 +352,7 @@ pub fn open(path: &str, flags: OpenFlags, mode: OpenMode) -> FdtabResult<Fd> {
         Some(fs) => fs.root_inode(),
         None => {
             println!("GLOBAL_FS 未初始化1！");
-            return Err(FdtabError::FsError("global fs not initialized".to_string()));
+            return Err(FdtabError::FsError(Errno::EIO, "global fs not initialized".to_string()));
         }
     };
     
@@ -342,17 +373,17 @@ pub fn open(path: &str, flags: OpenFlags, mode: OpenMode) -> FdtabResult<Fd> {
             println!("open: allocated fd {}", fd);
             Ok(fd)
         },
-        Err(_) => {
+        Err(e) => {
             if flags.contains(OpenFlags::O_CREAT) {
                 // 只支持根目录下创建
                 let name = path.trim_start_matches('/');
                 let inode = root.create(name, FileType::File, 0o644)
-                    .map_err(|e| FdtabError::FsError(format!("create failed: {:?}", e)))?;
+                    .map_err(|e| fs_err("create", e))?;
                 let fd = alloc_fd(inode, readable, writable);
                 Ok(fd)
             } else {
                 println!("file not found");
-                Err(FdtabError::FsError("invalid fd".into()))
+                Err(fs_err(&format!("open {}", path), e))
             }
         }
     }
@@ -366,10 +397,10 @@ pub fn read(fd: Fd, buf: &mut [u8]) -> FdtabResult<Size> {
     let mut fdtab = FD_TABLE.lock();
     if let Some(file_wrapper) = fdtab.get_mut(&fd) {
         file_wrapper.read(buf)
-            .map_err(|e| FdtabError::FsError(format!("read failed: {:?}", e)))
+            .map_err(|e| fs_err("read", e))
             .map(|len| Size::from(len))
     } else {
-        Err(FdtabError::FsError("invalid fd".into()))
+        Err(FdtabError::NoExistFd(fd))
     }
 }
 
@@ -378,29 +409,60 @@ pub fn write(fd: Fd, buf: &[u8]) -> FdtabResult<Size> {
     let _ = *INIT_DONE;
     #[cfg(feature = "lock")]
     let _lock = GLOBAL_LOCK.lock();
-    println!("sfs_apis::write: starting write for fd {}", fd);
     
     // 处理标准输出和标准错误
     if fd == 1 || fd == 2 {
-        println!("sfs_apis::write: writing to stdout/stderr, calling libos!(stdout)");
-        let result = libos!(stdout(buf));
-        println!("sfs_apis::write: stdout result: {}", result);
+        let result = if fd == 2 {
+            libos!(stderr(buf))
+        } else {
+            libos!(stdout(buf))
+        };
         return Ok(Size::from(result));
     }
     
     let mut fdtab = FD_TABLE.lock();
-    println!("sfs_apis::write: FD_TABLE has {} entries", fdtab.len());
-    println!("sfs_apis::write: FD_TABLE keys: {:?}", fdtab.keys().collect::<Vec<_>>());
     if let Some(file_wrapper) = fdtab.get_mut(&fd) {
-        println!("sfs_apis::write: found file_wrapper for fd {}", fd);
-        let result = file_wrapper.write(buf)
-            .map_err(|e| FdtabError::FsError(format!("write failed: {:?}", e)))
-            .map(|len| Size::from(len));
-        println!("sfs_apis::write: write result: {:?}", result);
-        result
+        file_wrapper.write(buf)
+            .map_err(|e| fs_err("write", e))
+            .map(|len| Size::from(len))
     } else {
-        println!("sfs_apis::write: invalid fd {}", fd);
-        Err(FdtabError::FsError("invalid fd".into()))
+        Err(FdtabError::NoExistFd(fd))
+    }
+}
+
+#[no_mangle]
+pub fn readv(fd: Fd, bufs: &mut [&mut [u8]]) -> FdtabResult<Size> {
+    readv_by(bufs, |buf| read(fd, buf))
+}
+
+#[no_mangle]
+pub fn writev(fd: Fd, bufs: &[&[u8]]) -> FdtabResult<Size> {
+    writev_by(bufs, |buf| write(fd, buf))
+}
+
+#[no_mangle]
+pub fn pread(fd: Fd, buf: &mut [u8], offset: u64) -> FdtabResult<Size> {
+    let _ = *INIT_DONE;
+    #[cfg(feature = "lock")]
+    let _lock = GLOBAL_LOCK.lock();
+    match get_file_wrapper(fd) {
+        Some(file_wrapper) => file_wrapper
+            .read_at(offset as usize, buf)
+            .map_err(|e| fs_err("pread", e)),
+        None => Err(FdtabError::NoExistFd(fd)),
+    }
+}
+
+#[no_mangle]
+pub fn pwrite(fd: Fd, buf: &[u8], offset: u64) -> FdtabResult<Size> {
+    let _ = *INIT_DONE;
+    #[cfg(feature = "lock")]
+    let _lock = GLOBAL_LOCK.lock();
+    match get_file_wrapper(fd) {
+        Some(file_wrapper) => file_wrapper
+            .write_at(offset as usize, buf)
+            .map_err(|e| fs_err("pwrite", e)),
+        None => Err(FdtabError::NoExistFd(fd)),
     }
 }
 
@@ -414,16 +476,17 @@ pub fn close(fd: Fd) -> FdtabResult<()> {
 }
 
 #[no_mangle]
-pub fn lseek(fd: Fd, pos: u32) -> FdtabResult<()> {
+pub fn lseek(fd: Fd, offset: i64, whence: Whence) -> FdtabResult<u64> {
     let _ = *INIT_DONE;
     #[cfg(feature = "lock")]
     let _lock = GLOBAL_LOCK.lock();
     let mut fdtab = FD_TABLE.lock();
     if let Some(file_wrapper) = fdtab.get_mut(&fd) {
-        file_wrapper.seek(pos as usize)
-            .map_err(|e| FdtabError::FsError(format!("lseek failed: {:?}", e)))
+        file_wrapper
+            .seek(offset, whence)
+            .map_err(|e| fs_err("lseek", e))
     } else {
-        Err(FdtabError::FsError("invalid fd".into()))
+        Err(FdtabError::NoExistFd(fd))
     }
 }
 
@@ -436,9 +499,9 @@ pub fn stat(fd: Fd) -> FdtabResult<Stat> {
         Some(file_wrapper) => {
             file_wrapper.metadata()
                 .map(|m| convert(&m))
-                .map_err(|e| FdtabError::FsError(format!("stat failed: {:?}", e)))
+                .map_err(|e| fs_err("stat", e))
         },
-        None => Err(FdtabError::FsError("invalid fd".into())),
+        None => Err(FdtabError::NoExistFd(fd)),
     }
 }
 
@@ -451,7 +514,7 @@ pub fn readdir(path: &str) -> FdtabResult<Vec<DirEntry>> {
         Some(fs) => fs.root_inode(),
         None => {
             println!("GLOBAL_FS 未初始化2！");
-            return Err(FdtabError::FsError("global fs not initialized".to_string()));
+            return Err(FdtabError::FsError(Errno::EIO, "global fs not initialized".to_string()));
         }
     };
     let inode = if path.starts_with('/') {
@@ -477,10 +540,10 @@ pub fn readdir(path: &str) -> FdtabResult<Vec<DirEntry>> {
                     }
                     Ok(entries)
                 }
-                Err(e) => Err(FdtabError::FsError(format!("readdir failed: {:?}", e))),
+                Err(e) => Err(fs_err("readdir", e)),
             }
         }
-        Err(e) => Err(FdtabError::FsError(format!("readdir failed: {:?}", e))),
+        Err(e) => Err(fs_err("readdir", e)),
     }
 }
 
@@ -5,6 +5,24 @@ pub mod rc_apis;
 
 pub mod img2ramfs;
 
+as_hostcall::service_manifest! {
+    name: "rcorefdtab",
+    provides: [
+        "write",
+        "read",
+        "open",
+        "close",
+        "lseek",
+        "stat",
+        "readdir",
+        "readv",
+        "writev",
+        "pread",
+        "pwrite",
+    ],
+    depends: ["stdio"],
+}
+
 // #[cfg(test)]
 // mod tests {
 //     use super::*;
@@ -4,15 +4,16 @@ use std::path::PathBuf;
 
 use alloc::vec;
 use as_std::libos::libos;
+use axerrno::LinuxError;
 use spin::Mutex;
 
 use crate::{
-    fd_ops::{close_file_like, get_file_like},
+    fd_ops::{close_file_like, get_file_like, rux_err},
     fs,
 };
 use as_hostcall::{
-    fdtab::{FdtabError, FdtabResult},
-    types::{DirEntry, Fd, OpenFlags, OpenMode, Size, Stat, TimeSpec},
+    fdtab::{readv_by, writev_by, FdtabResult},
+    types::{DirEntry, Fd, OpenFlags, OpenMode, Size, Stat, TimeSpec, Whence},
 };
 
 #[cfg(feature = "use-ramdisk")]
@@ -123,7 +124,7 @@ pub fn open(path: &str, flags: OpenFlags, mode: OpenMode) -> FdtabResult<Fd> {
     }
 
     let file = ruxfs::fops::File::open(path, &options)
-        .map_err(|e| FdtabError::RuxfsError(format!("open {} failed: {}", path, e)))?;
+        .map_err(|e| rux_err(&format!("open {}", path), e))?;
 
     let result = fs::File::new(file).add_to_fd_table()?;
     #[cfg(feature = "log")]
@@ -143,7 +144,7 @@ pub fn write(fd: Fd, buf: &[u8]) -> FdtabResult<Size> {
 
     let result = f
         .write(buf)
-        .map_err(|e| FdtabError::RuxfsError(e.to_string()));
+        .map_err(|e| rux_err("write", e));
 
     #[cfg(feature = "use-ramdisk")]
     f.flush().unwrap();
@@ -159,7 +160,49 @@ pub fn read(fd: Fd, buf: &mut [u8]) -> FdtabResult<Size> {
 
     get_file_like(fd)?
         .read(buf)
-        .map_err(|e| FdtabError::RuxfsError(e.to_string()))
+        .map_err(|e| rux_err("read", e))
+}
+
+#[no_mangle]
+pub fn readv(fd: Fd, bufs: &mut [&mut [u8]]) -> FdtabResult<Size> {
+    readv_by(bufs, |buf| read(fd, buf))
+}
+
+#[no_mangle]
+pub fn writev(fd: Fd, bufs: &[&[u8]]) -> FdtabResult<Size> {
+    writev_by(bufs, |buf| write(fd, buf))
+}
+
+#[no_mangle]
+pub fn pread(fd: Fd, buf: &mut [u8], offset: u64) -> FdtabResult<Size> {
+    let _exec = *MUST_EXIC;
+    #[cfg(feature = "lock")]
+    let _lock = GLOBAL_LOCK.lock();
+
+    fs::File::from_fd(fd)?
+        .inner
+        .lock()
+        .read_at(offset, buf)
+        .map_err(|e| rux_err("pread", e))
+}
+
+#[no_mangle]
+pub fn pwrite(fd: Fd, buf: &[u8], offset: u64) -> FdtabResult<Size> {
+    let _exec = *MUST_EXIC;
+    #[cfg(feature = "lock")]
+    let _lock = GLOBAL_LOCK.lock();
+
+    let f = fs::File::from_fd(fd)?;
+    let result = f
+        .inner
+        .lock()
+        .write_at(offset, buf)
+        .map_err(|e| rux_err("pwrite", e));
+
+    #[cfg(feature = "use-ramdisk")]
+    f.flush().unwrap();
+
+    result
 }
 
 #[no_mangle]
@@ -172,18 +215,24 @@ pub fn close(fd: Fd) -> FdtabResult<()> {
 }
 
 #[no_mangle]
-pub fn lseek(fd: Fd, pos: u32) -> FdtabResult<()> {
+pub fn lseek(fd: Fd, offset: i64, whence: Whence) -> FdtabResult<u64> {
     let _exec = *MUST_EXIC;
     #[cfg(feature = "lock")]
     let _lock = GLOBAL_LOCK.lock();
 
+    let pos = match whence {
+        Whence::Set => axio::SeekFrom::Start(
+            u64::try_from(offset).map_err(|_| rux_err("lseek", LinuxError::EINVAL))?,
+        ),
+        Whence::Cur => axio::SeekFrom::Current(offset),
+        Whence::End => axio::SeekFrom::End(offset),
+    };
+
     fs::File::from_fd(fd)?
         .inner
         .lock()
-        .seek(axio::SeekFrom::Start(pos as u64))
-        .map_err(|e| FdtabError::RuxfsError(e.to_string()))?;
-
-    Ok(())
+        .seek(pos)
+        .map_err(|e| rux_err("lseek", e))
 }
 
 #[no_mangle]
@@ -194,7 +243,7 @@ pub fn stat(fd: Fd) -> FdtabResult<Stat> {
 
     let stat = fs::File::from_fd(fd)?
         .stat()
-        .map_err(|e| FdtabError::RuxfsError(e.to_string()))?;
+        .map_err(|e| rux_err("stat", e))?;
     let res = convert(stat);
     // #[cfg(feature = "log")]
     // println!("[DEBUG] stat fd: {:?}, stat: {:?}", fd, res);
@@ -1,20 +1,24 @@
+use core::fmt::Display;
+
 use alloc::sync::Arc;
 use as_hostcall::{
+    err::Errno,
     fdtab::{FdtabError, FdtabResult},
     types::Fd,
 };
+use axerrno::LinuxError;
 use ruxfdtable::{FileLike, RuxStat, RuxTimeSpec, FD_TABLE};
 
 use crate::{
     ctypes,
-    stdio::{stdin, stdout},
+    stdio::{stderr, stdin, stdout},
 };
 
 lazy_static::lazy_static! {
     static ref MUST_EXEC: usize = {
         FD_TABLE.write().add_at(0, Arc::new(stdin()) as _).unwrap(); // stdin
         FD_TABLE.write().add_at(1, Arc::new(stdout()) as _).unwrap(); // stdout
-        FD_TABLE.write().add_at(2, Arc::new(stdout()) as _).unwrap(); // stderr
+        FD_TABLE.write().add_at(2, Arc::new(stderr()) as _).unwrap(); // stderr
         0
     };
 }
@@ -59,16 +63,24 @@ pub fn get_file_like(fd: Fd) -> FdtabResult<Arc<dyn FileLike>> {
         .ok_or(FdtabError::NoExistFd(fd))
 }
 
+/// An error of ruxfs, with the errno it stands for.
+pub fn rux_err<E: Into<LinuxError> + Display>(op: &str, e: E) -> FdtabError {
+    let msg = format!("{} failed: {}", op, e);
+    FdtabError::RuxfsError(Errno::from_code_or_io(e.into().code()), msg)
+}
+
 pub fn add_file_like(f: Arc<dyn FileLike>) -> FdtabResult<Fd> {
     let _exec = *MUST_EXEC;
-    let err_msg = FdtabError::RuxfsError("add_file_like failed".to_owned());
+    let err_msg = FdtabError::RuxfsError(Errno::EMFILE, "add_file_like failed".to_owned());
     Ok(FD_TABLE.write().add(f).ok_or(err_msg)? as u32)
 }
 
 pub fn close_file_like(fd: Fd) -> FdtabResult<()> {
     let _exec = *MUST_EXEC;
-    let err_msg = FdtabError::RuxfsError("close_file_like failed".to_owned());
-    let f = FD_TABLE.write().remove(fd as usize).ok_or(err_msg)?;
+    let f = FD_TABLE
+        .write()
+        .remove(fd as usize)
+        .ok_or(FdtabError::NoExistFd(fd))?;
     drop(f);
     Ok(())
 }
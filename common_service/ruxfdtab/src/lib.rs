@@ -22,3 +22,21 @@ pub mod stdio;
 #[path = "./ctypes_gen.rs"]
 #[allow(dead_code, non_snake_case, non_camel_case_types, non_upper_case_globals, clippy::upper_case_acronyms, missing_docs, clippy::missing_safety_doc)]
 pub mod ctypes;
+
+as_hostcall::service_manifest! {
+    name: "ruxfdtab",
+    provides: [
+        "write",
+        "read",
+        "open",
+        "close",
+        "lseek",
+        "stat",
+        "readdir",
+        "readv",
+        "writev",
+        "pread",
+        "pwrite",
+    ],
+    depends: ["stdio"],
+}
@@ -4,7 +4,9 @@ use axio::PollState;
 use as_std::libos::libos;
 
 pub struct Stdin {}
-pub struct Stdout {}
+pub struct Stdout {
+    stderr: bool,
+}
 
 /// Constructs a new handle to the standard input of the current process.
 pub fn stdin() -> Stdin {
@@ -52,7 +54,12 @@ impl ruxfdtable::FileLike for Stdin {
 
 /// Constructs a new handle to the standard output of the current process.
 pub fn stdout() -> Stdout {
-    Stdout {}
+    Stdout { stderr: false }
+}
+
+/// Constructs a new handle to the standard error of the current process.
+pub fn stderr() -> Stdout {
+    Stdout { stderr: true }
 }
 
 impl ruxfdtable::FileLike for Stdout {
@@ -61,7 +68,11 @@ impl ruxfdtable::FileLike for Stdout {
     }
 
     fn write(&self, buf: &[u8]) -> LinuxResult<usize> {
-        Ok(libos!(stdout(buf)))
+        if self.stderr {
+            Ok(libos!(stderr(buf)))
+        } else {
+            Ok(libos!(stdout(buf)))
+        }
     }
 
     fn flush(&self) -> LinuxResult {
@@ -2,6 +2,12 @@ use as_hostcall::signal::SigAction;
 pub use as_std;
 use nc::{self, sigset_t};
 
+as_hostcall::service_manifest! {
+    name: "signal",
+    provides: ["libos_sigaction"],
+    depends: [],
+}
+
 #[no_mangle]
 #[allow(clippy::not_unsafe_ptr_arg_deref)]
 pub fn libos_sigaction(signum: i32, act: *const SigAction, old_act: *const SigAction) -> i32 {
@@ -21,6 +21,7 @@ use smoltcp::{
 
 use crate::setup_tap::exec_tap_setup;
 use as_hostcall::{
+    err::Errno,
     socket::{SmoltcpError, SmoltcpResult},
     types::{NetdevName, SockFd},
 };
@@ -29,6 +30,20 @@ use as_std::init_context;
 pub mod apis;
 pub mod logs;
 
+as_hostcall::service_manifest! {
+    name: "socket",
+    provides: [
+        "addrinfo",
+        "smol_connect",
+        "smol_send",
+        "smol_recv",
+        "smol_bind",
+        "smol_accept",
+        "smol_close",
+    ],
+    depends: [],
+}
+
 thread_local! {
     static DEVICE: Mutex<TunTapInterface> = {
         log::set_logger(&logs::LOGGER).expect("fail to init log");
@@ -134,7 +149,10 @@ fn try_phy_wait(
     sockets: &mut SocketSet,
 ) -> SmoltcpResult<()> {
     phy_wait(get_tap_raw_fd(), iface.poll_delay(timestamp, sockets))
-        .map_err(|e| SmoltcpError::HostIOErr(e.to_string()))
+        .map_err(|e| {
+            let errno = e.raw_os_error().map_or(Errno::EIO, Errno::from_code_or_io);
+            SmoltcpError::HostIOErr(errno, e.to_string())
+        })
 }
 
 fn acquire_sockets() -> SmoltcpResult<MutexGuard<'static, SocketSet<'static>>> {
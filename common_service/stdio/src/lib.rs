@@ -1,7 +1,19 @@
 use as_hostcall::types::Size;
 
+as_hostcall::service_manifest! {
+    name: "stdio",
+    provides: ["host_stdout", "host_stderr"],
+    depends: [],
+}
+
 #[no_mangle]
 pub fn host_stdout(buf: &[u8]) -> Size {
     print!("{}", String::from_utf8_lossy(buf));
     buf.len()
 }
+
+#[no_mangle]
+pub fn host_stderr(buf: &[u8]) -> Size {
+    eprint!("{}", String::from_utf8_lossy(buf));
+    buf.len()
+}
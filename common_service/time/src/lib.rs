@@ -7,6 +7,12 @@ use nix::{
     time::clock_gettime,
 };
 
+as_hostcall::service_manifest! {
+    name: "time",
+    provides: ["get_time", "host_nanosleep"],
+    depends: [],
+}
+
 #[no_mangle]
 pub fn get_time() -> Result<u128, String> {
     let r = clock_gettime(CLOCK_REALTIME.into()).map_err(|e| e.to_string())?;
@@ -1,6 +1,11 @@
-use std::{collections::BTreeMap, fs, io::BufReader, path::PathBuf};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    io::BufReader,
+    path::PathBuf,
+};
 
-use anyhow;
+use anyhow::anyhow;
 use log::{debug, warn};
 use as_hostcall::types::ServiceName;
 #[allow(unused_imports)]
@@ -10,6 +15,8 @@ use std::io;
 
 use crate::utils;
 
+use super::policy::Policy;
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct App {
     pub name: ServiceName,
@@ -78,6 +85,15 @@ pub struct IsolationConfig {
     pub with_libos: Option<bool>,
     #[serde(default = "Vec::default")]
     pub groups: Vec<IsolationGroup>,
+    /// Hostcalls routed to another service than the one whose manifest
+    /// provides them. A key is the name of a hostcall, or of the default
+    /// service of hostcalls to route all of them, e.g. `"fdtab": "ruxfdtab"`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub routes: BTreeMap<String, ServiceName>,
+    /// Policies of apps, which may only use the hostcalls, paths and network
+    /// destinations their policy allows. Apps without one are unrestricted.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub policies: BTreeMap<ServiceName, Policy>,
 }
 
 impl IsolationConfig {
@@ -99,8 +115,19 @@ impl IsolationConfig {
         debug!("config file path: {}", p.to_str().unwrap());
         let content = fs::File::open(p)?;
 
-        let mut config: IsolationConfig = serde_json::from_reader(BufReader::new(content))?;
-        for LoadableUnit(_, path) in config.services.iter_mut().chain(config.apps.iter_mut()) {
+        let config: IsolationConfig = serde_json::from_reader(BufReader::new(content))?;
+        Ok(config.resolve())
+    }
+
+    /// Same as `from_file`, but the config comes from an already parsed json,
+    /// e.g. the body of a request.
+    pub fn from_value(value: serde_json::Value) -> Result<Self, anyhow::Error> {
+        let config: IsolationConfig = serde_json::from_value(value)?;
+        Ok(config.resolve())
+    }
+
+    fn resolve(mut self) -> Self {
+        for LoadableUnit(_, path) in self.services.iter_mut().chain(self.apps.iter_mut()) {
             *path = PathBuf::from("target")
                 .join(if cfg!(debug_assertions) {
                     "debug"
@@ -111,7 +138,7 @@ impl IsolationConfig {
         }
 
         #[cfg(feature = "namespace")]
-        config.services.insert(
+        self.services.insert(
             0,
             LoadableUnit(
                 "libc".to_owned(),
@@ -119,11 +146,136 @@ impl IsolationConfig {
             ),
         );
 
-        if config.with_libos.eq(&Some(false)) && !config.services.is_empty() {
+        if self.with_libos.eq(&Some(false)) && !self.services.is_empty() {
             warn!("disable_libos is true, will ignore services");
         }
 
-        Ok(config)
+        self
+    }
+
+    /// Check the config is self-consistent: module names are unique, every
+    /// app in `groups` is declared in `apps`, and every library exists.
+    pub fn validate(&self) -> Result<(), anyhow::Error> {
+        if self.apps.is_empty() {
+            return Err(anyhow!("config has no apps"));
+        }
+
+        let mut names = HashSet::new();
+        for LoadableUnit(name, _) in self.all_modules() {
+            if !names.insert(name) {
+                return Err(anyhow!("duplicate module name: {}", name));
+            }
+        }
+
+        for group in &self.groups {
+            for app in group.to_isolation() {
+                if !self.apps.iter().any(|unit| unit.0 == app.name) {
+                    return Err(anyhow!("group refers to undeclared app: {}", app.name));
+                }
+            }
+        }
+
+        for (from, to) in &self.routes {
+            if !self.services.iter().any(|unit| &unit.0 == to) {
+                return Err(anyhow!(
+                    "route of {} refers to undeclared service: {}",
+                    from,
+                    to
+                ));
+            }
+        }
+
+        for app in self.policies.keys() {
+            if !self.apps.iter().any(|unit| &unit.0 == app) {
+                return Err(anyhow!("policy of undeclared app: {}", app));
+            }
+        }
+
+        for LoadableUnit(name, path) in self.all_modules() {
+            let path = if !path.is_file() {
+                utils::REPOS_ROOT_PATH.join(path)
+            } else {
+                path.to_owned()
+            };
+            if !path.is_file() {
+                return Err(anyhow!(
+                    "library of {} not found: {}",
+                    name,
+                    path.to_string_lossy()
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Pass `args` to every app in `groups`, overriding args of the same key
+    /// from the config. Apps run as a sequence take no args.
+    pub fn extend_args(&mut self, args: &BTreeMap<String, String>) {
+        for group in &mut self.groups {
+            group.args.extend(args.clone());
+            for app in &mut group.list {
+                if let IsolationGroupApp::Detailed(app) = app {
+                    app.args.extend(args.clone())
+                }
+            }
+        }
+    }
+
+    /// Set arg `key` of every instance of `app` in `groups`, overriding the
+    /// group's arg of the same key.
+    pub fn set_app_arg(&mut self, app: &str, key: &str, value: &str) -> Result<(), anyhow::Error> {
+        let mut found = false;
+        for group_app in self.groups.iter_mut().flat_map(|group| &mut group.list) {
+            if let IsolationGroupApp::Name(name) = group_app {
+                if name == app {
+                    *group_app = IsolationGroupApp::Detailed(App {
+                        name: name.to_owned(),
+                        args: BTreeMap::new(),
+                    });
+                }
+            }
+            if let IsolationGroupApp::Detailed(detailed) = group_app {
+                if detailed.name == app {
+                    detailed.args.insert(key.to_owned(), value.to_owned());
+                    found = true;
+                }
+            }
+        }
+
+        if found {
+            Ok(())
+        } else {
+            Err(anyhow!("app {} is not in any group", app))
+        }
+    }
+
+    /// Set arg `key` of the `idx`th group, for all of its apps.
+    pub fn set_group_arg(
+        &mut self,
+        idx: usize,
+        key: &str,
+        value: &str,
+    ) -> Result<(), anyhow::Error> {
+        let group_num = self.groups.len();
+        let group = self.groups.get_mut(idx).ok_or_else(|| {
+            anyhow!(
+                "group {} out of range, config has {} groups",
+                idx,
+                group_num
+            )
+        })?;
+
+        group.args.insert(key.to_owned(), value.to_owned());
+        for app in &mut group.list {
+            if let IsolationGroupApp::Detailed(app) = app {
+                if let Some(arg) = app.args.get_mut(key) {
+                    *arg = value.to_owned()
+                }
+            }
+        }
+
+        Ok(())
     }
 
     pub fn all_modules(&self) -> Vec<&LoadableUnit> {
@@ -148,3 +300,70 @@ fn all_modules_test() {
         config.all_modules()
     )
 }
+
+#[test]
+fn validate_undeclared_app_test() {
+    let config = IsolationConfig::from_value(serde_json::json!({
+        "services": [],
+        "apps": [["hello1", "libhello_world.so"]],
+        "groups": [{ "list": ["hello2"], "args": {} }],
+    }))
+    .expect("parse config failed");
+
+    let err = config.validate().expect_err("hello2 is not declared");
+    assert!(err.to_string().contains("hello2"), "{}", err)
+}
+
+#[test]
+fn set_args_test() {
+    let mut config = IsolationConfig::from_value(serde_json::json!({
+        "services": [],
+        "apps": [["mapper", "libmapper.so"], ["reducer", "libreducer.so"]],
+        "groups": [
+            { "list": ["mapper", { "name": "mapper", "args": { "reducer_num": "2" } }], "args": {} },
+            { "list": ["reducer"], "args": { "reducer_num": "2" } },
+        ],
+    }))
+    .expect("parse config failed");
+
+    config.set_group_arg(0, "reducer_num", "4").unwrap();
+    config
+        .set_app_arg("reducer", "input_file", "a.txt")
+        .unwrap();
+    assert!(config
+        .set_app_arg("file_reader", "input_file", "a.txt")
+        .is_err());
+    assert!(config.set_group_arg(2, "reducer_num", "4").is_err());
+
+    let mappers = config.groups[0].to_isolation();
+    assert!(mappers.iter().all(|app| app.args["reducer_num"] == "4"));
+    let reducers = config.groups[1].to_isolation();
+    assert_eq!(reducers[0].args["reducer_num"], "2");
+    assert_eq!(reducers[0].args["input_file"], "a.txt");
+}
+
+#[test]
+fn validate_undeclared_route_test() {
+    let config = IsolationConfig::from_value(serde_json::json!({
+        "services": [["ruxfdtab", "libruxfdtab.so"]],
+        "apps": [["hello1", "libhello_world.so"]],
+        "routes": { "fdtab": "ext4fdtab" },
+    }))
+    .expect("parse config failed");
+
+    let err = config.validate().expect_err("ext4fdtab is not declared");
+    assert!(err.to_string().contains("ext4fdtab"), "{}", err)
+}
+
+#[test]
+fn validate_undeclared_policy_test() {
+    let config = IsolationConfig::from_value(serde_json::json!({
+        "services": [["fdtab", "libfdtab.so"]],
+        "apps": [["hello1", "libhello_world.so"]],
+        "policies": { "hello2": { "allow": ["fdtab"] } },
+    }))
+    .expect("parse config failed");
+
+    let err = config.validate().expect_err("hello2 is not declared");
+    assert!(err.to_string().contains("hello2"), "{}", err)
+}
@@ -1,9 +1,12 @@
 use core::panic;
 use std::mem::transmute;
 
-use log::info;
+use log::{info, warn};
 use as_hostcall::{
-    types::{FsImageFunc, IsolationID, MetricEvent, MetricFunc, NetdevName},
+    types::{
+        FsImageFunc, HostCallError, HostStdioFunc, IsolationID, MetricEvent, MetricFunc,
+        NetdevName, TraceEvent, TraceFunc,
+    },
     CommonHostCall, HostCallID,
 };
 
@@ -12,14 +15,29 @@ use crate::mpk;
 #[cfg(feature = "enable_mpk")]
 use as_hostcall::mpk::LIBOS_PKEY;
 
-use crate::{isolation::get_isol, logger};
+use crate::{
+    isolation::{
+        get_isol,
+        output::{self, stderr_handler, stdout_handler},
+        strace, Isolation, Wrapper,
+    },
+    logger,
+    service::ServiceInitError,
+};
 
 /// # Safety
 /// This is unsafe because it it be a callback function used to lookup the address of
 /// hostcall function symbols, and it should be only invocated by service modules.
 ///
+/// A non-zero `signature` is compared with the one of the provider, so that
+/// a module built against another `as_hostcall` fails here rather than
+/// calling through a wrong function type.
 #[allow(improper_ctypes_definitions)]
-pub unsafe extern "C" fn find_host_call(isol_id: IsolationID, hc_id: HostCallID) -> usize {
+pub unsafe extern "C" fn find_host_call(
+    isol_id: IsolationID,
+    hc_id: HostCallID,
+    signature: u64,
+) -> Result<usize, HostCallError> {
     // let id = HostCallID::Common(CommonHostCall::Write);
     // thread::sleep(Duration::from_secs(1));
     logger::debug!(
@@ -28,47 +46,215 @@ pub unsafe extern "C" fn find_host_call(isol_id: IsolationID, hc_id: HostCallID)
         hc_id.to_string()
     );
     let isol = get_isol(isol_id).expect("isol don't exist?");
+    // Hostcalls handled by as-visor itself have the types it is built with.
+    let host_signature = match &hc_id {
+        HostCallID::Common(common) => common.signature(),
+        HostCallID::Custom(_) => None,
+    };
 
-    let addr = match hc_id {
-        HostCallID::Common(CommonHostCall::Metric) => metric_handler as MetricFunc as usize,
-        HostCallID::Common(CommonHostCall::FsImage) => fs_image_handler as FsImageFunc as usize,
-        HostCallID::Common(CommonHostCall::SpawnFaultThread) => spwan_fault_thread_handler as usize,
+    let (addr, expect) = match hc_id {
+        HostCallID::Common(CommonHostCall::Metric) => {
+            (metric_handler as MetricFunc as usize, host_signature)
+        }
+        HostCallID::Common(CommonHostCall::FsImage) => {
+            (fs_image_handler as FsImageFunc as usize, host_signature)
+        }
+        HostCallID::Common(CommonHostCall::SpawnFaultThread) => {
+            (spwan_fault_thread_handler as usize, host_signature)
+        }
+        // Resolved to null when not observed, so `libos!` skips the calls.
+        HostCallID::Common(CommonHostCall::Trace) => {
+            let addr = if isol.metric.observes_hostcalls() {
+                trace_handler as TraceFunc as usize
+            } else {
+                0
+            };
+            (addr, host_signature)
+        }
+        // Output of capturing isolations is kept by the host rather than
+        // printed by the stdio service.
+        HostCallID::Common(CommonHostCall::Stdout) if isol.output().is_some() => {
+            (stdout_handler as HostStdioFunc as usize, host_signature)
+        }
+        HostCallID::Common(CommonHostCall::Stderr) if isol.output().is_some() => {
+            (stderr_handler as HostStdioFunc as usize, host_signature)
+        }
         _ => {
-            let svc_name = hc_id.belong_to();
+            let svc_name = isol.route(&hc_id).map_err(|e| {
+                warn!("hostcall {} is not routed: {}", hc_id, e);
+                HostCallError::NoRoute
+            })?;
             logger::debug!(
                 "hostcall_{} belong to service: {}",
                 hc_id.to_string(),
                 svc_name
             );
 
-            let service = isol.service_or_load(&svc_name).unwrap_or_else(|e| {
-                panic!("need find: {}, need load: {}, err: {}", hc_id, svc_name, e)
-            });
-
-            let symbol = service
-                .interface::<fn()>(&hc_id.to_string())
-                .unwrap_or_else(|| {
-                    panic!(
-                        "not found interface \"{}\" in service \"{}\"",
-                        hc_id,
-                        hc_id.belong_to()
-                    )
-                });
-            *symbol as usize
+            let service = isol.service_or_load(&svc_name).map_err(|e| {
+                warn!(
+                    "hostcall {} needs service \"{}\", which fails to load: {}",
+                    hc_id, svc_name, e
+                );
+                match e.downcast_ref::<ServiceInitError>() {
+                    Some(ServiceInitError::AbiVersion(version)) => {
+                        HostCallError::AbiVersion(*version)
+                    }
+                    _ => HostCallError::LoadFailed(format!("{:#}", e)),
+                }
+            })?;
+            let manifest = service.manifest();
+            match (&hc_id, &manifest) {
+                (HostCallID::Common(_), Some(manifest)) => {
+                    if !manifest.provides.contains(&hc_id.to_string()) {
+                        warn!(
+                            "service \"{}\" does not provide \"{}\" in its manifest",
+                            svc_name, hc_id
+                        );
+                        return Err(HostCallError::NotProvided(svc_name));
+                    }
+                }
+                // A custom hostcall is called through the type the app
+                // expects, so it must be exported with exactly that type.
+                (HostCallID::Custom(custom), manifest) => {
+                    let signature = manifest
+                        .as_ref()
+                        .and_then(|m| m.custom.get(custom.name).cloned());
+                    match signature {
+                        Some(signature) if signature == custom.signature => {}
+                        Some(signature) => {
+                            warn!(
+                                "custom hostcall \"{}\" of service \"{}\" is {}, but called as {}",
+                                hc_id, svc_name, signature, custom.signature
+                            );
+                            return Err(HostCallError::CustomSignature {
+                                called: custom.signature.to_owned(),
+                                provided: signature,
+                            });
+                        }
+                        None => {
+                            warn!(
+                                "service \"{}\" does not declare custom hostcall \"{}\"",
+                                svc_name, hc_id
+                            );
+                            return Err(HostCallError::NotProvided(svc_name));
+                        }
+                    }
+                }
+                (HostCallID::Common(_), None) => {}
+            }
+
+            let Some(symbol) = service.interface::<fn()>(&hc_id.to_string()) else {
+                warn!(
+                    "not found interface \"{}\" in service \"{}\"",
+                    hc_id, svc_name
+                );
+                return Err(HostCallError::NoSymbol(svc_name));
+            };
+            let expect = manifest.and_then(|m| m.signatures.get(&hc_id.to_string()).copied());
+            (*symbol as usize, expect)
         }
     };
+    if let Some(expect) = expect.filter(|expect| signature != 0 && *expect != signature) {
+        warn!(
+            "hostcall \"{}\" is called with signature {:#x} but provided with {:#x}, \
+            built against different as_hostcall?",
+            hc_id, signature, expect
+        );
+        return Err(HostCallError::Signature {
+            called: signature,
+            provided: expect,
+        });
+    }
 
     log::debug!("interface '{}' addr = 0x{:x}", hc_id, addr);
-    addr
+    Ok(addr)
+}
+
+/// # Safety
+/// Same as `find_host_call`, which it is used instead of by app modules.
+///
+/// Applies the policy of the app running on the current thread before
+/// `find_host_call`, and resolves hostcalls to the wrappers that log them if
+/// the isolation traces hostcalls.
+#[allow(improper_ctypes_definitions)]
+pub unsafe extern "C" fn find_app_host_call(
+    isol_id: IsolationID,
+    hc_id: HostCallID,
+    signature: u64,
+) -> Result<usize, HostCallError> {
+    let isol = get_isol(isol_id).expect("isol don't exist?");
+    let traced = match hc_id {
+        HostCallID::Common(common) if isol.traces_hostcalls() => {
+            strace::wrapper_of(common).map(|wrapper| (common, wrapper))
+        }
+        _ => None,
+    };
+    let (addr, guard) = find_with_policy(&isol, hc_id, signature)?;
+    match traced {
+        Some((common, wrapper)) => {
+            isol.set_traced(common, addr, guard);
+            strace::set_unknown_caller_target(common, addr, guard);
+            Ok(wrapper)
+        }
+        None => Ok(addr),
+    }
+}
+
+/// `find_host_call` for the app running on the current thread, if its
+/// policy allows, and whether the address is a policy guard. Hostcalls
+/// handled by as-visor itself are always allowed, and threads that run no
+/// app are denied in isolations with policies.
+unsafe fn find_with_policy(
+    isol: &Isolation,
+    hc_id: HostCallID,
+    signature: u64,
+) -> Result<(usize, bool), HostCallError> {
+    let unguarded = |addr| (addr, false);
+    if !isol.has_policies() || hc_id.belong_to().is_empty() {
+        return find_host_call(isol.id, hc_id, signature).map(unguarded);
+    }
+    let Some((app, _)) = output::current_app() else {
+        warn!("hostcall {} out of an app is denied by policies", hc_id);
+        return Err(HostCallError::Denied);
+    };
+    let Some(policy) = isol.policy(&app) else {
+        return find_host_call(isol.id, hc_id, signature).map(unguarded);
+    };
+
+    let svc_name = isol.route(&hc_id).unwrap_or_default();
+    if !policy.allows_hostcall(&hc_id.to_string(), &svc_name) {
+        warn!("hostcall {} of app {} is denied by its policy", hc_id, app);
+        return Err(HostCallError::Denied);
+    }
+    let guard = match hc_id {
+        HostCallID::Common(common) => policy.guard_of(common).map(|guard| (common, guard)),
+        HostCallID::Custom(_) => None,
+    };
+    let addr = find_host_call(isol.id, hc_id, signature)?;
+    match guard {
+        Some((common, guard)) => {
+            isol.set_wrapped(Wrapper::Policy, common, addr);
+            Ok((guard, true))
+        }
+        None => Ok((addr, false)),
+    }
 }
 
 fn metric_handler(isol_id: IsolationID, event: MetricEvent) -> Result<(), ()> {
     let isol = get_isol(isol_id).expect("isol don't exist?");
-    isol.metric.mark(event);
+    match event {
+        MetricEvent::Mem => isol.sample_mem(),
+        event => isol.metric.mark(event),
+    }
 
     Ok(())
 }
 
+fn trace_handler(isol_id: IsolationID, event: TraceEvent) {
+    let isol = get_isol(isol_id).expect("isol don't exist?");
+    isol.metric.trace_event(event);
+}
+
 fn fs_image_handler(isol_id: IsolationID) -> Option<String> {
     get_isol(isol_id)
         .expect("isol don't exist?")
@@ -102,13 +288,13 @@ fn spwan_fault_thread_handler(isol_id: IsolationID) -> Result<(), String> {
     let thread_builder =
         std::thread::Builder::new().name(format!("isol-{}-fault-handler", isol.id));
 
-    // TODO: Save thread handler to send exit signal.
-    let _thread_handler = thread_builder
+    let thread_handler = thread_builder
         .spawn(move || {
             let fault_handler: fn() = unsafe { transmute(fault_handler_addr) };
             fault_handler()
         })
         .expect("spawn thread failed.");
+    isol.add_fault_thread(thread_handler);
 
     Ok(())
 }
@@ -139,12 +325,14 @@ fn find_host_call_test() {
             groups: Default::default(),
             fs_image: None,
             with_libos: None,
+            routes: Default::default(),
+            policies: Default::default(),
         })
         // isol_table.insert(1, Arc::clone(&isol));
     };
 
     let hostcall_id = HostCallID::Common(as_hostcall::CommonHostCall::Write);
-    let addr = unsafe { find_host_call(1, hostcall_id) };
+    let addr = unsafe { find_host_call(1, hostcall_id, 0) }.unwrap();
 
     let fs_svc = isol
         .service_or_load(&"fdtab".to_string())
@@ -1,21 +1,35 @@
 pub mod config;
 pub mod handler;
+pub mod output;
+pub mod policy;
+mod strace;
 
 use std::{
-    collections::{BTreeMap, HashMap},
+    collections::{BTreeMap, HashMap, HashSet},
     iter::zip,
-    sync::{Arc, Mutex, MutexGuard, Weak},
-    thread,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, MutexGuard, OnceLock, Weak,
+    },
+    thread::{self, JoinHandle},
 };
 
 use anyhow::{anyhow, Ok};
 
 use lazy_static::lazy_static;
 use log::{info, warn};
-use as_hostcall::types::{
-    IsolationID as IsolID,
-    MetricEvent::{IsolBegin, IsolEnd, Mem},
-    ServiceName,
+use as_hostcall::{
+    mm::{
+        BufferStats, BufferStatsFunc, HeapStats, HeapStatsFunc, BUFFER_STATS_SYMBOL,
+        HEAP_STATS_SYMBOL,
+    },
+    mmap_file_backend::{StopFaultHandlerFunc, STOP_FAULT_HANDLER_SYMBOL},
+    types::{
+        IsolationID as IsolID,
+        MetricEvent::{IsolBegin, IsolEnd, IsolRun, Mem},
+        ServiceName,
+    },
+    CommonHostCall, HostCallID,
 };
 
 #[cfg(feature = "enable_mpk")]
@@ -25,26 +39,43 @@ use as_hostcall::mpk::LIBOS_PKEY;
 
 use crate::{
     logger,
-    metric::MetricBucket,
+    metric::{MemSample, MetricBucket},
+    now_microsec,
     service::{Service, ServiceLoader},
     utils::gen_new_id,
 };
 use config::IsolationConfig;
+use output::OutputCapture;
+use policy::Policy;
 
 use self::config::App;
 
-type IsolTable = Vec<Weak<Isolation>>;
+type IsolTable = BTreeMap<IsolID, Weak<Isolation>>;
 
-pub static ISOL_TABLE: Mutex<IsolTable> = Mutex::new(Vec::new());
+pub static ISOL_TABLE: Mutex<IsolTable> = Mutex::new(BTreeMap::new());
 
 fn get_isol_table() -> MutexGuard<'static, IsolTable> {
     ISOL_TABLE.lock().unwrap()
 }
 
+/// Cancel all isolations that are still alive, and return their ids. Used
+/// on shutdown, see `Isolation::cancel`.
+pub fn cancel_all() -> Vec<IsolID> {
+    let isols: Vec<_> = get_isol_table()
+        .values()
+        .filter_map(|isol| isol.upgrade())
+        .collect();
+    for isol in &isols {
+        isol.cancel()
+    }
+
+    isols.iter().map(|isol| isol.id).collect()
+}
+
 pub fn get_isol(handle: IsolID) -> anyhow::Result<Arc<Isolation>> {
     let isol_table = get_isol_table();
     Ok(isol_table
-        .get(handle as usize - 1)
+        .get(&handle)
         .ok_or_else(|| anyhow!("isol don't exsit. handle={}", handle))?
         .upgrade()
         .ok_or_else(|| {
@@ -55,9 +86,25 @@ pub fn get_isol(handle: IsolID) -> anyhow::Result<Arc<Isolation>> {
         })?)
 }
 
+/// Functions `find_app_host_call` resolves a hostcall to instead of the
+/// address found for it, which they call in turn.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum Wrapper {
+    /// Checks arguments of the hostcall against the policy of the app.
+    Policy,
+    /// Logs the hostcall, see `trace_hostcalls`.
+    Trace,
+}
+
 #[derive(Default)]
 pub struct IsolationInner {
     modules: HashMap<ServiceName, Arc<Service>>,
+    /// Hostcall to the loaded service whose manifest provides it.
+    provided: HashMap<String, ServiceName>,
+    /// Addresses the wrappers of hostcalls call.
+    wrapped: HashMap<(Wrapper, CommonHostCall), usize>,
+    /// Hostcalls whose trace wrapper calls a policy guard.
+    guarded_traces: HashSet<CommonHostCall>,
 }
 
 impl Drop for IsolationInner {
@@ -74,7 +121,13 @@ pub struct Isolation {
     pub metric: Arc<MetricBucket>,
     app_names: Vec<ServiceName>,
     groups: Vec<Vec<App>>,
+    routes: BTreeMap<String, ServiceName>,
+    policies: BTreeMap<ServiceName, Policy>,
     fs_image: Option<String>,
+    cancelled: AtomicBool,
+    trace_hostcalls: AtomicBool,
+    output: OnceLock<Arc<OutputCapture>>,
+    fault_threads: Mutex<Vec<JoinHandle<()>>>,
     // #[cfg(feature = "enable_mpk")]
     // _pkey: i32,
     inner: Mutex<IsolationInner>,
@@ -113,13 +166,19 @@ impl Isolation {
                 .iter()
                 .map(|group| group.to_isolation())
                 .collect(),
+            routes: config.routes.clone(),
+            policies: config.policies.clone(),
             fs_image: config.fs_image.clone(),
+            cancelled: AtomicBool::new(false),
+            trace_hostcalls: AtomicBool::new(false),
+            output: OnceLock::new(),
+            fault_threads: Mutex::new(Vec::new()),
             // #[cfg(feature = "enable_mpk")]
             // _pkey: 0,
             inner: Mutex::new(IsolationInner::default()),
         });
 
-        get_isol_table().push(Arc::downgrade(&isol));
+        get_isol_table().insert(new_id, Arc::downgrade(&isol));
 
         isol
     }
@@ -137,10 +196,176 @@ impl Isolation {
         Ok(())
     }
 
+    /// Ask the isolation to stop. Apps that are already running can not be
+    /// interrupted, so it takes effect before the next app or group starts.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Release)
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Acquire)
+    }
+
+    fn check_cancelled(&self) -> Result<(), anyhow::Error> {
+        if self.is_cancelled() {
+            Err(anyhow!("isolation_{} has been cancelled", self.id))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Log every I/O, network and buffer hostcall of apps to stderr, with
+    /// the app instance, the arguments and the result, must be called
+    /// before `run`.
+    pub fn trace_hostcalls(&self) {
+        self.trace_hostcalls.store(true, Ordering::Release)
+    }
+
+    pub fn traces_hostcalls(&self) -> bool {
+        self.trace_hostcalls.load(Ordering::Acquire)
+    }
+
+    /// Capture stdout and stderr of apps instead of printing them, must be
+    /// called before `run`.
+    pub fn capture_output(&self, output: Arc<OutputCapture>) -> Result<(), anyhow::Error> {
+        self.output
+            .set(output)
+            .map_err(|_| anyhow!("isolation_{} already captures output", self.id))
+    }
+
+    pub fn output(&self) -> Option<&Arc<OutputCapture>> {
+        self.output.get()
+    }
+
+    pub(crate) fn add_fault_thread(&self, handle: JoinHandle<()>) {
+        self.fault_threads.lock().unwrap().push(handle)
+    }
+
+    /// Ask `mmap_file_backend` to stop its page fault handler, and wait for
+    /// the threads to exit before the service is unloaded.
+    fn stop_fault_threads(&self) {
+        let threads: Vec<_> = self.fault_threads.lock().unwrap().drain(..).collect();
+        if threads.is_empty() {
+            return;
+        }
+
+        let mmap_file_backend = self
+            .inner_access()
+            .modules
+            .get("mmap_file_backend")
+            .map(Arc::clone);
+        match mmap_file_backend
+            .as_ref()
+            .and_then(|svc| svc.interface::<StopFaultHandlerFunc>(STOP_FAULT_HANDLER_SYMBOL))
+        {
+            Some(stop) => stop(),
+            None => {
+                warn!("isolation_{} can not stop its fault handler", self.id);
+                return;
+            }
+        }
+
+        for thread in threads {
+            if thread.join().is_err() {
+                warn!("fault handler of isolation_{} panicked", self.id)
+            }
+        }
+    }
+
     pub fn inner_access(&self) -> MutexGuard<'_, IsolationInner> {
         self.inner.lock().unwrap()
     }
 
+    /// The service `hc_id` is routed to: a route of the config for the
+    /// hostcall, the loaded service whose manifest provides it, or else its
+    /// default service, which a route of the config can redirect. Custom
+    /// hostcalls go to the service they name, and only follow routes of it.
+    pub fn route(&self, hc_id: &HostCallID) -> Result<ServiceName, anyhow::Error> {
+        if let HostCallID::Common(_) = hc_id {
+            let hostcall = hc_id.to_string();
+            if let Some(svc) = self.routes.get(&hostcall) {
+                return Ok(svc.clone());
+            }
+            if let Some(svc) = self.inner_access().provided.get(&hostcall) {
+                return Ok(svc.clone());
+            }
+        }
+
+        let default = hc_id.belong_to();
+        if default.is_empty() {
+            return Err(anyhow!("hostcall {} is not provided by a service", hc_id));
+        }
+        Ok(self.routes.get(&default).cloned().unwrap_or(default))
+    }
+
+    pub fn has_policies(&self) -> bool {
+        !self.policies.is_empty()
+    }
+
+    pub fn policy(&self, app: &str) -> Option<&Policy> {
+        self.policies.get(app)
+    }
+
+    pub(crate) fn set_wrapped(&self, wrapper: Wrapper, hostcall: CommonHostCall, addr: usize) {
+        self.inner_access()
+            .wrapped
+            .insert((wrapper, hostcall), addr);
+    }
+
+    /// Set the address the trace wrapper of `hostcall` calls, unless it
+    /// calls a policy guard already. The wrapper is shared by all apps of the
+    /// isolation, so an app without a policy resolving `hostcall` after a
+    /// guarded one must not let the latter bypass its guard. Apps without a
+    /// policy pass the guards anyway.
+    pub(crate) fn set_traced(&self, hostcall: CommonHostCall, addr: usize, guard: bool) {
+        let mut inner = self.inner_access();
+        if guard {
+            inner.guarded_traces.insert(hostcall);
+        } else if inner.guarded_traces.contains(&hostcall) {
+            return;
+        }
+        inner.wrapped.insert((Wrapper::Trace, hostcall), addr);
+    }
+
+    /// The address `wrapper` of `hostcall` calls.
+    pub(crate) fn wrapped(&self, wrapper: Wrapper, hostcall: CommonHostCall) -> Option<usize> {
+        self.inner_access()
+            .wrapped
+            .get(&(wrapper, hostcall))
+            .copied()
+    }
+
+    /// Remember the hostcalls `svc` provides, and warn about dependencies
+    /// that can not be loaded.
+    fn register_manifest(&self, isol_inner: &mut IsolationInner, svc: &Service) {
+        let Some(manifest) = svc.manifest() else {
+            return;
+        };
+        for hostcall in manifest.provides {
+            if let Some(other) = isol_inner.provided.get(&hostcall) {
+                warn!(
+                    "hostcall {} is provided by both {} and {}, use {}",
+                    hostcall,
+                    other,
+                    svc.name(),
+                    other
+                );
+                continue;
+            }
+            isol_inner.provided.insert(hostcall, svc.name());
+        }
+        for dep in manifest.depends {
+            let dep = self.routes.get(&dep).cloned().unwrap_or(dep);
+            if !self.loader.is_registered(&dep) {
+                warn!(
+                    "service {} depends on {}, which is not in the config",
+                    svc.name(),
+                    dep
+                );
+            }
+        }
+    }
+
     pub fn service_or_load(&self, name: &ServiceName) -> Result<Arc<Service>, anyhow::Error> {
         let mut isol_inner = self.inner_access();
         match isol_inner.modules.get(name) {
@@ -149,6 +374,7 @@ impl Isolation {
                 info!("[service] first load {}.", name);
                 let svc = self.loader.load_service(name)?;
                 isol_inner.modules.insert(name.to_owned(), Arc::clone(&svc));
+                self.register_manifest(&mut isol_inner, &svc);
 
                 #[cfg(feature = "enable_mpk")]
                 mpk::set_libs_with_pkey(&[svc.path()], LIBOS_PKEY)?;
@@ -185,11 +411,16 @@ impl Isolation {
         };
 
         for app in &self.app_names {
+            self.check_cancelled()?;
             let app = self
                 .app_or_load(app)
                 .map_err(|e| anyhow!("load app failed: {e}"))?;
 
+            let _guard = output::enter_app(self.id, app.name(), "0".to_owned());
+            let run_begin = now_microsec!();
             let result = app.run(&args);
+            self.metric
+                .trace_span(format!("run {}", app.name()), "run", run_begin);
             result.map_err(|e| anyhow!("app_{} run failed, reason: {}", app.name(), e))?
         }
 
@@ -208,8 +439,13 @@ impl Isolation {
             for (app, app_config) in zip(apps, group) {
                 let builder = thread::Builder::new().name(app.name());
                 let app_result = builder.spawn_scoped(scope, move || {
-                    app.run(&app_config.args)
-                        .map_err(|e| anyhow!("app {} run failed. {}", app.name(), e))
+                    let instance = app_config.args.get("id").cloned().unwrap_or_default();
+                    let _guard = output::enter_app(self.id, app.name(), instance);
+                    let run_begin = now_microsec!();
+                    let result = app.run(&app_config.args);
+                    self.metric
+                        .trace_span(format!("run {}", app.name()), "run", run_begin);
+                    result.map_err(|e| anyhow!("app {} run failed. {}", app.name(), e))
                 })?;
                 join_handles.push(Some(app_result));
             }
@@ -223,8 +459,67 @@ impl Isolation {
         })
     }
 
+    /// Read data buffer statistics from the `mm` service, if it was loaded.
+    fn buffer_stats(&self) -> Option<BufferStats> {
+        let mm = self.inner_access().modules.get("mm").map(Arc::clone);
+        mm.as_ref()
+            .and_then(|mm| mm.interface::<BufferStatsFunc>(BUFFER_STATS_SYMBOL))
+            .map(|buffer_stats| buffer_stats())
+    }
+
+    fn collect_buffer_stats(&self) {
+        if let Some(stats) = self.buffer_stats() {
+            self.metric.set_buffer_stats(&stats);
+        }
+    }
+
+    /// Heap statistics of the loaded modules that use the allocator of
+    /// `as_std`.
+    fn heap_stats(&self) -> BTreeMap<ServiceName, HeapStats> {
+        let modules: Vec<_> = self
+            .inner_access()
+            .modules
+            .values()
+            .map(Arc::clone)
+            .collect();
+        modules
+            .iter()
+            .filter_map(|module| {
+                let heap_stats = module.interface::<HeapStatsFunc>(HEAP_STATS_SYMBOL)?;
+                Some((module.name(), heap_stats()))
+            })
+            .collect()
+    }
+
+    fn collect_heap_stats(&self) {
+        self.metric
+            .set_heap_stats(&self.heap_stats(), &self.app_names);
+    }
+
+    /// Record the memory of the process, of the heap of each loaded module
+    /// and of data buffers in use, for `MetricEvent::Mem`.
+    pub fn sample_mem(&self) {
+        let mut sample = MemSample::of_process();
+        sample.heap_used_bytes = self
+            .heap_stats()
+            .into_iter()
+            .map(|(name, stats)| (name, stats.used_bytes))
+            .collect();
+        sample.buffer_in_use_bytes = self
+            .buffer_stats()
+            .map(|stats| stats.in_use_bytes)
+            .unwrap_or_default();
+        if let Some((app, instance)) = output::current_app() {
+            sample.app = Some(app);
+            sample.instance = Some(instance);
+        }
+
+        self.metric.record_mem(sample)
+    }
+
     pub fn run(&self) -> Result<(), anyhow::Error> {
-        self.metric.mark(Mem);
+        self.metric.mark(IsolRun);
+        self.sample_mem();
         #[cfg(feature = "enable_mpk")]
         {
             let this_proc_name = std::env::current_exe()?;
@@ -243,17 +538,24 @@ impl Isolation {
         self.service_or_load(&"libc".to_owned())
             .map_err(|e| anyhow!("namespace feature, load libc failed: {e}"))?;
 
-        if self.groups.is_empty() {
+        let result = if self.groups.is_empty() {
             self.run_as_sequence()
-                .map_err(|e| anyhow!("run_as_sequence failed: {e}"))?
+                .map_err(|e| anyhow!("run_as_sequence failed: {e}"))
         } else {
-            for group in &self.groups {
+            self.groups.iter().try_for_each(|group| {
+                self.check_cancelled()?;
                 self.run_group_in_parallel(group)
-                    .map_err(|e| anyhow!("run_group_in_parallel failed: {e}"))?
-            }
+                    .map_err(|e| anyhow!("run_group_in_parallel failed: {e}"))
+            })
         };
+        if let Some(output) = self.output() {
+            output.flush()
+        }
+        result?;
 
-        self.metric.mark(Mem);
+        self.collect_buffer_stats();
+        self.collect_heap_stats();
+        self.sample_mem();
         self.metric.mark(IsolEnd);
         Ok(())
     }
@@ -261,7 +563,10 @@ impl Isolation {
 
 impl Drop for Isolation {
     fn drop(&mut self) {
-        get_isol_table().remove(self.id as usize - 1);
+        // Services run their `drop` symbols when `inner` is dropped after
+        // this, the fault handler is still running code of one of them.
+        self.stop_fault_threads();
+        get_isol_table().remove(&self.id);
     }
 }
 
@@ -276,3 +581,54 @@ lazy_static! {
         "[vvar]",
     ];
 }
+
+#[test]
+fn set_traced_test() {
+    let config = IsolationConfig::from_value(serde_json::json!({
+        "services": [],
+        "apps": [["guarded", "libguarded.so"], ["free", "libfree.so"]],
+        "policies": { "guarded": { "paths": ["/data"] } },
+    }))
+    .expect("parse config failed");
+    let isol = Isolation::new(&config);
+    let traced = |hostcall| isol.wrapped(Wrapper::Trace, hostcall);
+    let (guard, open) = (0x1000, 0x2000);
+
+    // The guarded app resolves `open` to the guard, the unguarded one to
+    // the service, in either order.
+    isol.set_traced(CommonHostCall::Open, guard, true);
+    isol.set_traced(CommonHostCall::Open, open, false);
+    assert_eq!(traced(CommonHostCall::Open), Some(guard));
+
+    isol.set_traced(CommonHostCall::FatfsOpen, open, false);
+    assert_eq!(traced(CommonHostCall::FatfsOpen), Some(open));
+    isol.set_traced(CommonHostCall::FatfsOpen, guard, true);
+    assert_eq!(traced(CommonHostCall::FatfsOpen), Some(guard));
+}
+
+#[test]
+fn route_test() {
+    use as_hostcall::{CommonHostCall, CustomHostCall};
+
+    let config = IsolationConfig::from_value(serde_json::json!({
+        "services": [["ruxfdtab", "libruxfdtab.so"], ["mm", "libmm.so"]],
+        "apps": [],
+        "routes": { "fdtab": "ruxfdtab", "buffer_alloc": "fdtab" },
+    }))
+    .expect("parse config failed");
+    let isol = Isolation::new(&config);
+
+    let route = |hostcall| isol.route(&HostCallID::Common(hostcall)).unwrap();
+    assert_eq!(route(CommonHostCall::Write), "ruxfdtab");
+    assert_eq!(route(CommonHostCall::AccessBuffer), "mm");
+    assert_eq!(route(CommonHostCall::BufferAlloc), "fdtab");
+    let cache_get = HostCallID::Custom(CustomHostCall {
+        service: "cache",
+        name: "get",
+        signature: "fn()",
+    });
+    assert_eq!(isol.route(&cache_get).unwrap(), "cache");
+    assert!(isol
+        .route(&HostCallID::Common(CommonHostCall::Trace))
+        .is_err());
+}
@@ -0,0 +1,280 @@
+//! Capture of the stdout and stderr written by apps of an isolation. Output
+//! is attributed to the app instance running on the current thread, split
+//! into lines and prefixed with `[app#instance]`.
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt::Display,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    sync::Mutex,
+};
+
+use as_hostcall::types::{IsolationID, Size};
+use serde::{Deserialize, Serialize};
+
+use crate::isolation::get_isol;
+
+/// Output kept in memory per isolation, later lines are only written to the
+/// log file and the sink.
+pub const MAX_CAPTURED_BYTES: usize = 4 << 20;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct OutputLine {
+    pub app: String,
+    pub instance: String,
+    pub stream: Stream,
+    pub line: String,
+}
+
+impl Display for OutputLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.stream {
+            Stream::Stdout => write!(f, "[{}#{}] {}", self.app, self.instance, self.line),
+            Stream::Stderr => write!(f, "[{}#{}:err] {}", self.app, self.instance, self.line),
+        }
+    }
+}
+
+/// Called with every complete line, while the capture is locked.
+pub type OutputSink = Box<dyn Fn(&OutputLine) + Send + Sync>;
+
+#[derive(Default)]
+struct CaptureInner {
+    lines: Vec<OutputLine>,
+    bytes: usize,
+    truncated: bool,
+    /// Incomplete lines of each app instance and stream.
+    pending: HashMap<(String, String, Stream), String>,
+}
+
+#[derive(Default)]
+pub struct OutputCapture {
+    inner: Mutex<CaptureInner>,
+    log_file: Option<Mutex<BufWriter<File>>>,
+    sink: Option<OutputSink>,
+}
+
+impl OutputCapture {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Also write every line to `path`, the file is truncated.
+    pub fn log_file(mut self, path: &Path) -> anyhow::Result<Self> {
+        let file = File::create(path)
+            .map_err(|e| anyhow::anyhow!("create log file {} failed: {e}", path.display()))?;
+        self.log_file = Some(Mutex::new(BufWriter::new(file)));
+        Ok(self)
+    }
+
+    pub fn sink(mut self, sink: OutputSink) -> Self {
+        self.sink = Some(sink);
+        self
+    }
+
+    fn write(&self, app: &AppInstance, stream: Stream, buf: &[u8]) {
+        let mut inner = self.inner.lock().unwrap();
+        let key = (app.name.clone(), app.instance.clone(), stream);
+        let pending = inner.pending.entry(key).or_default();
+        pending.push_str(&String::from_utf8_lossy(buf));
+
+        let mut complete = Vec::new();
+        while let Some(pos) = pending.find('\n') {
+            let line: String = pending.drain(..=pos).collect();
+            complete.push(line.trim_end_matches(['\n', '\r']).to_owned());
+        }
+        // A line is not kept whole past the cap, its start is emitted
+        // instead so that output without newlines does not grow unbounded.
+        if pending.len() > MAX_CAPTURED_BYTES {
+            complete.push(std::mem::take(pending));
+            inner.truncated = true;
+        }
+        for line in complete {
+            self.push(&mut inner, app, stream, line);
+        }
+    }
+
+    fn push(&self, inner: &mut CaptureInner, app: &AppInstance, stream: Stream, line: String) {
+        let line = OutputLine {
+            app: app.name.clone(),
+            instance: app.instance.clone(),
+            stream,
+            line,
+        };
+
+        if let Some(log_file) = &self.log_file {
+            let _ = writeln!(log_file.lock().unwrap(), "{}", line);
+        }
+        if let Some(sink) = &self.sink {
+            sink(&line)
+        }
+
+        if inner.bytes + line.line.len() > MAX_CAPTURED_BYTES {
+            inner.truncated = true;
+            return;
+        }
+        inner.bytes += line.line.len();
+        inner.lines.push(line);
+    }
+
+    /// Emit incomplete lines and flush the log file, called when the
+    /// isolation finishes running.
+    pub fn flush(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        let pending: Vec<_> = inner
+            .pending
+            .drain()
+            .filter(|(_, line)| !line.is_empty())
+            .collect();
+        for ((name, instance, stream), line) in pending {
+            self.push(&mut inner, &AppInstance { name, instance }, stream, line);
+        }
+
+        if let Some(log_file) = &self.log_file {
+            let _ = log_file.lock().unwrap().flush();
+        }
+    }
+
+    /// Access the captured lines. Lines are only added while the capture is
+    /// locked, so nothing is missed by a sink subscribed inside `f`.
+    pub fn with_lines<T, F: FnOnce(&[OutputLine]) -> T>(&self, f: F) -> T {
+        f(&self.inner.lock().unwrap().lines)
+    }
+
+    /// Whether lines were dropped because of `MAX_CAPTURED_BYTES`.
+    pub fn truncated(&self) -> bool {
+        self.inner.lock().unwrap().truncated
+    }
+}
+
+struct AppInstance {
+    name: String,
+    instance: String,
+}
+
+thread_local! {
+    static CURRENT_APP: RefCell<Option<(IsolationID, AppInstance)>> = const { RefCell::new(None) };
+}
+
+/// Attribute output of the current thread to an app instance, until the
+/// returned guard is dropped.
+pub(crate) fn enter_app(isol_id: IsolationID, name: String, instance: String) -> AppGuard {
+    CURRENT_APP.with(|current| {
+        *current.borrow_mut() = Some((isol_id, AppInstance { name, instance }));
+    });
+    AppGuard
+}
+
+/// Name and instance of the app running on the current thread.
+pub(crate) fn current_app() -> Option<(String, String)> {
+    CURRENT_APP.with(|current| {
+        let current = current.borrow();
+        let (_, app) = current.as_ref()?;
+        Some((app.name.clone(), app.instance.clone()))
+    })
+}
+
+/// The isolation, name and instance of the app running on the current
+/// thread.
+pub(crate) fn current_instance() -> Option<(IsolationID, String, String)> {
+    CURRENT_APP.with(|current| {
+        let current = current.borrow();
+        let (isol_id, app) = current.as_ref()?;
+        Some((*isol_id, app.name.clone(), app.instance.clone()))
+    })
+}
+
+pub(crate) struct AppGuard;
+
+impl Drop for AppGuard {
+    fn drop(&mut self) {
+        CURRENT_APP.with(|current| *current.borrow_mut() = None)
+    }
+}
+
+fn write_output(stream: Stream, buf: &[u8]) -> Size {
+    let captured = CURRENT_APP.with(|current| {
+        let current = current.borrow();
+        let Some((isol_id, app)) = current.as_ref() else {
+            return false;
+        };
+        let Some(output) = get_isol(*isol_id).ok().and_then(|isol| isol.output().cloned()) else {
+            return false;
+        };
+        output.write(app, stream, buf);
+        true
+    });
+
+    // Threads that are not app instances, such as threads spawned by apps,
+    // fall back to the behavior of the stdio service.
+    if !captured {
+        match stream {
+            Stream::Stdout => print!("{}", String::from_utf8_lossy(buf)),
+            Stream::Stderr => eprint!("{}", String::from_utf8_lossy(buf)),
+        }
+    }
+
+    buf.len()
+}
+
+pub(crate) fn stdout_handler(buf: &[u8]) -> Size {
+    write_output(Stream::Stdout, buf)
+}
+
+pub(crate) fn stderr_handler(buf: &[u8]) -> Size {
+    write_output(Stream::Stderr, buf)
+}
+
+#[test]
+fn output_capture_split_lines_test() {
+    use std::sync::Arc;
+
+    let captured = Arc::new(Mutex::new(Vec::new()));
+    let sink_captured = Arc::clone(&captured);
+    let output = OutputCapture::new().sink(Box::new(move |line| {
+        sink_captured.lock().unwrap().push(line.to_string())
+    }));
+    let app = AppInstance {
+        name: "hello".to_owned(),
+        instance: "0".to_owned(),
+    };
+
+    output.write(&app, Stream::Stdout, b"hello, ");
+    output.write(&app, Stream::Stderr, b"oops\n");
+    output.write(&app, Stream::Stdout, b"world\nbye");
+    output.flush();
+
+    assert_eq!(
+        *captured.lock().unwrap(),
+        vec!["[hello#0:err] oops", "[hello#0] hello, world", "[hello#0] bye"]
+    );
+    output.with_lines(|lines| assert_eq!(lines.len(), 3));
+    assert!(!output.truncated());
+}
+
+#[test]
+fn output_capture_pending_cap_test() {
+    let output = OutputCapture::new();
+    let app = AppInstance {
+        name: "hello".to_owned(),
+        instance: "0".to_owned(),
+    };
+
+    let chunk = vec![b'x'; MAX_CAPTURED_BYTES / 2 + 1];
+    output.write(&app, Stream::Stdout, &chunk);
+    assert!(!output.truncated());
+    output.write(&app, Stream::Stdout, &chunk);
+    assert!(output.truncated());
+    let inner = output.inner.lock().unwrap();
+    assert!(inner.pending.values().all(String::is_empty));
+}
@@ -0,0 +1,270 @@
+//! Policies of what apps may do through hostcalls, set per app by the
+//! `policies` of the config and enforced by `find_app_host_call`.
+//!
+//! Resolved addresses are cached by the library of an app, so apps loaded
+//! from the same library should have the same policy.
+
+use std::{
+    fmt,
+    net::{Ipv4Addr, SocketAddrV4},
+    str::FromStr,
+};
+
+use anyhow::anyhow;
+use as_hostcall::{
+    fatfs::{FatfsError, FatfsOpenFunc, FatfsResult},
+    fdtab::{ConnectFunc, FdtabError, FdtabResult, OpenFunc, ReadDirFunc},
+    socket::{SmoltcpConnectFunc, SmoltcpError, SmoltcpResult},
+    types::{DirEntry, Fd, OpenFlags, OpenMode, SockFd},
+    CommonHostCall,
+};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use super::{get_isol, output, Wrapper};
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Policy {
+    /// Hostcalls and services the app may use, all of them if absent.
+    /// Hostcalls of as-visor itself are always allowed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allow: Option<Vec<String>>,
+    /// Hostcalls and services the app may not use, even if allowed.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deny: Vec<String>,
+    /// Prefixes of the paths the app may open, any path if absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub paths: Option<Vec<String>>,
+    /// Destinations the app may connect to, any if absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub net: Option<Vec<NetRule>>,
+}
+
+impl Policy {
+    /// Whether the app may resolve `hostcall`, which is routed to `svc`.
+    pub fn allows_hostcall(&self, hostcall: &str, svc: &str) -> bool {
+        let listed = |list: &[String]| list.iter().any(|name| name == hostcall || name == svc);
+        self.allow.as_deref().is_none_or(listed) && !listed(&self.deny)
+    }
+
+    pub fn allows_path(&self, path: &str) -> bool {
+        let Some(prefixes) = &self.paths else {
+            return true;
+        };
+        // `..` could leave an allowed directory.
+        if path.split('/').any(|part| part == "..") {
+            return false;
+        }
+        prefixes.iter().any(|prefix| {
+            path.strip_prefix(prefix.as_str()).is_some_and(|rest| {
+                rest.is_empty() || rest.starts_with('/') || prefix.ends_with('/')
+            })
+        })
+    }
+
+    pub fn allows_addr(&self, addr: &SocketAddrV4) -> bool {
+        self.net
+            .as_ref()
+            .is_none_or(|rules| rules.iter().any(|rule| rule.matches(addr)))
+    }
+
+    /// The guard `hostcall` is resolved to instead, if the policy checks its
+    /// arguments. Every hostcall taking a path is guarded by `paths`; the
+    /// others, such as `register_file_backend`, take fds of guarded opens.
+    pub(crate) fn guard_of(&self, hostcall: CommonHostCall) -> Option<usize> {
+        let guard = match hostcall {
+            CommonHostCall::Open if self.paths.is_some() => open_guard as OpenFunc as usize,
+            CommonHostCall::ReadDir if self.paths.is_some() => {
+                readdir_guard as ReadDirFunc as usize
+            }
+            CommonHostCall::FatfsOpen if self.paths.is_some() => {
+                fatfs_open_guard as FatfsOpenFunc as usize
+            }
+            CommonHostCall::Connect if self.net.is_some() => connect_guard as ConnectFunc as usize,
+            CommonHostCall::SmoltcpConnect if self.net.is_some() => {
+                smol_connect_guard as SmoltcpConnectFunc as usize
+            }
+            _ => return None,
+        };
+
+        Some(guard)
+    }
+}
+
+/// A network destination: an address such as `10.0.0.1` or a subnet such as
+/// `10.0.0.0/8`, optionally with a `:port`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(try_from = "String", into = "String")]
+pub struct NetRule {
+    addr: Ipv4Addr,
+    prefix_len: u8,
+    port: Option<u16>,
+}
+
+impl NetRule {
+    pub fn matches(&self, dest: &SocketAddrV4) -> bool {
+        let mask = u32::MAX
+            .checked_shl(32 - self.prefix_len as u32)
+            .unwrap_or(0);
+        u32::from(*dest.ip()) & mask == u32::from(self.addr) & mask
+            && self.port.is_none_or(|port| port == dest.port())
+    }
+}
+
+impl FromStr for NetRule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (net, port) = match s.split_once(':') {
+            Some((net, port)) => {
+                let port = port.parse().map_err(|_| anyhow!("bad port: {}", s))?;
+                (net, Some(port))
+            }
+            None => (s, None),
+        };
+        let (addr, prefix_len) = match net.split_once('/') {
+            Some((addr, len)) => {
+                let len = len.parse().ok().filter(|len| *len <= 32);
+                (
+                    addr,
+                    len.ok_or_else(|| anyhow!("bad prefix length: {}", s))?,
+                )
+            }
+            None => (net, 32),
+        };
+        let addr = addr.parse().map_err(|_| anyhow!("bad address: {}", s))?;
+
+        Ok(Self {
+            addr,
+            prefix_len,
+            port,
+        })
+    }
+}
+
+impl TryFrom<String> for NetRule {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for NetRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.addr)?;
+        if self.prefix_len != 32 {
+            write!(f, "/{}", self.prefix_len)?;
+        }
+        if let Some(port) = self.port {
+            write!(f, ":{}", port)?;
+        }
+        Ok(())
+    }
+}
+
+impl From<NetRule> for String {
+    fn from(rule: NetRule) -> Self {
+        rule.to_string()
+    }
+}
+
+/// The function of the service a guard of `hostcall` calls, if the policy
+/// of the app on the current thread passes `check`.
+fn guarded<F>(hostcall: CommonHostCall, check: impl FnOnce(&Policy) -> bool) -> Option<F> {
+    let (isol_id, app, _) = output::current_instance()?;
+    let isol = get_isol(isol_id).ok()?;
+    if !isol.policy(&app).is_none_or(check) {
+        warn!("{} of app {} is denied by its policy", hostcall, app);
+        return None;
+    }
+    let addr = isol.wrapped(Wrapper::Policy, hostcall)?;
+
+    Some(unsafe { std::mem::transmute_copy::<usize, F>(&addr) })
+}
+
+fn open_guard(path: &str, flags: OpenFlags, mode: OpenMode) -> FdtabResult<Fd> {
+    let open: OpenFunc = guarded(CommonHostCall::Open, |policy| policy.allows_path(path))
+        .ok_or(FdtabError::Denied)?;
+    open(path, flags, mode)
+}
+
+fn readdir_guard(path: &str) -> FdtabResult<Vec<DirEntry>> {
+    let readdir: ReadDirFunc = guarded(CommonHostCall::ReadDir, |policy| policy.allows_path(path))
+        .ok_or(FdtabError::Denied)?;
+    readdir(path)
+}
+
+fn fatfs_open_guard(path: &str, flags: OpenFlags) -> FatfsResult<Fd> {
+    let open: FatfsOpenFunc = guarded(CommonHostCall::FatfsOpen, |policy| policy.allows_path(path))
+        .ok_or(FatfsError::Denied)?;
+    open(path, flags)
+}
+
+fn connect_guard(addr: SocketAddrV4) -> FdtabResult<Fd> {
+    let connect: ConnectFunc = guarded(CommonHostCall::Connect, |policy| policy.allows_addr(&addr))
+        .ok_or(FdtabError::Denied)?;
+    connect(addr)
+}
+
+fn smol_connect_guard(addr: SocketAddrV4) -> SmoltcpResult<SockFd> {
+    let connect: SmoltcpConnectFunc = guarded(CommonHostCall::SmoltcpConnect, |policy| {
+        policy.allows_addr(&addr)
+    })
+    .ok_or(SmoltcpError::Denied)?;
+    connect(addr)
+}
+
+#[test]
+fn policy_test() {
+    let policy: Policy = serde_json::from_value(serde_json::json!({
+        "allow": ["fdtab", "host_stdout"],
+        "deny": ["connect"],
+        "paths": ["/data", "/tmp/"],
+        "net": ["10.0.0.0/8:80", "192.168.1.1"],
+    }))
+    .unwrap();
+
+    assert!(policy.allows_hostcall("write", "fdtab"));
+    assert!(policy.allows_hostcall("host_stdout", "stdio"));
+    assert!(!policy.allows_hostcall("host_stderr", "stdio"));
+    assert!(!policy.allows_hostcall("connect", "fdtab"));
+
+    assert!(policy.allows_path("/data"));
+    assert!(policy.allows_path("/data/input.txt"));
+    assert!(policy.allows_path("/tmp/out"));
+    assert!(!policy.allows_path("/database"));
+    assert!(!policy.allows_path("/data/../etc/passwd"));
+
+    let addr = |s: &str| s.parse::<SocketAddrV4>().unwrap();
+    assert!(policy.allows_addr(&addr("10.1.2.3:80")));
+    assert!(!policy.allows_addr(&addr("10.1.2.3:443")));
+    assert!(policy.allows_addr(&addr("192.168.1.1:22")));
+    assert!(!policy.allows_addr(&addr("192.168.1.2:22")));
+
+    for hostcall in [
+        CommonHostCall::Open,
+        CommonHostCall::ReadDir,
+        CommonHostCall::FatfsOpen,
+        CommonHostCall::Connect,
+        CommonHostCall::SmoltcpConnect,
+    ] {
+        assert!(
+            policy.guard_of(hostcall).is_some(),
+            "{} is not guarded",
+            hostcall
+        );
+    }
+    assert!(policy.guard_of(CommonHostCall::Read).is_none());
+    assert!(policy
+        .guard_of(CommonHostCall::RegisterFileBackend)
+        .is_none());
+    let unrestricted = Policy::default();
+    assert!(unrestricted.guard_of(CommonHostCall::ReadDir).is_none());
+
+    assert!("10.0.0.0/33".parse::<NetRule>().is_err());
+    assert_eq!(
+        serde_json::to_value(&policy.net).unwrap(),
+        serde_json::json!(["10.0.0.0/8:80", "192.168.1.1"])
+    );
+}
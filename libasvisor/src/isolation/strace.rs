@@ -0,0 +1,239 @@
+//! Hostcall log of isolations that `trace_hostcalls`, like strace. Apps of
+//! such an isolation resolve I/O, network and buffer hostcalls to wrappers
+//! here, which call the resolved address and print a line per call:
+//!
+//! ```text
+//! [1697612345.123456] isolation_1 file_reader#0 read(fd=3, len=4096) = Ok(4096) <0.000012>
+//! ```
+//!
+//! Hostcalls between services are not logged, and calls on threads that run
+//! no app, such as threads spawned by an app, are logged with an unknown
+//! caller.
+
+use std::{
+    alloc::Layout,
+    collections::HashMap,
+    fmt::Debug,
+    mem::transmute,
+    net::{Ipv4Addr, SocketAddrV4},
+    sync::Mutex,
+};
+
+use as_hostcall::{
+    fatfs::{
+        FatfsCloseFunc, FatfsOpenFunc, FatfsPreadFunc, FatfsPwriteFunc, FatfsReadFunc, FatfsResult,
+        FatfsSeekFunc, FatfsStatFunc, FatfsWriteFunc,
+    },
+    fdtab::{
+        AcceptFunc, BindFunc, CloseFunc, ConnectFunc, FdtabResult, LseekFunc, OpenFunc, PreadFunc,
+        PwriteFunc, ReadDirFunc, ReadFunc, ReadvFunc, StatFunc, WriteFunc, WritevFunc,
+    },
+    mm::{AccessBufferFunc, BufferAllocFunc, BufferDeallocFunc, MMResult},
+    socket::{
+        SmoltcpAcceptFunc, SmoltcpAddrInfoFunc, SmoltcpBindFunc, SmoltcpCloseFunc,
+        SmoltcpConnectFunc, SmoltcpRecvFunc, SmoltcpResult, SmoltcpSendFunc,
+    },
+    types::{DirEntry, Fd, IsolationID, OpenFlags, OpenMode, Size, SockFd, Stat, Whence},
+    CommonHostCall,
+};
+use lazy_static::lazy_static;
+
+use super::{get_isol, output, Wrapper};
+use crate::now_microsec;
+
+lazy_static! {
+    /// Addresses the wrappers call on threads that run no app, whose
+    /// isolation is not known: those of the isolation that resolved the
+    /// hostcall last, or its policy guard once any did, which denies calls
+    /// out of an app.
+    static ref UNKNOWN_CALLER_TARGETS: Mutex<HashMap<CommonHostCall, (usize, bool)>> =
+        Mutex::new(HashMap::new());
+}
+
+pub(crate) fn set_unknown_caller_target(hostcall: CommonHostCall, addr: usize, guard: bool) {
+    let mut targets = UNKNOWN_CALLER_TARGETS.lock().unwrap();
+    let guarded = targets.get(&hostcall).is_some_and(|(_, guarded)| *guarded);
+    if guard || !guarded {
+        targets.insert(hostcall, (addr, guard));
+    }
+}
+
+/// A hostcall being called by an app.
+struct TracedCall {
+    hostcall: CommonHostCall,
+    /// The isolation, app and instance, unless the thread runs no app.
+    caller: Option<(IsolationID, String, String)>,
+    target: usize,
+    begin_us: u128,
+}
+
+impl TracedCall {
+    fn begin(hostcall: CommonHostCall) -> Self {
+        let caller = output::current_instance();
+        let target = caller
+            .as_ref()
+            .and_then(|(isol_id, ..)| get_isol(*isol_id).ok())
+            .and_then(|isol| isol.wrapped(Wrapper::Trace, hostcall))
+            .or_else(|| {
+                let targets = UNKNOWN_CALLER_TARGETS.lock().unwrap();
+                targets.get(&hostcall).map(|(addr, _)| *addr)
+            })
+            .unwrap_or_else(|| panic!("traced hostcall {} is not resolved?", hostcall));
+
+        Self {
+            hostcall,
+            caller,
+            target,
+            begin_us: now_microsec!(),
+        }
+    }
+
+    fn end(self, args: &str, res: &dyn Debug) {
+        let caller = match &self.caller {
+            Some((isol_id, app, instance)) => {
+                format!("isolation_{} {}#{}", isol_id, app, instance)
+            }
+            None => "<unknown>".to_owned(),
+        };
+        let line = call_line(
+            self.begin_us,
+            now_microsec!() - self.begin_us,
+            &caller,
+            &format!("{}({})", self.hostcall, args),
+            res,
+        );
+        eprintln!("{}", line)
+    }
+}
+
+fn call_line(begin_us: u128, dur_us: u128, caller: &str, call: &str, res: &dyn Debug) -> String {
+    format!(
+        "[{}.{:06}] {} {} = {:?} <{}.{:06}>",
+        begin_us / 1_000_000,
+        begin_us % 1_000_000,
+        caller,
+        call,
+        res,
+        dur_us / 1_000_000,
+        dur_us % 1_000_000
+    )
+}
+
+/// Define a wrapper of type `$func` for each hostcall, which logs its
+/// arguments as `$fmt`, and `wrapper_of` to find them.
+macro_rules! traced {
+    ($($hostcall:ident: $func:ty =>
+        fn $name:ident($($arg:ident: $ty:ty),*) $(-> $ret:ty)?, $fmt:literal $(, $fmt_arg:expr)*;)*) => {
+        $(
+            fn $name($($arg: $ty),*) $(-> $ret)? {
+                let args = format!($fmt $(, $fmt_arg)*);
+                let call = TracedCall::begin(CommonHostCall::$hostcall);
+                let func: $func = unsafe { transmute(call.target) };
+                let res = func($($arg),*);
+                call.end(&args, &res);
+                res
+            }
+        )*
+
+        /// The wrapper `hostcall` is resolved to if the isolation traces
+        /// hostcalls.
+        pub(crate) fn wrapper_of(hostcall: CommonHostCall) -> Option<usize> {
+            match hostcall {
+                $(CommonHostCall::$hostcall => Some($name as $func as usize),)*
+                _ => None,
+            }
+        }
+    };
+}
+
+traced! {
+    Open: OpenFunc => fn open(path: &str, flags: OpenFlags, mode: OpenMode) -> FdtabResult<Fd>,
+        "path={:?}, flags={:?}, mode={:?}", path, flags, mode;
+    Write: WriteFunc => fn write(fd: Fd, buf: &[u8]) -> FdtabResult<Size>,
+        "fd={}, len={}", fd, buf.len();
+    Read: ReadFunc => fn read(fd: Fd, buf: &mut [u8]) -> FdtabResult<Size>,
+        "fd={}, len={}", fd, buf.len();
+    Close: CloseFunc => fn close(fd: Fd) -> FdtabResult<()>, "fd={}", fd;
+    Lseek: LseekFunc => fn lseek(fd: Fd, offset: i64, whence: Whence) -> FdtabResult<u64>,
+        "fd={}, offset={}, whence={:?}", fd, offset, whence;
+    Stat: StatFunc => fn stat(fd: Fd) -> FdtabResult<Stat>, "fd={}", fd;
+    ReadDir: ReadDirFunc => fn readdir(path: &str) -> FdtabResult<Vec<DirEntry>>,
+        "path={:?}", path;
+    Connect: ConnectFunc => fn connect(addr: SocketAddrV4) -> FdtabResult<Fd>, "addr={}", addr;
+    Bind: BindFunc => fn bind(addr: SocketAddrV4) -> FdtabResult<Fd>, "addr={}", addr;
+    Accept: AcceptFunc => fn accept(fd: SockFd) -> FdtabResult<SockFd>, "fd={}", fd;
+    Readv: ReadvFunc => fn readv(fd: Fd, bufs: &mut [&mut [u8]]) -> FdtabResult<Size>,
+        "fd={}, iovcnt={}", fd, bufs.len();
+    Writev: WritevFunc => fn writev(fd: Fd, bufs: &[&[u8]]) -> FdtabResult<Size>,
+        "fd={}, iovcnt={}", fd, bufs.len();
+    Pread: PreadFunc => fn pread(fd: Fd, buf: &mut [u8], offset: u64) -> FdtabResult<Size>,
+        "fd={}, len={}, offset={}", fd, buf.len(), offset;
+    Pwrite: PwriteFunc => fn pwrite(fd: Fd, buf: &[u8], offset: u64) -> FdtabResult<Size>,
+        "fd={}, len={}, offset={}", fd, buf.len(), offset;
+
+    FatfsOpen: FatfsOpenFunc => fn fatfs_open(path: &str, flags: OpenFlags) -> FatfsResult<Fd>,
+        "path={:?}, flags={:?}", path, flags;
+    FatfsWrite: FatfsWriteFunc => fn fatfs_write(fd: Fd, buf: &[u8]) -> FatfsResult<Size>,
+        "fd={}, len={}", fd, buf.len();
+    FatfsRead: FatfsReadFunc => fn fatfs_read(fd: Fd, buf: &mut [u8]) -> FatfsResult<Size>,
+        "fd={}, len={}", fd, buf.len();
+    FatfsClose: FatfsCloseFunc => fn fatfs_close(fd: Fd) -> FatfsResult<()>, "fd={}", fd;
+    FatfsSeek: FatfsSeekFunc =>
+        fn fatfs_seek(fd: Fd, offset: i64, whence: Whence) -> FatfsResult<u64>,
+        "fd={}, offset={}, whence={:?}", fd, offset, whence;
+    FatfsStat: FatfsStatFunc => fn fatfs_stat(fd: Fd) -> FatfsResult<Stat>, "fd={}", fd;
+    FatfsPread: FatfsPreadFunc =>
+        fn fatfs_pread(fd: Fd, buf: &mut [u8], offset: u64) -> FatfsResult<Size>,
+        "fd={}, len={}, offset={}", fd, buf.len(), offset;
+    FatfsPwrite: FatfsPwriteFunc =>
+        fn fatfs_pwrite(fd: Fd, buf: &[u8], offset: u64) -> FatfsResult<Size>,
+        "fd={}, len={}, offset={}", fd, buf.len(), offset;
+
+    SmoltcpAddrInfo: SmoltcpAddrInfoFunc =>
+        fn addrinfo(name: &str) -> SmoltcpResult<Ipv4Addr>, "name={:?}", name;
+    SmoltcpConnect: SmoltcpConnectFunc =>
+        fn smol_connect(addr: SocketAddrV4) -> SmoltcpResult<SockFd>, "addr={}", addr;
+    SmoltcpSend: SmoltcpSendFunc => fn send(fd: SockFd, buf: &[u8]) -> SmoltcpResult<()>,
+        "fd={}, len={}", fd, buf.len();
+    SmoltcpRecv: SmoltcpRecvFunc => fn recv(fd: SockFd, buf: &mut [u8]) -> SmoltcpResult<Size>,
+        "fd={}, len={}", fd, buf.len();
+    SmoltcpBind: SmoltcpBindFunc =>
+        fn smol_bind(addr: SocketAddrV4) -> SmoltcpResult<SockFd>, "addr={}", addr;
+    SmoltcpAccept: SmoltcpAcceptFunc => fn smol_accept(fd: SockFd) -> SmoltcpResult<SockFd>,
+        "fd={}", fd;
+    SmoltcpClose: SmoltcpCloseFunc => fn smol_close(fd: SockFd) -> SmoltcpResult<()>,
+        "fd={}", fd;
+
+    BufferAlloc: BufferAllocFunc =>
+        fn buffer_alloc(slot: &str, layout: Layout, fingerprint: u64) -> MMResult<usize>,
+        "slot={:?}, size={}, fingerprint={:#x}", slot, layout.size(), fingerprint;
+    AccessBuffer: AccessBufferFunc =>
+        fn access_buffer(slot: &str, fingerprint: u64) -> Option<(usize, u64)>,
+        "slot={:?}, fingerprint={:#x}", slot, fingerprint;
+    BufferDealloc: BufferDeallocFunc => fn buffer_dealloc(addr: usize, layout: Layout),
+        "addr={:#x}, size={}", addr, layout.size();
+}
+
+#[test]
+fn call_line_test() {
+    let res: FdtabResult<Size> = Ok(4096);
+    assert_eq!(
+        call_line(
+            1_697_612_345_000_012,
+            1_000_034,
+            "isolation_1 file_reader#0",
+            "read(fd=3, len=4096)",
+            &res
+        ),
+        "[1697612345.000012] isolation_1 file_reader#0 read(fd=3, len=4096) = Ok(4096) <1.000034>"
+    );
+
+    assert!(wrapper_of(CommonHostCall::FatfsRead).is_some());
+
+    // A guard is kept for threads that run no app.
+    set_unknown_caller_target(CommonHostCall::Readv, 0x1000, true);
+    set_unknown_caller_target(CommonHostCall::Readv, 0x2000, false);
+    let targets = UNKNOWN_CALLER_TARGETS.lock().unwrap();
+    assert_eq!(targets.get(&CommonHostCall::Readv), Some(&(0x1000, true)));
+    assert!(wrapper_of(CommonHostCall::Metric).is_none());
+}
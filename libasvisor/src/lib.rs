@@ -12,13 +12,17 @@ pub mod isolation;
 pub mod logger;
 mod metric;
 pub mod service;
+pub mod trace;
 pub mod utils;
 use std::sync::Arc;
 
 #[cfg(feature = "enable_mpk")]
 pub mod mpk;
 
-pub use metric::MetricOpt;
+pub use metric::{
+    get_current_pss_kb, get_current_vm_rss_kb, HostcallStatSnapshot, IsolMetricSnapshot,
+    MemSample, MetricBucket, MetricOpt, SvcMetricSnapshot, SvcThreadSnapshot,
+};
 
 pub use hostcalls::{GetHandlerFuncSybmol, RustMainFuncSybmol, SetHandlerFuncSybmol};
 
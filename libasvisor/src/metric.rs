@@ -1,15 +1,23 @@
 use std::{
+    cell::RefCell,
+    collections::BTreeMap,
     fs,
     iter::zip,
-    sync::{Arc, Mutex},
-    time::UNIX_EPOCH,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    time::Instant,
 };
 
-use as_hostcall::types::{MetricEvent, ServiceName};
+use as_hostcall::{
+    mm::{BufferStats, HeapStats},
+    types::{IsolationID, MetricEvent, ServiceName, TraceEvent},
+};
 use serde::Serialize;
 use serde_json::{json, Value};
 
-use crate::{now_microsec, now_millis};
+use crate::{isolation::output, now_microsec, now_millis, trace::TraceBuffer};
 
 #[derive(Default, Serialize)]
 struct MetricBucketInner {
@@ -17,22 +25,54 @@ struct MetricBucketInner {
     svc_metrics: Vec<Arc<SvcMetricBucket>>,
 
     begin_t: u128,
+    run_t: u128,
     end_t: u128,
     load_service_num: u32,
-    mem_metrics: Vec<(u128, usize)>,
+    mem_metrics: Vec<MemSample>,
+    heap: BTreeMap<ServiceName, HeapMetric>,
+    /// High-water marks of each app.
+    app_mem: BTreeMap<ServiceName, AppMemMetric>,
+    buffer_alloc_bytes: usize,
+    buffer_transferred_bytes: usize,
+    buffer_in_use_bytes: usize,
 }
 
 impl MetricBucketInner {
+    fn push_mem(&mut self, sample: MemSample) {
+        if let Some(app) = &sample.app {
+            let app_mem = self.app_mem.entry(app.clone()).or_default();
+            app_mem.rss_peak_kb = app_mem.rss_peak_kb.max(sample.rss_kb);
+            app_mem.pss_peak_kb = app_mem.pss_peak_kb.max(sample.pss_kb);
+        }
+        self.mem_metrics.push(sample)
+    }
+
     fn to_json(&self) -> Value {
         let mut val = serde_json::json!(self);
-        val["total_dur(ms)"] = json!((self.end_t - self.begin_t) as f32 / 1000.);
+        // An isolation that failed or is still running has no end time.
+        if self.end_t > 0 {
+            val["total_dur(ms)"] = json!((self.end_t - self.begin_t) as f32 / 1000.);
+        }
 
         val
     }
 }
 
+/// Hostcall stats by app and hostcall.
+type AppHostcalls = BTreeMap<ServiceName, BTreeMap<String, HostcallStat>>;
+
+static NEXT_BUCKET_ID: AtomicU64 = AtomicU64::new(1);
+
 pub struct MetricBucket {
+    /// Tells the buckets apart in `THREAD_HOSTCALLS`.
+    id: u64,
     inner: Mutex<MetricBucketInner>,
+    /// `None` unless `enable_trace` is called.
+    trace: Mutex<Option<TraceBuffer>>,
+    /// Latency of hostcalls, a buffer per thread that made hostcalls, unset
+    /// unless `enable_hostcall_stats` is called. Buffers are merged when
+    /// read, so threads do not contend while recording.
+    hostcalls: OnceLock<Mutex<Vec<Arc<Mutex<AppHostcalls>>>>>,
 }
 
 impl Default for MetricBucket {
@@ -44,7 +84,10 @@ impl Default for MetricBucket {
 impl MetricBucket {
     pub fn new() -> Self {
         MetricBucket {
+            id: NEXT_BUCKET_ID.fetch_add(1, Ordering::Relaxed),
             inner: Mutex::new(Default::default()),
+            trace: Mutex::new(None),
+            hostcalls: OnceLock::new(),
         }
     }
 
@@ -56,24 +99,25 @@ impl MetricBucket {
         svc_metric
     }
 
+    /// Count the isolation as beginning now, for an isolation whose modules
+    /// were loaded ahead of the run it is used for.
+    pub fn restart(&self) {
+        self.inner.lock().unwrap().begin_t = now_microsec!()
+    }
+
     pub fn mark(&self, event: MetricEvent) {
         let mut inner = self.inner.lock().unwrap();
         match event {
             MetricEvent::LoadService => inner.load_service_num += 1,
-            MetricEvent::Mem => {
-                let timestamp = std::time::SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .unwrap()
-                    .as_micros();
-                // let vm_rss_kb = get_current_vm_rss_kb();
-                let vm_rss_kb = 0;
-
-                inner.mem_metrics.push((timestamp, vm_rss_kb))
-            }
+            MetricEvent::Mem => inner.push_mem(MemSample::of_process()),
             MetricEvent::IsolBegin => {
                 assert_eq!(inner.begin_t, 0);
                 inner.begin_t = now_microsec!()
             }
+            MetricEvent::IsolRun => {
+                assert_eq!(inner.run_t, 0);
+                inner.run_t = now_microsec!()
+            }
             MetricEvent::IsolEnd => {
                 assert_eq!(inner.end_t, 0);
                 inner.end_t = now_microsec!()
@@ -88,12 +132,190 @@ impl MetricBucket {
         }
     }
 
-    pub fn analyze(&self, opt: &MetricOpt) {
+    /// Record a memory sample with the modules of the isolation, see
+    /// `Isolation::sample_mem`. `mark(Mem)` only samples the process.
+    pub fn record_mem(&self, sample: MemSample) {
+        self.inner.lock().unwrap().push_mem(sample)
+    }
+
+    /// Heap statistics of the loaded modules, `apps` get their peak in the
+    /// high-water marks of apps.
+    pub fn set_heap_stats(&self, stats: &BTreeMap<ServiceName, HeapStats>, apps: &[ServiceName]) {
+        let mut inner = self.inner.lock().unwrap();
+        for (name, stats) in stats {
+            inner.heap.insert(name.clone(), HeapMetric::from(stats));
+            if apps.contains(name) {
+                inner
+                    .app_mem
+                    .entry(name.clone())
+                    .or_default()
+                    .heap_peak_bytes = stats.peak_bytes;
+            }
+        }
+    }
+
+    /// Record a trace of the execution, see `crate::trace`. Must be called
+    /// before the isolation loads any module.
+    pub fn enable_trace(&self) {
+        self.trace
+            .lock()
+            .unwrap()
+            .get_or_insert_with(Default::default);
+    }
+
+    pub fn tracing(&self) -> bool {
+        self.trace.lock().unwrap().is_some()
+    }
+
+    /// Count and time every hostcall made through `libos!`. Must be called
+    /// before the isolation loads any module.
+    pub fn enable_hostcall_stats(&self) {
+        self.hostcalls.get_or_init(Default::default);
+    }
+
+    /// Whether modules should report `TraceEvent`s.
+    pub(crate) fn observes_hostcalls(&self) -> bool {
+        self.tracing() || self.hostcalls.get().is_some()
+    }
+
+    /// A span that ends now, if tracing.
+    pub(crate) fn trace_span(&self, name: String, cat: &str, begin_us: u128) {
+        if let Some(trace) = self.trace.lock().unwrap().as_mut() {
+            trace.span(name, cat, begin_us)
+        }
+    }
+
+    pub(crate) fn trace_event(&self, event: TraceEvent) {
+        if let TraceEvent::HostcallBegin(_) = event {
+            // Timed after recording the trace, which is not part of the
+            // hostcall.
+            self.record_trace(event);
+            HOSTCALL_BEGIN.with(|begin| begin.borrow_mut().push(Instant::now()));
+            return;
+        }
+
+        match event {
+            TraceEvent::HostcallEnd(ref hostcall) => {
+                if let Some(begin) = HOSTCALL_BEGIN.with(|begin| begin.borrow_mut().pop()) {
+                    self.hostcall_stat(hostcall.to_string(), |stat| {
+                        let dur_ns = begin.elapsed().as_nanos();
+                        stat.count += 1;
+                        stat.total_ns += dur_ns;
+                        stat.max_ns = stat.max_ns.max(dur_ns);
+                    })
+                }
+            }
+            TraceEvent::PkeySwitch(ref hostcall, cycles) => {
+                self.hostcall_stat(hostcall.to_string(), |stat| {
+                    stat.pkey_switch_cycles += cycles
+                });
+            }
+            _ => {}
+        }
+        self.record_trace(event)
+    }
+
+    fn record_trace(&self, event: TraceEvent) {
+        if let Some(trace) = self.trace.lock().unwrap().as_mut() {
+            trace.record(event)
+        }
+    }
+
+    /// Update the stat of `hostcall` of the app on the current thread, if
+    /// hostcall statistics are enabled.
+    fn hostcall_stat(&self, hostcall: String, update: impl FnOnce(&mut HostcallStat)) {
+        let Some(threads) = self.hostcalls.get() else {
+            return;
+        };
+        let app = output::current_app()
+            .map(|(app, _)| app)
+            .unwrap_or_else(|| "(no app)".to_owned());
+
+        THREAD_HOSTCALLS.with(|current| {
+            let mut current = current.borrow_mut();
+            if current.as_ref().map(|(id, _)| *id) != Some(self.id) {
+                let stats = Arc::new(Mutex::default());
+                threads.lock().unwrap().push(Arc::clone(&stats));
+                *current = Some((self.id, stats));
+            }
+            let (_, stats) = current.as_ref().unwrap();
+            let mut stats = stats.lock().unwrap();
+            update(stats.entry(app).or_default().entry(hostcall).or_default());
+        })
+    }
+
+    /// Hostcall stats of all threads, `None` unless `enable_hostcall_stats`
+    /// is called.
+    fn hostcall_stats(&self) -> Option<AppHostcalls> {
+        let threads = self.hostcalls.get()?;
+        let mut merged = AppHostcalls::new();
+        for stats in threads.lock().unwrap().iter() {
+            for (app, hostcalls) in stats.lock().unwrap().iter() {
+                let app_stats = merged.entry(app.clone()).or_default();
+                for (hostcall, stat) in hostcalls {
+                    app_stats.entry(hostcall.clone()).or_default().merge(stat)
+                }
+            }
+        }
+
+        Some(merged)
+    }
+
+    /// Chrome Trace Events recorded so far, empty if not tracing.
+    pub fn trace_events(&self, isol_id: IsolationID) -> Vec<Value> {
+        self.trace
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|trace| trace.to_events(isol_id))
+            .unwrap_or_default()
+    }
+
+    pub fn set_buffer_stats(&self, stats: &BufferStats) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.buffer_alloc_bytes = stats.alloc_bytes;
+        inner.buffer_transferred_bytes = stats.transferred_bytes;
+        inner.buffer_in_use_bytes = stats.in_use_bytes;
+    }
+
+    /// Structured copy of the collected metrics, for exporters that need
+    /// numbers rather than the json of `to_json`.
+    pub fn snapshot(&self) -> IsolMetricSnapshot {
+        let inner = self.inner.lock().unwrap();
+        let run_t_millis = inner.run_t / 1000;
+
+        IsolMetricSnapshot {
+            total_dur_us: (inner.end_t > 0).then(|| inner.end_t - inner.begin_t),
+            load_service_num: inner.load_service_num,
+            hostcalls: self
+                .hostcall_stats()
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(app, hostcalls)| {
+                    let hostcalls = hostcalls
+                        .into_iter()
+                        .map(|(hostcall, stat)| (hostcall, stat.snapshot()))
+                        .collect();
+                    (app, hostcalls)
+                })
+                .collect(),
+            buffer_alloc_bytes: inner.buffer_alloc_bytes,
+            buffer_transferred_bytes: inner.buffer_transferred_bytes,
+            services: inner
+                .svc_metrics
+                .iter()
+                .map(|metric| metric.snapshot(run_t_millis))
+                .collect(),
+        }
+    }
+
+    /// Build the metrics json selected by `opt`, `None` for `MetricOpt::None`.
+    pub fn to_json(&self, opt: &MetricOpt) -> Option<Value> {
         let inner = self.inner.lock().unwrap();
         let mut result = serde_json::Value::default();
 
         match opt {
-            MetricOpt::None => return,
+            MetricOpt::None => return None,
             MetricOpt::All => {
                 // let mut total_run_dur = 0.;
                 result["services"] = {
@@ -109,20 +331,142 @@ impl MetricBucket {
                     // }
                     serde_json::json!(svc_metrics)
                 };
-                let isol = inner.to_json();
+                let mut isol = inner.to_json();
+                if let Some(hostcalls) = self.hostcall_stats() {
+                    isol["hostcalls"] = json!(hostcalls);
+                }
                 // isol["other(ms)"] = json!(isol["total_dur(ms)"].as_f64().unwrap() - total_run_dur);
                 result["isolation"] = isol;
             }
-            MetricOpt::Mem => result["mem_metrics"] = json!(inner.mem_metrics),
+            MetricOpt::Mem => {
+                result["mem_metrics"] = json!(inner.mem_metrics);
+                result["heap"] = json!(inner.heap);
+                result["apps"] = json!(inner.app_mem);
+                result["buffer_in_use_bytes"] = json!(inner.buffer_in_use_bytes);
+            }
             MetricOpt::TotalDur => {
-                result["total_dur(ms)"] = inner.to_json().get("total_dur(ms)").unwrap().clone()
+                result["total_dur(ms)"] = inner.to_json()["total_dur(ms)"].clone()
             }
         }
 
-        eprintln!(
-            "{}",
-            serde_json::to_string_pretty(&result).expect("format json failed")
-        );
+        Some(result)
+    }
+
+    pub fn analyze(&self, opt: &MetricOpt) {
+        if let Some(result) = self.to_json(opt) {
+            eprintln!(
+                "{}",
+                serde_json::to_string_pretty(&result).expect("format json failed")
+            );
+        }
+    }
+
+    /// Per-service progress of a running isolation: how many threads of each
+    /// loaded module have started and finished.
+    pub fn progress(&self) -> Value {
+        let inner = self.inner.lock().unwrap();
+        let services: Vec<Value> = inner
+            .svc_metrics
+            .iter()
+            .map(|metric| metric.progress())
+            .collect();
+
+        json!({
+            "load_service_num": inner.load_service_num,
+            "services": services,
+        })
+    }
+}
+
+/// Memory of the process and of the modules of an isolation at one point.
+#[derive(Default, Serialize)]
+pub struct MemSample {
+    /// Microseconds since the unix epoch.
+    pub timestamp: u128,
+    pub rss_kb: usize,
+    pub pss_kb: usize,
+    /// App that asked for the sample through `MetricEvent::Mem`, `None` if
+    /// as-visor took it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub app: Option<ServiceName>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+    /// Heap bytes in use of each loaded module.
+    pub heap_used_bytes: BTreeMap<ServiceName, usize>,
+    /// Data buffer bytes allocated and not yet deallocated.
+    pub buffer_in_use_bytes: usize,
+}
+
+impl MemSample {
+    /// A sample of the process only.
+    pub fn of_process() -> Self {
+        Self {
+            timestamp: now_microsec!(),
+            rss_kb: get_current_vm_rss_kb(),
+            pss_kb: get_current_pss_kb().unwrap_or_default(),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct HeapMetric {
+    used_bytes: usize,
+    free_bytes: usize,
+    peak_bytes: usize,
+}
+
+impl From<&HeapStats> for HeapMetric {
+    fn from(stats: &HeapStats) -> Self {
+        Self {
+            used_bytes: stats.used_bytes,
+            free_bytes: stats.free_bytes,
+            peak_bytes: stats.peak_bytes,
+        }
+    }
+}
+
+#[derive(Default, Serialize)]
+struct AppMemMetric {
+    /// Peak of the app's own heap, shared by its instances.
+    heap_peak_bytes: usize,
+    /// Peaks of the process among the samples the app asked for.
+    rss_peak_kb: usize,
+    pss_peak_kb: usize,
+}
+
+thread_local! {
+    /// Begin time of the hostcalls in progress on this thread, hostcalls
+    /// made by services while serving one are nested.
+    static HOSTCALL_BEGIN: RefCell<Vec<Instant>> = const { RefCell::new(Vec::new()) };
+    /// Hostcall stats this thread records for the bucket of the id.
+    static THREAD_HOSTCALLS: RefCell<Option<(u64, Arc<Mutex<AppHostcalls>>)>> =
+        const { RefCell::new(None) };
+}
+
+#[derive(Default, Serialize)]
+struct HostcallStat {
+    count: u64,
+    total_ns: u128,
+    max_ns: u128,
+    /// Only under `libos_with_switch_mpk`, in TSC cycles.
+    pkey_switch_cycles: u64,
+}
+
+impl HostcallStat {
+    fn merge(&mut self, other: &HostcallStat) {
+        self.count += other.count;
+        self.total_ns += other.total_ns;
+        self.max_ns = self.max_ns.max(other.max_ns);
+        self.pkey_switch_cycles += other.pkey_switch_cycles;
+    }
+
+    fn snapshot(&self) -> HostcallStatSnapshot {
+        HostcallStatSnapshot {
+            count: self.count,
+            total_ns: self.total_ns,
+            max_ns: self.max_ns,
+        }
     }
 }
 
@@ -201,16 +545,90 @@ impl SvcMetricBucket {
         val["path"] = json!(&self.svc_path);
         json!({ &self.svc_name:  val})
     }
+
+    fn snapshot(&self, isol_run_t_millis: u128) -> SvcMetricSnapshot {
+        let inner = self.inner.lock().unwrap();
+        // Loaded after the isolation started running, so loading was on the
+        // critical path of the first thread.
+        let loaded_on_demand = isol_run_t_millis > 0 && inner.init_t >= isol_run_t_millis;
+        let threads = zip(&inner.run_t, &inner.end_t)
+            .enumerate()
+            .filter(|(_, (_, end))| **end > 0)
+            .map(|(idx, (run, end))| SvcThreadSnapshot {
+                init_dur_ms: run - inner.init_t,
+                run_dur_ms: end - run,
+                cold: idx == 0 && loaded_on_demand,
+            })
+            .collect();
+
+        SvcMetricSnapshot {
+            name: self.svc_name.clone(),
+            loaded_on_demand,
+            threads,
+        }
+    }
+
+    pub fn progress(&self) -> Value {
+        let inner = self.inner.lock().unwrap();
+        json!({
+            "name": &self.svc_name,
+            "started": inner.run_t.len(),
+            "finished": inner.end_t.len(),
+        })
+    }
+}
+
+pub struct IsolMetricSnapshot {
+    /// `None` if the isolation has not ended.
+    pub total_dur_us: Option<u128>,
+    pub load_service_num: u32,
+    /// Hostcall stats by app and hostcall, empty unless
+    /// `enable_hostcall_stats` is called.
+    pub hostcalls: BTreeMap<ServiceName, BTreeMap<String, HostcallStatSnapshot>>,
+    pub buffer_alloc_bytes: usize,
+    pub buffer_transferred_bytes: usize,
+    pub services: Vec<SvcMetricSnapshot>,
+}
+
+pub struct HostcallStatSnapshot {
+    pub count: u64,
+    pub total_ns: u128,
+    pub max_ns: u128,
 }
 
-fn get_current_vm_rss_kb() -> usize {
+pub struct SvcMetricSnapshot {
+    pub name: ServiceName,
+    pub loaded_on_demand: bool,
+    /// Finished threads only. Libos services have no thread.
+    pub threads: Vec<SvcThreadSnapshot>,
+}
+
+pub struct SvcThreadSnapshot {
+    pub init_dur_ms: u128,
+    pub run_dur_ms: u128,
+    /// Whether loading the module was part of this thread's start.
+    pub cold: bool,
+}
+
+pub fn get_current_vm_rss_kb() -> usize {
     let proc_status = fs::read_to_string("/proc/self/status").expect("get proc status failed.");
 
     let line = proc_status
         .lines()
         .find(|line| line.starts_with("VmRSS:"))
         .expect("wrong status content");
+    parse_kb(line)
+}
 
+/// Proportional set size of the process, `None` if the kernel has no
+/// `smaps_rollup`.
+pub fn get_current_pss_kb() -> Option<usize> {
+    let smaps = fs::read_to_string("/proc/self/smaps_rollup").ok()?;
+    let line = smaps.lines().find(|line| line.starts_with("Pss:"))?;
+    Some(parse_kb(line))
+}
+
+fn parse_kb(line: &str) -> usize {
     // assert!(line.len() == 1, "{}", line);
 
     let number_vec: Vec<char> = line.chars().filter(|c| c.is_ascii_digit()).collect();
@@ -235,3 +653,70 @@ fn get_current_vm_rss_test() {
     let vm_rss = get_current_vm_rss_kb();
     assert!(vm_rss > 8, "{}", vm_rss)
 }
+
+#[test]
+fn mem_high_water_test() {
+    let bucket = MetricBucket::new();
+    for rss_kb in [100, 300, 200] {
+        bucket.record_mem(MemSample {
+            rss_kb,
+            app: Some("app".to_owned()),
+            ..Default::default()
+        });
+    }
+    bucket.mark(MetricEvent::Mem);
+    let heap = BTreeMap::from([
+        (
+            "app".to_owned(),
+            HeapStats {
+                used_bytes: 1,
+                free_bytes: 2,
+                peak_bytes: 3,
+            },
+        ),
+        ("mm".to_owned(), HeapStats::default()),
+    ]);
+    bucket.set_heap_stats(&heap, &["app".to_owned()]);
+
+    let result = bucket.to_json(&MetricOpt::Mem).unwrap();
+    assert_eq!(result["mem_metrics"].as_array().unwrap().len(), 4);
+    assert_eq!(result["apps"]["app"]["rss_peak_kb"], 300);
+    assert_eq!(result["apps"]["app"]["heap_peak_bytes"], 3);
+    assert!(result["apps"].get("mm").is_none());
+    assert_eq!(result["heap"]["mm"]["used_bytes"], 0);
+}
+
+#[test]
+fn hostcall_stats_test() {
+    use as_hostcall::CommonHostCall;
+
+    let bucket = MetricBucket::new();
+    bucket.trace_event(TraceEvent::HostcallBegin(CommonHostCall::Read));
+    bucket.trace_event(TraceEvent::HostcallEnd(CommonHostCall::Read));
+    assert!(bucket.to_json(&MetricOpt::All).unwrap()["isolation"]
+        .get("hostcalls")
+        .is_none());
+
+    bucket.enable_hostcall_stats();
+    for _ in 0..2 {
+        bucket.trace_event(TraceEvent::PkeySwitch(CommonHostCall::Read, 10));
+        bucket.trace_event(TraceEvent::HostcallBegin(CommonHostCall::Read));
+        bucket.trace_event(TraceEvent::HostcallBegin(CommonHostCall::BufferAlloc));
+        bucket.trace_event(TraceEvent::HostcallEnd(CommonHostCall::BufferAlloc));
+        bucket.trace_event(TraceEvent::HostcallEnd(CommonHostCall::Read));
+    }
+
+    std::thread::scope(|scope| {
+        scope.spawn(|| {
+            bucket.trace_event(TraceEvent::HostcallBegin(CommonHostCall::Read));
+            bucket.trace_event(TraceEvent::HostcallEnd(CommonHostCall::Read));
+        });
+    });
+
+    let result = bucket.to_json(&MetricOpt::All).unwrap();
+    let stats = &result["isolation"]["hostcalls"]["(no app)"];
+    assert_eq!(stats["read"]["count"], 3);
+    assert_eq!(stats["read"]["pkey_switch_cycles"], 20);
+    assert_eq!(stats["buffer_alloc"]["count"], 2);
+    assert!(stats["read"]["total_ns"].as_u64() >= stats["buffer_alloc"]["total_ns"].as_u64());
+}
@@ -16,7 +16,10 @@ use libloading::{Library, Symbol};
 
 use log::info;
 use as_hostcall::{
-    types::{DropHandlerFunc, IsolationID, MetricEvent, ServiceName},
+    manifest::ABI_VERSION,
+    types::{
+        DropHandlerFunc, FindHostCallFunc, HostCallError, IsolationID, MetricEvent, ServiceName,
+    },
     IsolationContext, SERVICE_HEAP_SIZE, SERVICE_STACK_SIZE,
 };
 use nix::libc::{PF_KEY, RTLD_DI_LMID};
@@ -25,7 +28,7 @@ use thiserror::Error;
 #[cfg(feature = "enable_mpk")]
 use crate::mpk;
 use crate::{
-    isolation::handler::{find_host_call, panic_handler},
+    isolation::handler::panic_handler,
     logger,
     metric::SvcMetricBucket,
     utils::PAGE_SIZE,
@@ -356,7 +359,7 @@ impl Drop for ElfService {
 }
 
 #[derive(Error, Debug)]
-enum ServiceInitError {
+pub(crate) enum ServiceInitError {
     #[error("set isol context failed")]
     SetIsolCtxErr,
     #[error("missing set_handler_addr?")]
@@ -365,6 +368,8 @@ enum ServiceInitError {
     MissingGetCtx,
     #[error("check isol ctx failed.")]
     CtxCheckFailed,
+    #[error("built for ABI version {0}, expect {}", ABI_VERSION)]
+    AbiVersion(u32),
 }
 
 pub struct WithLibOSService {
@@ -396,7 +401,7 @@ impl WithLibOSService {
         self.elf.path.as_str()
     }
 
-    pub fn init(&self, isol_id: IsolationID) -> anyhow::Result<()> {
+    pub fn init(&self, isol_id: IsolationID, find_handler: FindHostCallFunc) -> anyhow::Result<()> {
         info!("init name={}", self.name());
         let heap_start = self.heap.c_ptr().as_ptr() as usize;
         let mut heap_size = SERVICE_HEAP_SIZE;
@@ -411,7 +416,7 @@ impl WithLibOSService {
             "init for service_{}, isol_id={}, find_host_call_addr=0x{:x}, heap_range={:x?}",
             self.elf.name,
             isol_id,
-            find_host_call as usize,
+            find_handler as usize,
             heap_range
         );
 
@@ -423,8 +428,9 @@ impl WithLibOSService {
         };
 
         let isol_ctx = IsolationContext {
+            abi_version: ABI_VERSION,
             isol_id,
-            find_handler: find_host_call as usize,
+            find_handler: find_handler as usize,
             panic_handler: panic_handler as usize,
             heap_range,
         };
@@ -434,7 +440,10 @@ impl WithLibOSService {
             .ok_or(ServiceInitError::MissingSetCtx)?;
 
         logger::info!("start set_handler...");
-        unsafe { set_handler(&isol_ctx) }.map_err(|_| ServiceInitError::SetIsolCtxErr)?;
+        unsafe { set_handler(&isol_ctx) }.map_err(|e| match e {
+            HostCallError::AbiVersion(version) => ServiceInitError::AbiVersion(version),
+            _ => ServiceInitError::SetIsolCtxErr,
+        })?;
         logger::info!("set_handler complete.");
 
         let get_handler: GetHandlerFuncSybmol = self
@@ -447,7 +456,7 @@ impl WithLibOSService {
             *get_handler as usize
         );
 
-        if unsafe { get_handler() } != find_host_call as usize {
+        if unsafe { get_handler() } != find_handler as usize {
             Err(ServiceInitError::CtxCheckFailed)?
         }
 
@@ -12,12 +12,22 @@ use as_hostcall::mpk::LIBOS_PKEY;
 
 use anyhow::anyhow;
 use libloading::Library;
-use as_hostcall::types::{IsolationID, MetricEvent, ServiceName};
+use as_hostcall::{
+    manifest::ABI_VERSION,
+    types::{FindHostCallFunc, IsolationID, MetricEvent, ServiceName},
+};
 use nix::libc::Lmid_t;
 
-use crate::{isolation::config::IsolationConfig, metric::MetricBucket, utils};
+use crate::{
+    isolation::{
+        config::IsolationConfig,
+        handler::{find_app_host_call, find_host_call},
+    },
+    metric::MetricBucket,
+    now_microsec, utils,
+};
 
-use super::Service;
+use super::{Service, ServiceInitError};
 
 #[derive(Default)]
 pub struct Namespace(Lmid_t);
@@ -64,7 +74,13 @@ impl ServiceLoader {
         self
     }
 
-    fn load(&self, name: &ServiceName, pkey: i32) -> Result<Arc<Service>, anyhow::Error> {
+    /// Load and init `name`, which resolves hostcalls through `find_handler`.
+    fn load(
+        &self,
+        name: &ServiceName,
+        pkey: i32,
+        find_handler: FindHostCallFunc,
+    ) -> Result<Arc<Service>, anyhow::Error> {
         let lib_path = self
             .registered
             .get(name)
@@ -76,6 +92,7 @@ impl ServiceLoader {
 
         // Service init contains Loading elf library.
         metric.mark(MetricEvent::SvcInit);
+        let load_begin = now_microsec!();
         let lib = Arc::from(
             load_dynlib(
                 lib_path,
@@ -93,6 +110,8 @@ impl ServiceLoader {
             )
             .map_err(|e| anyhow!("load_dynlib faile: {e}"))?,
         );
+        self.metric
+            .trace_span(format!("load {}", name), "load", load_begin);
         let service = Service::new(
             name,
             lib_path.to_str().unwrap(),
@@ -102,11 +121,35 @@ impl ServiceLoader {
             pkey,
         );
         self.namespace.get_or_init(|| service.namespace());
+        // The version is checked before the rest of the manifest is read,
+        // which calls functions of the service.
+        if let Some(version) = service.abi_version().filter(|v| *v != ABI_VERSION) {
+            return Err(anyhow::Error::from(ServiceInitError::AbiVersion(version))
+                .context(format!("service {}", name)));
+        }
+        if let Some(manifest) = service.manifest() {
+            for custom in manifest.custom.keys() {
+                if service.interface::<fn()>(custom).is_none() {
+                    return Err(anyhow!(
+                        "service {} declares custom hostcall {} but does not export it",
+                        name,
+                        custom
+                    ));
+                }
+            }
+        }
 
-        service.init(self.isol_id)?;
+        let init_begin = now_microsec!();
+        service.init(self.isol_id, find_handler)?;
+        self.metric
+            .trace_span(format!("init {}", name), "init", init_begin);
         Ok(Arc::from(service))
     }
 
+    pub fn is_registered(&self, name: &str) -> bool {
+        self.registered.contains_key(name)
+    }
+
     pub fn load_app(&self, name: &ServiceName) -> Result<Arc<Service>, anyhow::Error> {
         let pkey;
         #[cfg(feature = "enable_mpk")]
@@ -117,7 +160,7 @@ impl ServiceLoader {
         {
             pkey = 0;
         }
-        self.load(name, pkey)
+        self.load(name, pkey, find_app_host_call)
     }
 
     pub fn load_service(&self, name: &ServiceName) -> Result<Arc<Service>, anyhow::Error> {
@@ -132,7 +175,7 @@ impl ServiceLoader {
             pkey = 0;
         }
 
-        self.load(name, pkey)
+        self.load(name, pkey, find_host_call)
     }
 }
 
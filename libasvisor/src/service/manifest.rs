@@ -0,0 +1,62 @@
+use std::collections::BTreeMap;
+
+use as_hostcall::manifest::{
+    abi_version_of, ManifestFunc, ServiceManifest, ABI_VERSION, MANIFEST_SYMBOL,
+};
+
+use super::Service;
+
+/// Copy of the `ServiceManifest` a service exports, which lives in the
+/// library and goes away with it.
+#[derive(Debug, Clone)]
+pub struct Manifest {
+    pub name: String,
+    pub abi_version: u32,
+    pub provides: Vec<String>,
+    pub depends: Vec<String>,
+    /// Custom hostcalls and the `type_name` of their function types.
+    pub custom: BTreeMap<String, String>,
+    /// Signatures of the provided hostcalls the service knows the types of.
+    pub signatures: BTreeMap<String, u64>,
+}
+
+impl From<&ServiceManifest> for Manifest {
+    fn from(manifest: &ServiceManifest) -> Self {
+        Self {
+            name: manifest.name.to_owned(),
+            abi_version: manifest.abi_version,
+            provides: manifest.provides.iter().map(|s| s.to_string()).collect(),
+            depends: manifest.depends.iter().map(|s| s.to_string()).collect(),
+            custom: manifest
+                .custom
+                .iter()
+                .map(|custom| (custom.name.to_owned(), (custom.signature)().to_owned()))
+                .collect(),
+            signatures: manifest
+                .provides
+                .iter()
+                .filter_map(|name| Some((name.to_string(), (manifest.signature)(name)?)))
+                .collect(),
+        }
+    }
+}
+
+impl Service {
+    /// The ABI version of the manifest, read without decoding the rest of
+    /// it. `None` for apps and services built without a manifest.
+    pub fn abi_version(&self) -> Option<u32> {
+        let manifest = self.interface::<ManifestFunc>(MANIFEST_SYMBOL)?;
+        Some(unsafe { abi_version_of(manifest()) })
+    }
+
+    /// `None` for apps and services built without a manifest, or with one
+    /// of another ABI version, which is not decoded.
+    pub fn manifest(&self) -> Option<Manifest> {
+        let manifest = self.interface::<ManifestFunc>(MANIFEST_SYMBOL)?;
+        let manifest = manifest();
+        if unsafe { abi_version_of(manifest) } != ABI_VERSION {
+            return None;
+        }
+        Some(Manifest::from(manifest))
+    }
+}
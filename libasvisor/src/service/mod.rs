@@ -1,5 +1,6 @@
 mod elf_service;
 mod loader;
+mod manifest;
 #[cfg(feature = "serviceV2")]
 mod rust_service;
 
@@ -7,9 +8,11 @@ use std::{collections::BTreeMap, sync::Arc};
 
 use libloading::{Library, Symbol};
 
+pub(crate) use elf_service::ServiceInitError;
 use elf_service::WithLibOSService;
 pub use loader::ServiceLoader;
-use as_hostcall::types::{IsolationID, ServiceName};
+pub use manifest::Manifest;
+use as_hostcall::types::{FindHostCallFunc, IsolationID, ServiceName};
 
 use crate::{logger, metric::SvcMetricBucket, service::elf_service::ElfService};
 
@@ -40,10 +43,10 @@ impl Service {
             Self::ELFService(elf)
         }
     }
-    fn init(&self, isol_id: IsolationID) -> anyhow::Result<()> {
+    fn init(&self, isol_id: IsolationID, find_handler: FindHostCallFunc) -> anyhow::Result<()> {
         match self {
             Service::ELFService(svc) => svc.init(isol_id),
-            Service::WithLibOSService(svc) => svc.init(isol_id),
+            Service::WithLibOSService(svc) => svc.init(isol_id, find_handler),
             #[cfg(feature = "serviceV2")]
             Service::RustService(svc) => svc.init(isol_id),
         }
@@ -0,0 +1,143 @@
+//! Recording of an isolation's execution as Chrome Trace Events, which can
+//! be opened in Perfetto or chrome://tracing. Every app instance has its own
+//! track, other threads are tracked by their name and id. Spans cover
+//! service loading, init, app runs and hostcalls, and a flow arrow links
+//! each data buffer from its producer to its consumers.
+
+use std::{collections::HashMap, thread};
+
+use as_hostcall::types::{IsolationID, TraceEvent};
+use serde_json::{json, Value};
+
+use crate::{isolation::output, now_microsec};
+
+#[derive(Default)]
+pub(crate) struct TraceBuffer {
+    events: Vec<Value>,
+    /// Track name to tid.
+    tracks: HashMap<String, u64>,
+    /// Flow id of the last buffer produced for each slot.
+    slots: HashMap<String, u64>,
+    next_flow_id: u64,
+}
+
+impl TraceBuffer {
+    fn current_track(&mut self) -> u64 {
+        let name = match output::current_app() {
+            Some((app, instance)) => format!("{}#{}", app, instance),
+            None => {
+                let thread = thread::current();
+                format!("{} {:?}", thread.name().unwrap_or("thread"), thread.id())
+            }
+        };
+        let next_tid = self.tracks.len() as u64 + 1;
+        *self.tracks.entry(name).or_insert(next_tid)
+    }
+
+    fn push(&mut self, mut event: Value) {
+        event["tid"] = json!(self.current_track());
+        self.events.push(event)
+    }
+
+    /// A span on the current track that ends now.
+    pub fn span(&mut self, name: String, cat: &str, begin_us: u128) {
+        let end_us = now_microsec!();
+        self.push(json!({
+            "name": name,
+            "cat": cat,
+            "ph": "X",
+            "ts": begin_us as u64,
+            "dur": (end_us - begin_us) as u64,
+        }))
+    }
+
+    pub fn record(&mut self, event: TraceEvent) {
+        let ts = now_microsec!() as u64;
+        let event = match event {
+            TraceEvent::HostcallBegin(hostcall) => {
+                json!({ "name": hostcall.to_string(), "cat": "hostcall", "ph": "B", "ts": ts })
+            }
+            TraceEvent::HostcallEnd(hostcall) => {
+                json!({ "name": hostcall.to_string(), "cat": "hostcall", "ph": "E", "ts": ts })
+            }
+            TraceEvent::BufferProduced(slot) => {
+                self.next_flow_id += 1;
+                self.slots.insert(slot.to_owned(), self.next_flow_id);
+                json!({
+                    "name": format!("buffer {}", slot),
+                    "cat": "buffer",
+                    "ph": "s",
+                    "id": self.next_flow_id,
+                    "ts": ts,
+                })
+            }
+            TraceEvent::PkeySwitch(..) => return,
+            TraceEvent::BufferConsumed(slot) => {
+                let Some(id) = self.slots.get(slot).copied() else {
+                    return;
+                };
+                json!({
+                    "name": format!("buffer {}", slot),
+                    "cat": "buffer",
+                    "ph": "f",
+                    "bp": "e",
+                    "id": id,
+                    "ts": ts,
+                })
+            }
+        };
+        self.push(event)
+    }
+
+    /// Events of the isolation `pid`, with names of the process and tracks.
+    pub fn to_events(&self, pid: IsolationID) -> Vec<Value> {
+        let mut events = vec![json!({
+            "name": "process_name",
+            "ph": "M",
+            "pid": pid,
+            "args": { "name": format!("isolation_{}", pid) },
+        })];
+        events.extend(self.tracks.iter().map(|(name, tid)| {
+            json!({
+                "name": "thread_name",
+                "ph": "M",
+                "pid": pid,
+                "tid": tid,
+                "args": { "name": name },
+            })
+        }));
+        events.extend(self.events.iter().cloned().map(|mut event| {
+            event["pid"] = json!(pid);
+            event
+        }));
+
+        events
+    }
+}
+
+/// A Chrome Trace file of the events of one or more isolations, see
+/// `Isolation::trace_events`.
+pub fn chrome_trace(events: Vec<Value>) -> Value {
+    json!({
+        "traceEvents": events,
+        "displayTimeUnit": "ms",
+    })
+}
+
+#[test]
+fn trace_buffer_flow_test() {
+    let mut trace = TraceBuffer::default();
+    trace.record(TraceEvent::BufferConsumed("missing"));
+    trace.record(TraceEvent::BufferProduced("slot"));
+    trace.record(TraceEvent::BufferConsumed("slot"));
+    trace.span("run app".to_owned(), "run", now_microsec!());
+
+    let events = trace.to_events(1);
+    let flows: Vec<_> = events.iter().filter(|e| e["cat"] == "buffer").collect();
+    assert_eq!(flows.len(), 2);
+    assert_eq!(flows[0]["ph"], "s");
+    assert_eq!(flows[1]["ph"], "f");
+    assert_eq!(flows[0]["id"], flows[1]["id"]);
+    assert!(events.iter().all(|e| e["pid"] == 1));
+    assert!(events.iter().any(|e| e["name"] == "thread_name"));
+}
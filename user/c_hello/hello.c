@@ -0,0 +1,21 @@
+#include "alloystack.h"
+
+static size_t append(char *dst, size_t at, const char *src) {
+    while (*src)
+        dst[at++] = *src++;
+    return at;
+}
+
+int as_main(void) {
+    char id[32];
+    if (as_arg("id", id, sizeof(id)) < 0)
+        return 1;
+
+    char msg[64];
+    size_t len = append(msg, 0, "Hello from C! id: ");
+    len = append(msg, len, id);
+    msg[len++] = '\n';
+    as_print(msg, len);
+
+    return 0;
+}
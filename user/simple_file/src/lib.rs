@@ -11,7 +11,7 @@ use alloc::{
 use as_std::{
     agent::FaaSFuncResult as Result,
     fs::File,
-    io::{Read, Write},
+    io::{Read, Seek, SeekFrom, Write},
     println,
     time::SystemTime,
 };
@@ -116,7 +116,7 @@ pub fn main() -> Result<()> {
     assert_eq!(file_content, data);
 
     /////////////////// test seek. ///////////////////
-    input_file.seek(0)?;
+    input_file.seek(SeekFrom::Start(0))?;
     println!("seek to 0");
     file_content_buf.clear();
     input_file
@@ -129,6 +129,11 @@ pub fn main() -> Result<()> {
         String::from_utf8_lossy(&file_content_buf).to_string()
     );
 
+    let end = input_file.seek(SeekFrom::End(0))?;
+    if end != file_content.len() as u64 {
+        Err("seek to end failed")?
+    }
+
     /////////////////// test seek. ///////////////////
     let meta = input_file.metadata().unwrap();
     println!("file metadata: st_size={} expect_size={}", meta.st_size, file_content.len());
@@ -382,4 +382,48 @@ impl Errno {
             Errno::Unknown => "An unknown error has occured",
         }
     }
-}
\ No newline at end of file
+}
+
+impl From<as_hostcall::err::Errno> for Errno {
+    fn from(errno: as_hostcall::err::Errno) -> Self {
+        use as_hostcall::err::Errno as E;
+        match errno {
+            E::EPERM => Errno::Perm,
+            E::ENOENT => Errno::Noent,
+            E::EINTR => Errno::Intr,
+            E::EIO => Errno::Io,
+            E::EBADF => Errno::Badf,
+            E::EAGAIN => Errno::Again,
+            E::ENOMEM => Errno::Nomem,
+            E::EACCES => Errno::Access,
+            E::EFAULT => Errno::Fault,
+            E::EBUSY => Errno::Busy,
+            E::EEXIST => Errno::Exist,
+            E::ENODEV => Errno::Nodev,
+            E::ENOTDIR => Errno::Notdir,
+            E::EISDIR => Errno::Isdir,
+            E::EINVAL => Errno::Inval,
+            E::EMFILE => Errno::Mfile,
+            E::EFBIG => Errno::Fbig,
+            E::ENOSPC => Errno::Nospc,
+            E::ESPIPE => Errno::Spipe,
+            E::EROFS => Errno::Rofs,
+            E::ENAMETOOLONG => Errno::Nametoolong,
+            E::ENOSYS => Errno::Nosys,
+            E::ENOTEMPTY => Errno::Notempty,
+            E::ELOOP => Errno::Loop,
+            E::ENOTSOCK => Errno::Notsock,
+            E::EOPNOTSUPP => Errno::Notsup,
+            E::EADDRINUSE => Errno::Addrinuse,
+            E::EADDRNOTAVAIL => Errno::Addrnotavail,
+            E::ENETUNREACH => Errno::Netunreach,
+            E::ECONNABORTED => Errno::Connaborted,
+            E::ECONNRESET => Errno::Connreset,
+            E::EISCONN => Errno::Isconn,
+            E::ENOTCONN => Errno::Notconn,
+            E::ETIMEDOUT => Errno::Timedout,
+            E::ECONNREFUSED => Errno::Connrefused,
+            E::EHOSTUNREACH => Errno::Hostunreach,
+        }
+    }
+}
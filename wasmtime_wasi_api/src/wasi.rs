@@ -15,7 +15,7 @@ use sjlj::{longjmp, JumpBuf};
 use spin::{Mutex, MutexGuard};
 use wasmtime::Caller;
 
-use as_hostcall::{fdtab::FdtabResult, types::{DirEntry, Fd, OpenFlags, OpenMode, Stat}};
+use as_hostcall::{err::AsErrno, fdtab::FdtabResult, types::{DirEntry, Fd, OpenFlags, OpenMode, Stat, Whence}};
 use as_std::{
     libos::libos,
     time::{SystemTime, UNIX_EPOCH},
@@ -361,6 +361,37 @@ pub fn fd_pread(mut caller: Caller<'_, LibosCtx>, fd: i32, iovs: i32, iovs_len:
         println!("[Time] fd_pread: {}", SystemTime::now().duration_since(UNIX_EPOCH).as_micros() as f64 / 1000000f64);
     }
 
+    let memory = caller.get_export("memory").unwrap().into_memory().unwrap();
+    let mut read_size: usize = 0;
+
+    for i in 0..iovs_len {
+        let iov_offset: usize = iovs as usize + i as usize * core::mem::size_of::<WasiCiovec>();
+        let iov = memory.data(&caller)
+                                    .get(iov_offset..)
+                                    .and_then(|s| s.get(..core::mem::size_of::<WasiCiovec>() as usize))
+                                    .unwrap();
+        let iov: &WasiCiovec = unsafe { &*(iov.as_ptr() as *const WasiCiovec) };
+        let buf_len = iov.buf_len as usize;
+        let buf = memory.data_mut(&mut caller)
+                                    .get_mut(iov.buf as usize..)
+                                    .and_then(|s| s.get_mut(..buf_len))
+                                    .unwrap();
+        match libos!(pread(fd as u32, buf, offset as u64 + read_size as u64)) {
+            Ok(size) => {
+                read_size += size;
+                if size < buf_len {
+                    break;
+                }
+            }
+            Err(e) => {
+                let errno = Errno::from(e.errno());
+                forget(e);
+                return errno as i32;
+            }
+        }
+    }
+
+    memory.write(&mut caller, nread as usize, &(read_size as u32).to_ne_bytes()).unwrap();
     Errno::Success as i32
 }
 
@@ -375,6 +406,36 @@ pub fn fd_pwrite(mut caller: Caller<'_, LibosCtx>, fd: i32, iovs: i32, iovs_len:
         println!("[Time] fd_pwrite: {}", SystemTime::now().duration_since(UNIX_EPOCH).as_micros() as f64 / 1000000f64);
     }
 
+    let memory = caller.get_export("memory").unwrap().into_memory().unwrap();
+    let mut write_size: usize = 0;
+
+    for i in 0..iovs_len {
+        let iov_offset: usize = iovs as usize + i as usize * core::mem::size_of::<WasiCiovec>();
+        let iov = memory.data(&caller)
+                                    .get(iov_offset..)
+                                    .and_then(|s| s.get(..core::mem::size_of::<WasiCiovec>() as usize))
+                                    .unwrap();
+        let iov: &WasiCiovec = unsafe { &*(iov.as_ptr() as *const WasiCiovec) };
+        let buf = memory.data(&caller)
+                                    .get(iov.buf as usize..)
+                                    .and_then(|s| s.get(..iov.buf_len as usize))
+                                    .unwrap();
+        match libos!(pwrite(fd as u32, buf, offset as u64 + write_size as u64)) {
+            Ok(size) => {
+                write_size += size;
+                if size < buf.len() {
+                    break;
+                }
+            }
+            Err(e) => {
+                let errno = Errno::from(e.errno());
+                forget(e);
+                return errno as i32;
+            }
+        }
+    }
+
+    memory.write(&mut caller, nwritten as usize, &(write_size as u32).to_ne_bytes()).unwrap();
     Errno::Success as i32
 }
 
@@ -580,22 +641,26 @@ pub fn fd_seek(mut caller: Caller<'_, LibosCtx>, fd: i32, offset: i64, whence: i
         println!("[Time] fd_seek: {}", SystemTime::now().duration_since(UNIX_EPOCH).as_micros() as f64 / 1000000f64);
     }
 
-    // TO BE FIX FOR PY HELLO
-    // let fd = args.0 as u32;
-    // let offset = args.1;
-    // let whence = args.2;
-    // let pos = offset as u32;
-    // // if whence == 0 {
-
-    // // } else if whence == 1 {
-
-    // // } else if whence == 2{
-
-    // // }
-
-    // libos!(lseek(fd, pos)).unwrap();
+    // stdio is not seekable, like a terminal or a pipe.
+    if let 0..=2 = fd {
+        return Errno::Spipe as i32;
+    }
+    let Some(whence) = Whence::from_raw(whence as u32) else {
+        return Errno::Inval as i32;
+    };
 
-    Errno::Success as i32
+    match libos!(lseek(fd as u32, offset, whence)) {
+        Ok(new_offset) => {
+            let memory = caller.get_export("memory").unwrap().into_memory().unwrap();
+            memory.write(&mut caller, pos as usize, &new_offset.to_ne_bytes()).unwrap();
+            Errno::Success as i32
+        }
+        Err(e) => {
+            let errno = Errno::from(e.errno());
+            forget(e);
+            errno as i32
+        }
+    }
 }
 
 pub fn fd_sync(mut caller: Caller<'_, LibosCtx>, fd: i32) -> i32 {
@@ -609,7 +674,7 @@ pub fn fd_sync(mut caller: Caller<'_, LibosCtx>, fd: i32) -> i32 {
     Errno::Success as i32
 }
 
-pub fn fd_tell(mut caller: Caller<'_, LibosCtx>, fd: i32, offset: i32) -> i32 {
+pub fn fd_tell(caller: Caller<'_, LibosCtx>, fd: i32, offset: i32) -> i32 {
     #[cfg(feature = "log")]
     {
         println!("[Debug] Invoke into fd_tell");
@@ -617,7 +682,7 @@ pub fn fd_tell(mut caller: Caller<'_, LibosCtx>, fd: i32, offset: i32) -> i32 {
         println!("[Time] fd_tell: {}", SystemTime::now().duration_since(UNIX_EPOCH).as_micros() as f64 / 1000000f64);
     }
 
-    Errno::Success as i32
+    fd_seek(caller, fd, 0, Whence::Cur as i32, offset)
 }
 
 pub fn fd_write(mut caller: Caller<'_, LibosCtx>, fd: i32, iovs_ptr: i32, iovs_len: i32, retptr: i32) -> i32 {
@@ -690,14 +755,15 @@ pub fn path_filestat_get(mut caller: Caller<'_, LibosCtx>, fd: i32, flags: i32,
 
     let path_fd: FdtabResult<Fd> = libos!(open(&path, OpenFlags::empty(), OpenMode::RD));
     let path_fd = if let Err(_e) = path_fd {
+        let errno = Errno::from(_e.errno());
         #[cfg(feature = "log")]
         {
-            println!("[WASI ERR] Errno in path_filestat_get: Noent");
+            println!("[WASI ERR] Errno in path_filestat_get: {}", errno.name());
             println!("[WASI ERR] path error msg: {:?}", _e);
         }
         
         forget(_e);
-        return Errno::Noent as i32;
+        return errno as i32;
     } else {
         path_fd.unwrap() as u32
     };
@@ -791,13 +857,14 @@ pub fn path_open(mut caller: Caller<'_, LibosCtx>, fd: i32, dirflags: i32, path_
     // kind表示文件类型，是个enum。eg. File Dir
     let path_fd: FdtabResult<Fd> = libos!(open(&path, flags, mode));
     let path_fd = if let Err(_e) = path_fd {
+        let errno = Errno::from(_e.errno());
         #[cfg(feature = "log")] {
-            println!("[WASI ERR] Errno in path_open: Noent");
+            println!("[WASI ERR] Errno in path_open: {}", errno.name());
             println!("[WASI ERR] path error msg: {:?}", _e);
         }
 
         forget(_e);
-        return Errno::Noent as i32;
+        return errno as i32;
     } else {
         path_fd.unwrap() as u32
     };